@@ -1,9 +1,24 @@
-use anyhow::Result;
-use clap::{Parser, Subcommand};
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde_json::Value;
+use std::fs::{File, OpenOptions};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use tracing::{Event, Subscriber};
 use tracing_subscriber::EnvFilter;
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, MakeWriter};
+use tracing_subscriber::layer::{Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
 
-use crate::commands;
+use crate::commands::docs::DocsFormat;
+use crate::commands::{self, BuildOptions};
+use crate::config::AppConfig;
 use crate::doctor;
+use crate::events::{self, EventsLayer, JsonFields};
+use crate::verification::VerifyFailed;
 
 #[derive(Parser, Debug)]
 #[command(
@@ -12,16 +27,59 @@ use crate::doctor;
     about = "Python proto importer toolkit"
 )]
 pub struct Cli {
-    #[arg(short = 'v', action = clap::ArgAction::Count)]
+    /// Log debug output (-vv for trace)
+    #[arg(short = 'v', action = clap::ArgAction::Count, global = true)]
     pub verbose: u8,
 
+    /// Only log warnings and errors
+    #[arg(short = 'q', long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Format of log output
+    #[arg(long, value_enum, default_value_t = LogFormat::Text, global = true)]
+    pub log_format: LogFormat,
+
+    /// When to emit ANSI colors in log output
+    #[arg(long, value_enum, default_value_t = ColorMode::Auto, global = true)]
+    pub color: ColorMode,
+
+    /// Always emit ANSI colors, even when stdout is not a TTY (same as `--color always`)
+    #[arg(long, global = true, conflicts_with = "color")]
+    pub force_color: bool,
+
+    /// Append newline-delimited JSON pipeline events to this file as they happen
+    #[arg(long, global = true, value_name = "PATH")]
+    pub events_file: Option<PathBuf>,
+
     #[command(subcommand)]
     pub command: Commands,
 }
 
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    /// Colorize when stdout is a TTY and `NO_COLOR` is not set
+    Auto,
+    /// Always colorize, regardless of TTY detection or `NO_COLOR`
+    Always,
+    /// Never colorize
+    Never,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per line with `level`, `message` and any other fields
+    Json,
+}
+
 #[derive(Subcommand, Debug)]
 pub enum Commands {
-    Doctor,
+    Doctor {
+        /// Print the diagnostics as JSON instead of a text report
+        #[arg(long)]
+        json: bool,
+    },
     Build {
         #[arg(long)]
         pyproject: Option<String>,
@@ -29,49 +87,293 @@ pub enum Commands {
         no_verify: bool,
         #[arg(long)]
         postprocess_only: bool,
+        /// Regenerate even if the build cache says inputs are unchanged
+        #[arg(long)]
+        force: bool,
+        /// Write a compile_commands-style JSON describing each compiled proto
+        #[arg(long, value_name = "PATH")]
+        emit_proto_db: Option<PathBuf>,
+        /// Preview postprocess changes as diffs without modifying the output directory
+        #[arg(long)]
+        dry_run: bool,
+        /// Reuse the generation saved by a previous build that failed later,
+        /// resuming from postprocessing
+        #[arg(long, conflicts_with_all = ["postprocess_only", "dry_run"])]
+        resume: bool,
+        /// Skip the build unless protos under `include`/`inputs` changed in git
+        #[arg(long)]
+        changed: bool,
+        /// Git ref to compare against with `--changed` (default: HEAD)
+        #[arg(long, requires = "changed")]
+        since: Option<String>,
+        /// Only generate this proto (a path, or a name relative to an include
+        /// path) and the protos it imports
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["postprocess_only", "resume", "changed"])]
+        proto: Option<PathBuf>,
+        /// Do not fail when an `inputs` pattern matches no proto
+        #[arg(long)]
+        allow_empty_inputs: bool,
+        /// Build into a temporary directory and fail if the output directory
+        /// differs from it, without modifying it
+        #[arg(long, conflicts_with_all = ["postprocess_only", "dry_run", "resume", "proto", "changed"])]
+        check_only: bool,
+        /// Only run protoc (or buf) and stop before postprocessing and
+        /// verification, leaving the raw generated output
+        #[arg(long, conflicts_with_all = ["postprocess_only", "dry_run", "resume", "check_only"])]
+        generate_only: bool,
     },
     Check {
         #[arg(long)]
         pyproject: Option<String>,
     },
+    /// Rebuild whenever a .proto file under the include paths changes
+    Watch {
+        #[arg(long)]
+        pyproject: Option<String>,
+        /// Skip verification on each rebuild
+        #[arg(long)]
+        no_verify: bool,
+    },
     Clean {
         #[arg(long)]
         pyproject: Option<String>,
         #[arg(long)]
         yes: bool,
+        /// Remove the entire output directory, not only generated files
+        #[arg(long)]
+        all: bool,
+        /// Print the paths that would be removed without removing anything
+        #[arg(long, visible_alias = "list", conflicts_with = "yes")]
+        dry_run: bool,
+        /// With `--dry-run`, print the paths as JSON
+        #[arg(long, requires = "dry_run")]
+        json: bool,
+    },
+    /// Write a schema reference describing the messages, enums and services
+    Docs {
+        #[arg(long)]
+        pyproject: Option<String>,
+        #[arg(long, value_enum, default_value_t = DocsFormat::Markdown)]
+        format: DocsFormat,
+        /// File to write the reference to
+        #[arg(long, value_name = "PATH")]
+        out: PathBuf,
+    },
+    /// Rewrite imports of generated modules in user scripts and notebooks
+    Rewrite {
+        #[arg(long)]
+        pyproject: Option<String>,
+        /// File or directory of `.py`/`.ipynb` files to rewrite
+        #[arg(long)]
+        path: PathBuf,
+        /// Dotted package the output directory is imported as (default: detected)
+        #[arg(long)]
+        package: Option<String>,
+        /// Log diffs without modifying files
+        #[arg(long)]
+        dry_run: bool,
     },
 }
 
-fn init_tracing(verbosity: u8) {
-    let level = match verbosity {
-        0 => "info",
-        1 => "debug",
-        _ => "trace",
-    };
+/// Decide whether to emit ANSI escapes.
+///
+/// Precedence: explicit `--color` flag > `NO_COLOR` env var > TTY detection.
+fn resolve_ansi(mode: ColorMode, no_color: Option<&str>, is_tty: bool) -> bool {
+    match mode {
+        ColorMode::Always => true,
+        ColorMode::Never => false,
+        ColorMode::Auto => {
+            if no_color.is_some_and(|v| !v.is_empty()) {
+                false
+            } else {
+                is_tty
+            }
+        }
+    }
+}
+
+/// Default log level for the `-v`/`-q` flags; `RUST_LOG` overrides it.
+fn log_level(verbosity: u8, quiet: bool) -> &'static str {
+    match (quiet, verbosity) {
+        (true, _) => "warn",
+        (false, 0) => "info",
+        (false, 1) => "debug",
+        (false, _) => "trace",
+    }
+}
+
+fn build_subscriber<W>(
+    level: &str,
+    format: LogFormat,
+    ansi: bool,
+    writer: W,
+    events: Option<EventsLayer<File>>,
+) -> impl tracing::Subscriber + Send + Sync + 'static
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
     let env_filter = std::env::var("RUST_LOG").unwrap_or_else(|_| level.to_string());
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::new(env_filter))
-        .with_target(false)
-        .without_time()
-        .init();
+    // Pipeline events go to the events file only, never to the log output
+    let env_filter = EnvFilter::new(env_filter).add_directive(
+        format!("{}=off", events::TARGET)
+            .parse()
+            .expect("valid events directive"),
+    );
+    let fmt = tracing_subscriber::fmt::layer().with_writer(writer);
+    let fmt = match format {
+        LogFormat::Text => fmt
+            .with_target(false)
+            .without_time()
+            .with_ansi(ansi)
+            .with_filter(env_filter)
+            .boxed(),
+        LogFormat::Json => fmt
+            .with_ansi(false)
+            .event_format(JsonFormat)
+            .with_filter(env_filter)
+            .boxed(),
+    };
+    let events = events.with_filter(filter_fn(|meta| meta.target() == events::TARGET));
+    tracing_subscriber::registry().with(fmt).with(events)
 }
 
-pub fn run_cli() -> Result<()> {
-    let cli = Cli::parse();
-    init_tracing(cli.verbose);
-    match cli.command {
-        Commands::Doctor => doctor::run()?,
+/// Formats log events as single-line JSON objects.
+struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        _ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        let meta = event.metadata();
+        let mut fields = JsonFields(serde_json::Map::new());
+        fields
+            .0
+            .insert("level".to_string(), Value::from(meta.level().as_str()));
+        fields
+            .0
+            .insert("target".to_string(), Value::from(meta.target()));
+        event.record(&mut fields);
+        let line = serde_json::to_string(&Value::Object(fields.0)).map_err(|_| std::fmt::Error)?;
+        writeln!(writer, "{}", line)
+    }
+}
+
+fn init_tracing(cli: &Cli, color: ColorMode, events: Option<EventsLayer<File>>) {
+    let no_color = std::env::var("NO_COLOR").ok();
+    let ansi = resolve_ansi(color, no_color.as_deref(), std::io::stdout().is_terminal());
+    let level = log_level(cli.verbose, cli.quiet);
+    build_subscriber(level, cli.log_format, ansi, std::io::stdout, events).init();
+}
+
+fn dispatch(cli: Cli) -> Result<()> {
+    let color = if cli.force_color {
+        ColorMode::Always
+    } else {
+        cli.color
+    };
+    let events = match &cli.events_file {
+        Some(path) => Some(EventsLayer::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("failed to open events file {}", path.display()))?,
+        )),
+        None => None,
+    };
+    init_tracing(&cli, color, events);
+    let result = run_command(cli.command);
+    if let Err(e) = &result {
+        events::emit!("error", message = %format!("{:#}", e));
+        if let Some(failed) = e.downcast_ref::<VerifyFailed>() {
+            print!("{}", failed.summary());
+        }
+    }
+    result
+}
+
+fn run_command(command: Commands) -> Result<()> {
+    match command {
+        Commands::Doctor { json } => doctor::run(json)?,
         Commands::Build {
             pyproject,
             no_verify,
             postprocess_only,
-        } => commands::build(pyproject.as_deref(), no_verify, postprocess_only)?,
+            force,
+            emit_proto_db,
+            dry_run,
+            resume,
+            changed,
+            since,
+            proto,
+            allow_empty_inputs,
+            check_only,
+            generate_only,
+        } => {
+            let options = BuildOptions {
+                no_verify,
+                postprocess_only,
+                force,
+                emit_proto_db,
+                dry_run,
+                resume,
+                proto,
+                allow_empty_inputs,
+                check_only,
+                generate_only,
+            };
+            if changed {
+                commands::build_changed(pyproject.as_deref(), since.as_deref(), &options)?
+            } else {
+                let cfg = AppConfig::load(pyproject.as_deref().map(Path::new))
+                    .context("failed to load config")?;
+                commands::build_with(&cfg, &options)?;
+            }
+        }
         Commands::Check { pyproject } => commands::check(pyproject.as_deref())?,
-        Commands::Clean { pyproject, yes } => commands::clean(pyproject.as_deref(), yes)?,
+        Commands::Watch {
+            pyproject,
+            no_verify,
+        } => commands::watch(pyproject.as_deref(), no_verify)?,
+        Commands::Clean {
+            pyproject,
+            all,
+            dry_run: true,
+            json,
+            ..
+        } => commands::clean::clean_dry_run(pyproject.as_deref(), all, json)?,
+        Commands::Clean {
+            pyproject,
+            yes,
+            all,
+            ..
+        } => commands::clean(pyproject.as_deref(), yes, all)?,
+        Commands::Docs {
+            pyproject,
+            format,
+            out,
+        } => commands::docs(pyproject.as_deref(), format, &out)?,
+        Commands::Rewrite {
+            pyproject,
+            path,
+            package,
+            dry_run,
+        } => commands::rewrite(pyproject.as_deref(), &path, package.as_deref(), dry_run)?,
     }
     Ok(())
 }
 
+pub fn run_cli() -> Result<()> {
+    dispatch(Cli::parse())
+}
+
 pub fn run_cli_with<I, S>(args: I) -> Result<()>
 where
     I: IntoIterator<Item = S>,
@@ -82,17 +384,114 @@ where
         v.insert(0, "proto-importer".to_string());
     }
 
-    let cli = Cli::parse_from(v);
-    init_tracing(cli.verbose);
-    match cli.command {
-        Commands::Doctor => doctor::run()?,
-        Commands::Build {
-            pyproject,
-            no_verify,
-            postprocess_only,
-        } => commands::build(pyproject.as_deref(), no_verify, postprocess_only)?,
-        Commands::Check { pyproject } => commands::check(pyproject.as_deref())?,
-        Commands::Clean { pyproject, yes } => commands::clean(pyproject.as_deref(), yes)?,
+    dispatch(Cli::parse_from(v))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'w> MakeWriter<'w> for Capture {
+        type Writer = Capture;
+        fn make_writer(&'w self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    fn capture(level: &str, format: LogFormat, ansi: bool) -> String {
+        let capture = Capture::default();
+        let subscriber = build_subscriber(level, format, ansi, capture.clone(), None);
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("hello");
+            tracing::warn!(path = "a_pb2.py", "careful");
+        });
+        String::from_utf8(capture.0.lock().unwrap().clone()).unwrap()
+    }
+
+    fn capture_log(ansi: bool) -> String {
+        capture("info", LogFormat::Text, ansi)
+    }
+
+    #[test]
+    fn color_flag_takes_precedence() {
+        assert!(resolve_ansi(ColorMode::Always, Some("1"), false));
+        assert!(!resolve_ansi(ColorMode::Never, None, true));
+    }
+
+    #[test]
+    fn no_color_env_beats_tty_detection() {
+        assert!(!resolve_ansi(ColorMode::Auto, Some("1"), true));
+        // An empty NO_COLOR is treated as unset
+        assert!(resolve_ansi(ColorMode::Auto, Some(""), true));
+        assert!(resolve_ansi(ColorMode::Auto, None, true));
+        assert!(!resolve_ansi(ColorMode::Auto, None, false));
+    }
+
+    #[test]
+    fn force_color_parses_as_always() {
+        let cli = Cli::parse_from(["proto-importer", "--force-color", "doctor"]);
+        assert!(cli.force_color);
+        let cli = Cli::parse_from(["proto-importer", "doctor", "--color", "always"]);
+        assert_eq!(cli.color, ColorMode::Always);
+        assert!(
+            Cli::try_parse_from([
+                "proto-importer",
+                "--force-color",
+                "--color",
+                "never",
+                "doctor"
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn forced_color_emits_ansi_without_tty() {
+        let ansi = resolve_ansi(ColorMode::Always, None, false);
+        assert!(capture_log(ansi).contains("\x1b["));
+        assert!(!capture_log(false).contains("\x1b["));
+    }
+
+    #[test]
+    fn quiet_conflicts_with_verbose() {
+        let cli = Cli::parse_from(["proto-importer", "build", "-q"]);
+        assert_eq!(log_level(cli.verbose, cli.quiet), "warn");
+        let cli = Cli::parse_from(["proto-importer", "-vv", "doctor"]);
+        assert_eq!(log_level(cli.verbose, cli.quiet), "trace");
+        let err = Cli::try_parse_from(["proto-importer", "-q", "-v", "doctor"]).unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn quiet_drops_info_and_json_emits_one_object_per_line() {
+        let text = capture("warn", LogFormat::Text, false);
+        assert!(!text.contains("hello"), "{text}");
+        assert!(text.contains("careful"), "{text}");
+
+        let json = capture("info", LogFormat::Json, false);
+        let lines: Vec<Value> = json
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["message"], "hello");
+        assert_eq!(lines[1]["level"], "WARN");
+        assert_eq!(lines[1]["path"], "a_pb2.py");
     }
-    Ok(())
 }
@@ -1,13 +1,26 @@
-use crate::config::{AppConfig, Backend};
+use crate::commands::stale::{Staleness, stale_files};
+use crate::config::{AppConfig, Backend, ModuleTransform, PyTyped, VerifyProfile};
+use crate::events;
+use crate::generator::buf::BufRunner;
+use crate::generator::cache;
+use crate::generator::deps::proto_closure;
 use crate::generator::protoc::ProtocRunner;
+use crate::generator::snapshot::OutputSnapshot;
 use crate::postprocess::add_pyright_header;
-use crate::postprocess::apply::apply_rewrites_in_tree;
-use crate::postprocess::create_packages;
-use crate::postprocess::fds::{collect_generated_basenames_from_bytes, load_fds_from_bytes};
-use crate::postprocess::rel_imports::scan_and_report;
-use crate::verification::import_test::verify;
+use crate::postprocess::apply::TreeRewrite;
+use crate::postprocess::exports::{collect_exports, write_all_exports};
+use crate::postprocess::fds::{
+    check_package_declarations, collect_generated_basenames_from_bytes, find_unused_includes,
+    generated_modules, load_fds_from_bytes,
+};
+use crate::postprocess::metadata::write_package_metadata;
+use crate::postprocess::rel_imports::{find_escaping_relative_imports, scan_tree};
+use crate::postprocess::transform::apply_module_transform;
+use crate::postprocess::{create_packages, write_init_reexports, write_py_typed};
+use crate::verification::{ModuleFailure, verify_modules};
 use anyhow::{Context, Result};
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 
 /// Execute the build command to generate Python code from proto files.
 ///
@@ -17,9 +30,15 @@ use std::path::Path;
 ///
 /// # Arguments
 ///
-/// * `pyproject` - Optional path to the pyproject.toml file. If None, uses "pyproject.toml"
+/// * `pyproject` - Optional path to the pyproject.toml file. If None, the first
+///   pyproject.toml with a `[tool.python_proto_importer]` section is found by
+///   walking up from the current directory
 /// * `no_verify` - If true, skips the verification step after generation
-/// * `_postprocess_only` - If true, skips generation and only runs post-processing (experimental)
+/// * `postprocess_only` - If true, skips generation and only postprocesses the existing `out`
+/// * `force` - If true, regenerates even when the build cache says inputs are unchanged
+/// * `emit_proto_db` - If set, writes a `compile_commands.json`-style proto DB to this path
+/// * `dry_run` - If true, generates into a scratch directory and logs postprocess diffs
+///   without modifying `out`; the cache and verification are skipped
 ///
 /// # Returns
 ///
@@ -28,12 +47,21 @@ use std::path::Path;
 /// # Pipeline Steps
 ///
 /// 1. **Configuration**: Load and validate pyproject.toml settings
-/// 2. **Generation**: Run protoc or buf to generate Python files
-/// 3. **Post-processing**:
-///    - Create `__init__.py` files if configured
+/// 2. **Cache check**: Skip generation and post-processing when the inputs and
+///    settings match the build cache in `state_dir`
+/// 3. **Generation**: Run protoc or buf to generate Python files, then save the
+///    FileDescriptorSet in `state_dir` so a failed build can be resumed from
+///    post-processing with [`BuildOptions::resume`]
+/// 4. **Post-processing**:
+///    - Rename modules via `module_transform` if configured
+///    - Create `__init__.py` files if configured, re-exporting generated
+///      modules with `init_reexports`
+///    - Write `py.typed` markers per `py_typed`
 ///    - Convert absolute imports to relative imports
+///    - Write `__all__` into generated modules if `emit_all` is set
 ///    - Add type checker suppression headers
-/// 4. **Verification**: Run import tests and optional type checking
+///    - Write a `pyproject.toml` into `out` if `emit_package_metadata` is set
+/// 5. **Verification**: Run import tests and optional type checking
 ///
 /// # Example
 ///
@@ -41,53 +69,691 @@ use std::path::Path;
 /// use python_proto_importer::commands::build;
 ///
 /// // Standard build
-/// build(None, false, false)?;
+/// build(None, false, false, false, None, false)?;
 ///
 /// // Build without verification
-/// build(None, true, false)?;
+/// build(None, true, false, false, None, false)?;
 ///
 /// // Build with custom config file
-/// build(Some("custom.toml"), false, false)?;
+/// build(Some("custom.toml"), false, false, false, None, false)?;
 /// # Ok::<(), anyhow::Error>(())
 /// ```
-pub fn build(pyproject: Option<&str>, no_verify: bool, _postprocess_only: bool) -> Result<()> {
+pub fn build(
+    pyproject: Option<&str>,
+    no_verify: bool,
+    postprocess_only: bool,
+    force: bool,
+    emit_proto_db: Option<&Path>,
+    dry_run: bool,
+) -> Result<()> {
     let cfg = AppConfig::load(pyproject.map(Path::new)).context("failed to load config")?;
+    let options = BuildOptions {
+        no_verify,
+        postprocess_only,
+        force,
+        emit_proto_db: emit_proto_db.map(Path::to_path_buf),
+        dry_run,
+        resume: false,
+        proto: None,
+        allow_empty_inputs: false,
+        check_only: false,
+        generate_only: false,
+    };
+    build_with(&cfg, &options)?;
+    Ok(())
+}
+
+/// Options for [`build_with`], mirroring the flags of the `build` command.
+#[derive(Debug, Clone, Default)]
+pub struct BuildOptions {
+    /// Skip the verification step
+    pub no_verify: bool,
+    /// Skip generation and only postprocess the existing `out`
+    pub postprocess_only: bool,
+    /// Regenerate even when the build cache says inputs are unchanged
+    pub force: bool,
+    /// Write a `compile_commands.json`-style proto DB to this path
+    pub emit_proto_db: Option<PathBuf>,
+    /// Generate into a scratch directory and only log postprocess diffs
+    pub dry_run: bool,
+    /// Reuse the generation saved in `state_dir` when it matches the current
+    /// inputs, resuming from postprocessing
+    pub resume: bool,
+    /// Only generate this proto and the protos it imports (protoc backend).
+    /// The build cache is bypassed and only their modules are import-checked
+    pub proto: Option<PathBuf>,
+    /// Accept `inputs` patterns that match no proto instead of failing
+    pub allow_empty_inputs: bool,
+    /// Build into a scratch directory and fail if the output roots differ from
+    /// it, leaving them untouched; verification is skipped
+    pub check_only: bool,
+    /// Only run protoc/buf and leave its raw output in `out`; postprocessing,
+    /// the build cache and verification are skipped. The inverse of
+    /// `--postprocess-only`, for debugging what the generator produced
+    pub generate_only: bool,
+}
+
+/// What a [`build_with`] run did.
+///
+/// With `dry_run` the postprocess counts are what would have been changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuildReport {
+    /// Generation and postprocess were skipped because the build cache was current
+    pub up_to_date: bool,
+    /// Generation was skipped in favour of the one saved in `state_dir`
+    pub resumed: bool,
+    /// `__init__.py` files created
+    pub packages_created: usize,
+    /// Generated files whose imports were rewritten
+    pub files_rewritten: usize,
+    /// Modules that passed the import check
+    pub modules_verified: usize,
+    /// Modules that failed the import check but were tolerated by `max_failure_ratio`
+    pub failures: Vec<ModuleFailure>,
+    /// Each enabled postprocess pass in the order it ran, summed over output roots
+    pub passes: Vec<PassSummary>,
+}
+
+/// What one postprocess pass did, as listed in the end-of-postprocess summary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassSummary {
+    /// Config name of the pass, e.g. `create_package`
+    pub pass: &'static str,
+    /// Files created, modified or renamed by the pass
+    pub count: usize,
+    /// The pass is enabled but could not run, e.g. it needs the FDS under
+    /// `--postprocess-only`
+    pub skipped: bool,
+}
+
+impl BuildReport {
+    /// Add `count` to the summary of `pass`; `None` records it as skipped.
+    fn record_pass(&mut self, pass: &'static str, count: Option<usize>) {
+        let index = match self.passes.iter().position(|p| p.pass == pass) {
+            Some(index) => index,
+            None => {
+                self.passes.push(PassSummary {
+                    pass,
+                    count: 0,
+                    skipped: true,
+                });
+                self.passes.len() - 1
+            }
+        };
+        if let Some(count) = count {
+            self.passes[index].count += count;
+            self.passes[index].skipped = false;
+        }
+    }
+}
+
+/// Run the build pipeline for an already loaded configuration.
+///
+/// This is the library entry point behind [`build`]. Verification failures
+/// beyond what the config tolerates are returned as a
+/// [`VerifyFailed`](crate::verification::VerifyFailed) error carrying the
+/// failing modules.
+///
+/// # Example
+///
+/// ```no_run
+/// use python_proto_importer::commands::build::{BuildOptions, build_with};
+/// use python_proto_importer::config::AppConfig;
+///
+/// let cfg = AppConfig::load(None)?;
+/// let report = build_with(&cfg, &BuildOptions::default())?;
+/// println!("rewrote {} files", report.files_rewritten);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn build_with(cfg: &AppConfig, options: &BuildOptions) -> Result<BuildReport> {
     tracing::info!(?cfg.backend, out=%cfg.out.display(), "build start");
+    let mut report = BuildReport::default();
+    let single;
+    let cfg = match &options.proto {
+        Some(proto) => {
+            single = single_proto_config(cfg, proto)?;
+            &single
+        }
+        None => cfg,
+    };
+    if options.generate_only && options.postprocess_only {
+        anyhow::bail!("generate_only and postprocess_only are mutually exclusive");
+    }
+    let mut verify_only = None;
+    if !options.postprocess_only {
+        check_proto_sources(cfg, options.allow_empty_inputs)?;
+    }
+
+    if let Some(db_path) = &options.emit_proto_db {
+        if !matches!(cfg.backend, Backend::Protoc) {
+            anyhow::bail!("--emit-proto-db is only supported with the protoc backend");
+        }
+        let n = ProtocRunner::new(cfg)
+            .write_proto_db(db_path)
+            .context("emit proto db failed")?;
+        tracing::info!("proto db written: {} ({} files)", db_path.display(), n);
+    }
+
+    if options.check_only {
+        check_stale(cfg, &mut report)?;
+        return Ok(report);
+    }
+
+    if options.generate_only {
+        // `out` is left unprocessed, which the cache must not vouch for
+        cache::invalidate(&cfg.state_dir)?;
+        let fds_bytes = generate(cfg)?;
+        let mut names: Vec<String> = collect_generated_basenames_from_bytes(&fds_bytes)
+            .context("collect basenames from FDS failed")?
+            .into_iter()
+            .collect();
+        names.sort();
+        tracing::info!(
+            "generate-only: descriptor set covers {} modules, postprocess and verify skipped: {}",
+            names.len(),
+            names.join(", ")
+        );
+        return Ok(report);
+    }
+
+    if options.dry_run {
+        // Generate into a scratch directory so no output root is touched; with
+        // --postprocess-only the existing `out` is inspected read-only
+        let scratch = tempfile::tempdir().context("create scratch directory for dry-run")?;
+        let (dry_cfg, fds_bytes) = if options.postprocess_only {
+            existing_output(cfg)?;
+            (cfg.clone(), None)
+        } else {
+            let (dry_cfg, _) = relocate_outputs(cfg, scratch.path());
+            let fds_bytes = generate(&dry_cfg)?;
+            (dry_cfg, Some(fds_bytes))
+        };
+        let modified = run_postprocess(
+            &dry_cfg,
+            fds_bytes.as_deref(),
+            options.postprocess_only,
+            true,
+            &mut report,
+        )?;
+        tracing::info!(
+            "dry-run: {} files would be modified under {}",
+            modified,
+            cfg.out.display()
+        );
+        return Ok(report);
+    }
 
-    let allowed_basenames = if _postprocess_only {
-        if !cfg.out.exists() {
-            anyhow::bail!(
-                "--postprocess-only: output directory does not exist: {}",
-                cfg.out.display()
+    if options.postprocess_only {
+        existing_output(cfg)?;
+        run_postprocess(cfg, None, true, false, &mut report)?;
+    } else if options.proto.is_some() {
+        // The cache describes the full input set, which a partial build leaves stale
+        cache::invalidate(&cfg.state_dir)?;
+        let fds_bytes = generate(cfg)?;
+        run_postprocess(cfg, Some(&fds_bytes), false, false, &mut report)?;
+        let mut names = collect_generated_basenames_from_bytes(&fds_bytes)
+            .context("collect basenames from FDS failed")?;
+        if let Some(transform) = &cfg.postprocess.module_transform {
+            names = names.iter().map(|b| transform.apply(b)).collect();
+        }
+        verify_only = Some(names);
+    } else {
+        let fp = cache::fingerprint(cfg).context("compute build fingerprint failed")?;
+        let saved = options
+            .resume
+            .then(|| cache::read_generation(&cfg.state_dir, &fp))
+            .flatten()
+            .filter(|_| cfg.out.exists());
+        if !options.force
+            && cfg.out.exists()
+            && cache::read(&cfg.state_dir).as_deref() == Some(fp.as_str())
+        {
+            tracing::info!(
+                "proto inputs unchanged; skipping generation and postprocess (use --force to rebuild)"
             );
+            report.up_to_date = true;
+        } else if let Some(fds_bytes) = saved {
+            tracing::info!("resuming from saved generation; skipping generation");
+            report.resumed = true;
+            run_postprocess(cfg, Some(&fds_bytes), false, false, &mut report)?;
+            cache::write(&cfg.state_dir, &fp)?;
+        } else {
+            if options.resume {
+                tracing::info!("no saved generation matches the current inputs; regenerating");
+            }
+            cache::invalidate(&cfg.state_dir)?;
+            let fds_bytes = generate(cfg)?;
+            cache::write_generation(&cfg.state_dir, &fp, &fds_bytes)?;
+            run_postprocess(cfg, Some(&fds_bytes), false, false, &mut report)?;
+            cache::write(&cfg.state_dir, &fp)?;
+        }
+    }
+
+    if !options.no_verify {
+        let verified = events::phase("verify", || verify_modules(cfg, verify_only.as_ref()))?;
+        report.modules_verified = verified.modules_verified;
+        report.failures = verified.failures;
+    }
+    Ok(report)
+}
+
+/// Fail with every `include` path that is not a directory and, for the protoc
+/// backend, every `inputs` pattern that matches no proto, before protoc runs.
+fn check_proto_sources(cfg: &AppConfig, allow_empty_inputs: bool) -> Result<()> {
+    let mut problems: Vec<String> = cfg
+        .include
+        .iter()
+        .filter(|inc| !inc.is_dir())
+        .map(|inc| format!("include path is not a directory: {}", inc.display()))
+        .collect();
+    let mut unmatched = false;
+    // buf finds the protos of its modules itself and ignores `inputs`
+    if matches!(cfg.backend, Backend::Protoc) && !allow_empty_inputs {
+        for pattern in ProtocRunner::new(cfg).unmatched_inputs() {
+            problems.push(format!(
+                "inputs pattern matches no proto under the include paths: {pattern}"
+            ));
+            unmatched = true;
         }
-        tracing::info!("postprocess-only mode: skip generation");
-        None
+    }
+    if problems.is_empty() {
+        return Ok(());
+    }
+    let hint = if unmatched {
+        "\n(pass --allow-empty-inputs if an empty match is intended)"
     } else {
-        match cfg.backend {
-            Backend::Protoc => {
-                let runner = ProtocRunner::new(&cfg);
-                let fds_bytes = runner.generate()?;
-                let _pool = load_fds_from_bytes(&fds_bytes).context("decode FDS failed")?;
-                Some(
-                    collect_generated_basenames_from_bytes(&fds_bytes)
-                        .context("collect basenames from FDS failed")?,
-                )
+        ""
+    };
+    anyhow::bail!(
+        "proto sources do not resolve:\n  - {}{}",
+        problems.join("\n  - "),
+        hint
+    )
+}
+
+/// Generate and postprocess into a scratch copy of each output root, then fail
+/// listing the committed files that differ from it.
+fn check_stale(cfg: &AppConfig, report: &mut BuildReport) -> Result<()> {
+    let scratch = tempfile::tempdir().context("create scratch directory for --check-only")?;
+    let (fresh_cfg, fresh_roots) = relocate_outputs(cfg, scratch.path());
+    let fds_bytes = generate(&fresh_cfg)?;
+    run_postprocess(&fresh_cfg, Some(&fds_bytes), false, false, report)?;
+
+    let py_typed = cfg.postprocess.py_typed != PyTyped::Off;
+    let mut stale = Vec::new();
+    for (fresh, committed) in fresh_roots.iter().zip(&cfg.output_roots()) {
+        stale.extend(stale_files(fresh, committed, py_typed)?);
+    }
+    if stale.is_empty() {
+        tracing::info!("generated output is up to date");
+        return Ok(());
+    }
+    let lines: Vec<String> = stale
+        .iter()
+        .map(|f| {
+            let kind = match f.kind {
+                Staleness::Changed => "changed",
+                Staleness::Missing => "missing",
+                Staleness::Unexpected => "not generated anymore",
+            };
+            format!("{}: {}", kind, f.path.display())
+        })
+        .collect();
+    anyhow::bail!(
+        "generated output is stale ({} files differ from a fresh build):\n  - {}\n(run `build` to regenerate)",
+        stale.len(),
+        lines.join("\n  - ")
+    )
+}
+
+/// `cfg` with every output directory moved under `scratch`, so generating
+/// and postprocessing with it leaves the real output roots untouched.
+///
+/// Each output root keeps its directory name, which the package metadata is
+/// derived from. Relative plugin outputs follow the relocated `out`; absolute
+/// ones are moved along with the root they are under, or get a directory of
+/// their own under `scratch` otherwise.
+///
+/// # Returns
+///
+/// The relocated config and its output roots, in the order of
+/// [`AppConfig::output_roots`].
+fn relocate_outputs(cfg: &AppConfig, scratch: &Path) -> (AppConfig, Vec<PathBuf>) {
+    let roots = cfg.output_roots();
+    let fresh_roots: Vec<PathBuf> = roots
+        .iter()
+        .enumerate()
+        .map(|(i, root)| {
+            let name = root.file_name().unwrap_or_else(|| "out".as_ref());
+            scratch.join(i.to_string()).join(name)
+        })
+        .collect();
+    let relocate = |root: &PathBuf| {
+        let i = roots.iter().position(|r| r == root).unwrap_or(0);
+        fresh_roots[i].clone()
+    };
+    let mut fresh = cfg.clone();
+    fresh.out = relocate(&cfg.out);
+    fresh.grpc_out = cfg.grpc_out.as_ref().map(relocate);
+    fresh.mypy_out = cfg.mypy_out.as_ref().map(relocate);
+    for plugin in fresh.plugins.iter_mut().filter(|p| p.out.is_absolute()) {
+        plugin.out = roots
+            .iter()
+            .zip(&fresh_roots)
+            .find_map(|(root, fresh_root)| {
+                let rel = plugin.out.strip_prefix(root).ok()?;
+                Some(fresh_root.join(rel))
+            })
+            .unwrap_or_else(|| scratch.join("plugins").join(&plugin.name));
+    }
+    (fresh, fresh_roots)
+}
+
+/// `cfg` with `inputs` narrowed to `proto` and the protos it transitively imports.
+fn single_proto_config(cfg: &AppConfig, proto: &Path) -> Result<AppConfig> {
+    if !matches!(cfg.backend, Backend::Protoc) {
+        anyhow::bail!("--proto is only supported with the protoc backend");
+    }
+    let files = proto_closure(&cfg.include, proto)?;
+    tracing::info!(
+        "--proto {}: generating {} protos including its imports",
+        proto.display(),
+        files.len()
+    );
+    let mut single = cfg.clone();
+    single.inputs = files
+        .iter()
+        .map(|f| glob::Pattern::escape(&f.to_string_lossy()))
+        .collect();
+    Ok(single)
+}
+
+/// Fail unless `cfg.out` exists for `--postprocess-only`.
+fn existing_output(cfg: &AppConfig) -> Result<()> {
+    if !cfg.out.exists() {
+        anyhow::bail!(
+            "--postprocess-only: output directory does not exist: {}",
+            cfg.out.display()
+        );
+    }
+    tracing::info!("postprocess-only mode: skip generation");
+    Ok(())
+}
+
+/// Run protoc or buf into `cfg.out`, returning the FileDescriptorSet bytes.
+///
+/// With `cleanup_on_failure`, files the generator wrote before failing are
+/// removed again; the generator's error is returned either way.
+fn generate(cfg: &AppConfig) -> Result<Vec<u8>> {
+    events::phase("generate", || {
+        let snapshot = cfg.cleanup_on_failure.then(|| {
+            let mut roots = match cfg.backend {
+                Backend::Protoc => cfg.protoc_plugins().into_iter().map(|p| p.out).collect(),
+                Backend::Buf => vec![cfg.out.clone()],
+            };
+            roots.sort();
+            roots.dedup();
+            OutputSnapshot::take(roots)
+        });
+        let generated = match cfg.backend {
+            Backend::Protoc => ProtocRunner::new(cfg).generate(),
+            Backend::Buf => BufRunner::new(cfg).generate(),
+        };
+        let fds_bytes = match (generated, snapshot) {
+            (Ok(bytes), _) => bytes,
+            (Err(e), Some(snapshot)) => {
+                match snapshot.remove_new_files() {
+                    Ok(0) => {}
+                    Ok(n) => tracing::warn!("removed {} files written by the failed generation", n),
+                    Err(cleanup) => {
+                        tracing::warn!("cleanup after failed generation: {:#}", cleanup)
+                    }
+                }
+                return Err(e);
+            }
+            (Err(e), None) => return Err(e),
+        };
+        check_package_declarations(&fds_bytes, cfg.require_package)?;
+        if cfg.warn_unused_includes {
+            find_unused_includes(&fds_bytes, &cfg.include)?;
+        }
+        Ok(fds_bytes)
+    })
+}
+
+/// Run the postprocess steps as the `postprocess` phase.
+///
+/// `fds_bytes` is the FileDescriptorSet of the generation being processed,
+/// absent with `--postprocess-only`. Returns the number of files created or
+/// modified by postprocessing; with `dry_run` these are only logged as diffs.
+fn run_postprocess(
+    cfg: &AppConfig,
+    fds_bytes: Option<&[u8]>,
+    postprocess_only: bool,
+    dry_run: bool,
+    report: &mut BuildReport,
+) -> Result<usize> {
+    let touched = events::phase("postprocess", || {
+        postprocess(cfg, fds_bytes, postprocess_only, dry_run, report)
+    })?;
+    log_pass_summary(&report.passes);
+    Ok(touched)
+}
+
+/// Log one `postprocess summary` line per pass, also sent to the events file.
+///
+/// The pass, count and skipped state are event fields, so `--log-format json`
+/// renders the summary as JSON.
+fn log_pass_summary(passes: &[PassSummary]) {
+    for p in passes {
+        tracing::info!(
+            pass = p.pass,
+            count = p.count,
+            skipped = p.skipped,
+            "postprocess summary"
+        );
+        events::emit!(
+            "postprocess_pass",
+            pass = p.pass,
+            count = p.count,
+            skipped = p.skipped
+        );
+    }
+}
+
+/// Run the postprocess steps on each output root, returning the number of
+/// files touched.
+///
+/// With `grpc_out`/`mypy_out` set, every root gets the same package layout and
+/// rewrites; the other roots are passed along so imports between modules in
+/// different roots still resolve.
+fn postprocess(
+    cfg: &AppConfig,
+    fds_bytes: Option<&[u8]>,
+    postprocess_only: bool,
+    dry_run: bool,
+    report: &mut BuildReport,
+) -> Result<usize> {
+    let mut touched = 0usize;
+    let roots = cfg.output_roots();
+    let mut generation = fds_bytes.map(Generation::decode).transpose()?;
+    if let Some(transform) = &cfg.postprocess.module_transform {
+        if dry_run && postprocess_only {
+            // Renaming cannot be previewed without touching `out`
+            tracing::info!("dry-run: skipping module_transform on existing output");
+            report.record_pass("module_transform", None);
+        } else {
+            for root in &roots {
+                let renamed = apply_module_transform(root, transform)
+                    .context("apply module_transform failed")?;
+                tracing::info!("module_transform renamed {} modules", renamed.len());
+                report.record_pass("module_transform", Some(renamed.len()));
             }
-            Backend::Buf => {
-                tracing::warn!("buf backend is not implemented yet");
-                None
+            // Keep FDS-derived names in sync with the renamed modules
+            if let Some(generation) = &mut generation {
+                generation.rename(transform);
             }
         }
-    };
+    }
 
+    for root in &roots {
+        let overlays: Vec<PathBuf> = roots.iter().filter(|r| *r != root).cloned().collect();
+        touched += postprocess_root(cfg, root, &overlays, generation.as_ref(), dry_run, report)?;
+    }
+
+    if cfg.postprocess.emit_package_metadata {
+        let package = metadata_package_name(cfg);
+        let written = write_package_metadata(&cfg.out, &package, dry_run)?;
+        if written {
+            tracing::info!("package metadata written for {:?}", package);
+            touched += 1;
+        }
+        report.record_pass("emit_package_metadata", Some(usize::from(written)));
+    }
+
+    Ok(touched)
+}
+
+/// What postprocessing knows about the generation being processed, decoded
+/// once from its FileDescriptorSet.
+struct Generation<'a> {
+    fds_bytes: &'a [u8],
+    /// Generated module basenames, e.g. `orders_pb2`
+    basenames: HashSet<String>,
+    /// Generated modules by dotted name, with their paths relative to the
+    /// output root, e.g. `api.orders_pb2` -> `api/orders_pb2.py`
+    modules: HashMap<String, PathBuf>,
+}
+
+impl<'a> Generation<'a> {
+    fn decode(fds_bytes: &'a [u8]) -> Result<Self> {
+        let pool = load_fds_from_bytes(fds_bytes).context("decode FDS failed")?;
+        let modules = generated_modules(&pool);
+        let basenames = modules
+            .keys()
+            .map(|m| module_basename(m).to_string())
+            .collect();
+        Ok(Self {
+            fds_bytes,
+            basenames,
+            modules,
+        })
+    }
+
+    /// Follow the renames `module_transform` made on disk.
+    fn rename(&mut self, transform: &ModuleTransform) {
+        self.basenames = self.basenames.iter().map(|b| transform.apply(b)).collect();
+        self.modules = self
+            .modules
+            .drain()
+            .map(|(module, path)| {
+                let renamed = transform.apply(module_basename(&module));
+                let module = match module.rsplit_once('.') {
+                    Some((package, _)) => format!("{package}.{renamed}"),
+                    None => renamed.clone(),
+                };
+                (module, path.with_file_name(format!("{renamed}.py")))
+            })
+            .collect();
+    }
+}
+
+fn module_basename(module: &str) -> &str {
+    module.rsplit_once('.').map_or(module, |(_, base)| base)
+}
+
+/// The postprocess steps that run once per output root, after module_transform.
+fn postprocess_root(
+    cfg: &AppConfig,
+    root: &Path,
+    overlays: &[PathBuf],
+    generation: Option<&Generation>,
+    dry_run: bool,
+    report: &mut BuildReport,
+) -> Result<usize> {
+    let mut touched = 0usize;
     if cfg.postprocess.create_package {
-        let created = create_packages(&cfg.out)?;
+        let created = create_packages(
+            root,
+            cfg.postprocess.package_root_init,
+            &cfg.postprocess.exclude,
+            dry_run,
+        )?;
         tracing::info!("created __init__.py: {}", created);
+        report.packages_created += created;
+        report.record_pass("create_package", Some(created));
+        touched += created;
+    }
+
+    if cfg.postprocess.init_reexports {
+        match generation {
+            Some(generation) => {
+                let written = write_init_reexports(
+                    root,
+                    &generation.basenames,
+                    cfg.postprocess.package_root_init,
+                    &cfg.postprocess.exclude,
+                    dry_run,
+                )?;
+                tracing::info!("__init__.py re-exports written: {}", written);
+                report.record_pass("init_reexports", Some(written));
+                touched += written;
+            }
+            None => {
+                tracing::info!(
+                    "postprocess-only mode: skip __init__.py re-exports (needs the FDS)"
+                );
+                report.record_pass("init_reexports", None);
+            }
+        }
     }
 
-    let (files, hits) =
-        scan_and_report(&cfg.out).context("scan relative-import candidates failed")?;
+    if cfg.postprocess.py_typed != PyTyped::Off {
+        let created = write_py_typed(
+            root,
+            cfg.postprocess.out_is_import_root(),
+            cfg.postprocess.py_typed == PyTyped::All,
+            dry_run,
+        )?;
+        tracing::info!("py.typed markers created: {}", created);
+        report.record_pass("py_typed", Some(created));
+        touched += created;
+    }
+
+    // Read each file once, rewriting it while its content is at hand
+    let rewrite = cfg.postprocess.relative_imports.then(|| TreeRewrite {
+        root,
+        overlays,
+        exclude_google: cfg.postprocess.exclude_google,
+        module_suffixes: &cfg.postprocess.module_suffixes,
+        known_modules: generation.map(|g| &g.modules),
+        exclude: &cfg.postprocess.exclude,
+        dry_run,
+    });
+    // Rewritten imports are checked to stay within the root on the new
+    // content; nothing is written on disk in dry-run, so nothing is checked
+    let root_is_package = !cfg.postprocess.out_is_import_root();
+    let mut modified = 0usize;
+    let mut escaping = Vec::new();
+    let (files, hits) = scan_tree(root, |file| {
+        if let Some(rewrite) = &rewrite
+            && let Some(new_content) = rewrite
+                .apply(&file)
+                .context("apply relative-import rewrites failed")?
+        {
+            modified += 1;
+            if !dry_run {
+                escaping.extend(find_escaping_relative_imports(
+                    root,
+                    &file.path,
+                    &new_content,
+                    root_is_package,
+                ));
+            }
+        }
+        Ok(())
+    })
+    .context("scan relative-import candidates failed")?;
     tracing::info!(
         "relative-import candidates: files={}, lines={}",
         files,
@@ -95,28 +761,461 @@ pub fn build(pyproject: Option<&str>, no_verify: bool, _postprocess_only: bool)
     );
 
     if cfg.postprocess.relative_imports {
-        let modified = apply_rewrites_in_tree(
-            &cfg.out,
-            cfg.postprocess.exclude_google,
-            &cfg.postprocess.module_suffixes,
-            allowed_basenames.as_ref(),
-        )
-        .context("apply relative-import rewrites failed")?;
         tracing::info!(
             "relative-import rewrites applied: {} files modified",
             modified
         );
+        report.files_rewritten += modified;
+        report.record_pass("relative_imports", Some(modified));
+        touched += modified;
+    }
+
+    if !escaping.is_empty() {
+        for e in &escaping {
+            tracing::error!(
+                "{}:{}: relative import escapes output root: {}",
+                e.file.display(),
+                e.line,
+                e.statement
+            );
+        }
+        anyhow::bail!(
+            "{} relative imports escape the output root {}",
+            escaping.len(),
+            root.display()
+        );
+    }
+
+    if cfg.postprocess.emit_all {
+        match generation {
+            Some(generation) => {
+                let exports = collect_exports(generation.fds_bytes, &cfg.postprocess.all_exclude)
+                    .context("collect __all__ names from FDS failed")?;
+                let written = write_all_exports(
+                    root,
+                    &exports,
+                    cfg.postprocess.module_transform.as_ref(),
+                    &cfg.postprocess.exclude,
+                    dry_run,
+                )?;
+                tracing::info!("__all__ written: {} files", written);
+                report.record_pass("emit_all", Some(written));
+                touched += written;
+            }
+            None => {
+                tracing::info!("postprocess-only mode: skip __all__ (needs the FDS)");
+                report.record_pass("emit_all", None);
+            }
+        }
     }
 
     if cfg.postprocess.pyright_header {
-        let added = add_pyright_header(&cfg.out)?;
+        let added = add_pyright_header(root, dry_run)?;
         if added > 0 {
             tracing::info!("pyright header added: {} files", added);
         }
+        report.record_pass("pyright_header", Some(added));
+        touched += added;
     }
 
-    if !no_verify {
-        verify(&cfg)?;
+    Ok(touched)
+}
+
+/// Dotted package name the emitted metadata installs `out` as.
+///
+/// Uses the installed-profile `top_package` when configured, otherwise the
+/// output directory's name; empty when `out` holds top-level packages.
+fn metadata_package_name(cfg: &AppConfig) -> String {
+    if let Some(VerifyProfile::Installed { top_package }) = cfg.verify.as_ref().map(|v| &v.profile)
+    {
+        return top_package.clone();
+    }
+    if cfg.postprocess.out_is_import_root() {
+        return String::new();
+    }
+    cfg.out
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "generated".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use crate::utils::fake_protoc;
+    use prost::Message;
+    use prost_types::{FileDescriptorProto, FileDescriptorSet};
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn resume_postprocesses_with_saved_generation() {
+        let dir = tempdir().unwrap();
+        let out = dir.path().join("generated");
+        fs::create_dir_all(&out).unwrap();
+        fs::write(out.join("a_pb2.py"), "").unwrap();
+        fs::write(out.join("b_pb2.py"), "import a_pb2 as a__pb2\n").unwrap();
+        let config = dir.path().join("pyproject.toml");
+        fs::write(
+            &config,
+            format!(
+                "[tool.python_proto_importer]\nout = {:?}\n",
+                out.display().to_string()
+            ),
+        )
+        .unwrap();
+        let cfg = AppConfig::load(Some(&config)).unwrap();
+
+        // A previous build generated a.proto and b.proto, then failed later
+        let fds = FileDescriptorSet {
+            file: ["a.proto", "b.proto"]
+                .map(|name| FileDescriptorProto {
+                    name: Some(name.to_string()),
+                    package: Some("pkg".to_string()),
+                    ..Default::default()
+                })
+                .to_vec(),
+        };
+        let fp = cache::fingerprint(&cfg).unwrap();
+        cache::write_generation(&cfg.state_dir, &fp, &fds.encode_to_vec()).unwrap();
+
+        let options = BuildOptions {
+            no_verify: true,
+            resume: true,
+            ..Default::default()
+        };
+        let report = build_with(&cfg, &options).unwrap();
+        assert!(report.resumed);
+        assert_eq!(report.files_rewritten, 1);
+        assert_eq!(
+            fs::read_to_string(out.join("b_pb2.py")).unwrap(),
+            "from . import a_pb2 as a__pb2\n"
+        );
+        assert_eq!(cache::read(&cfg.state_dir), Some(fp));
+    }
+
+    #[test]
+    fn summary_lists_each_enabled_pass() {
+        let dir = tempdir().unwrap();
+        let out = dir.path().join("generated");
+        fs::create_dir_all(out.join("api")).unwrap();
+        fs::write(out.join("api/a_pb2.py"), "").unwrap();
+        fs::write(
+            out.join("api/b_pb2.py"),
+            "from api import a_pb2 as a__pb2\n",
+        )
+        .unwrap();
+        let config = dir.path().join("pyproject.toml");
+        fs::write(
+            &config,
+            format!(
+                "[tool.python_proto_importer]\nout = {:?}\n\
+                 [tool.python_proto_importer.postprocess]\n\
+                 emit_all = true\npyright_header = true\n",
+                out.display().to_string()
+            ),
+        )
+        .unwrap();
+        let cfg = AppConfig::load(Some(&config)).unwrap();
+
+        let options = BuildOptions {
+            no_verify: true,
+            postprocess_only: true,
+            ..Default::default()
+        };
+        let report = build_with(&cfg, &options).unwrap();
+        let summary = |pass: &'static str, count: usize, skipped: bool| PassSummary {
+            pass,
+            count,
+            skipped,
+        };
+        // `__all__` needs the FDS, which --postprocess-only does not have
+        assert_eq!(
+            report.passes,
+            vec![
+                summary("create_package", 2, false),
+                summary("relative_imports", 1, false),
+                summary("emit_all", 0, true),
+                summary("pyright_header", 2, false),
+            ]
+        );
+    }
+
+    /// Write and load a pyproject.toml generating `dir/proto` into
+    /// `dir/generated` with `dir/protoc`, followed by the `extra` settings.
+    #[cfg(unix)]
+    fn write_config(dir: &Path, extra: &str) -> AppConfig {
+        let config = dir.join("pyproject.toml");
+        fs::write(
+            &config,
+            format!(
+                "[tool.python_proto_importer]\nout = {:?}\nprotoc_path = {:?}\ninclude = [{:?}]\n{extra}",
+                dir.join("generated").display().to_string(),
+                dir.join("protoc").display().to_string(),
+                dir.join("proto").display().to_string()
+            ),
+        )
+        .unwrap();
+        AppConfig::load(Some(&config)).unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failed_generation_removes_partial_output() {
+        let dir = tempdir().unwrap();
+        let out = dir.path().join("generated");
+        fs::create_dir_all(&out).unwrap();
+        fs::write(out.join("keep.py"), "").unwrap();
+        // protoc writes one module, then a plugin fails
+        fake_protoc(
+            dir.path(),
+            &format!(
+                "mkdir -p {0}/api && echo partial > {0}/api/a_pb2.py\nexit 1",
+                out.display()
+            ),
+        );
+
+        let cfg = write_config(dir.path(), "");
+        assert!(generate(&cfg).is_err());
+        assert!(out.join("keep.py").is_file());
+        assert!(!out.join("api").exists());
+
+        let cfg = write_config(dir.path(), "cleanup_on_failure = false\n");
+        assert!(generate(&cfg).is_err());
+        assert!(out.join("api/a_pb2.py").is_file());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn single_proto_build_generates_only_its_imports() {
+        let dir = tempdir().unwrap();
+        let proto = dir.path().join("proto");
+        let out = dir.path().join("generated");
+        fs::create_dir_all(&proto).unwrap();
+        fs::write(
+            proto.join("orders.proto"),
+            "syntax = \"proto3\";\nimport \"money.proto\";\n",
+        )
+        .unwrap();
+        fs::write(proto.join("money.proto"), "syntax = \"proto3\";\n").unwrap();
+        fs::write(proto.join("unrelated.proto"), "syntax = \"proto3\";\n").unwrap();
+        // Writes an empty descriptor set and one module per input proto
+        fake_protoc(
+            dir.path(),
+            &format!(
+                "for a in \"$@\"; do case \"$a\" in\n\
+                 --descriptor_set_out=*) : > \"${{a#--descriptor_set_out=}}\" ;;\n\
+                 *.proto) n=$(basename \"$a\" .proto); : > {}/\"$n\"_pb2.py ;;\n\
+                 esac; done",
+                out.display()
+            ),
+        );
+        let cfg = write_config(dir.path(), "inputs = [\"*.proto\"]\n");
+
+        let options = BuildOptions {
+            no_verify: true,
+            proto: Some(PathBuf::from("orders.proto")),
+            ..Default::default()
+        };
+        build_with(&cfg, &options).unwrap();
+        assert!(out.join("orders_pb2.py").is_file());
+        assert!(out.join("money_pb2.py").is_file());
+        assert!(!out.join("unrelated_pb2.py").exists());
+        assert_eq!(cache::read(&cfg.state_dir), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn generate_only_leaves_raw_protoc_output() {
+        let dir = tempdir().unwrap();
+        let proto = dir.path().join("proto");
+        let out = dir.path().join("generated");
+        fs::create_dir_all(proto.join("api")).unwrap();
+        fs::write(proto.join("api/orders.proto"), "syntax = \"proto3\";\n").unwrap();
+        fake_protoc(
+            dir.path(),
+            "for a in \"$@\"; do case \"$a\" in\n\
+             --descriptor_set_out=*) : > \"${a#--descriptor_set_out=}\" ;;\n\
+             --python_out=*) o=\"${a#--python_out=}\" ;;\n\
+             *.proto) mkdir -p \"$o/api\"; echo 'import api.money_pb2' > \"$o/api/orders_pb2.py\"; : > \"$o/api/money_pb2.py\" ;;\n\
+             esac; done",
+        );
+        let cfg = write_config(dir.path(), "inputs = [\"api/*.proto\"]\n");
+
+        let report = build_with(
+            &cfg,
+            &BuildOptions {
+                generate_only: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(report.passes.is_empty());
+        assert_eq!(report.modules_verified, 0);
+        assert_eq!(
+            fs::read_to_string(out.join("api/orders_pb2.py")).unwrap(),
+            "import api.money_pb2\n"
+        );
+        assert!(!out.join("api/__init__.py").exists());
+        assert_eq!(cache::read(&cfg.state_dir), None);
+
+        let both = BuildOptions {
+            generate_only: true,
+            postprocess_only: true,
+            ..Default::default()
+        };
+        assert!(build_with(&cfg, &both).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dry_run_generates_every_output_root_into_scratch() {
+        let dir = tempdir().unwrap();
+        let proto = dir.path().join("proto");
+        let out = dir.path().join("generated");
+        let stubs = dir.path().join("stubs");
+        fs::create_dir_all(proto.join("api")).unwrap();
+        fs::write(proto.join("api/orders.proto"), "syntax = \"proto3\";\n").unwrap();
+        fake_protoc(
+            dir.path(),
+            "for a in \"$@\"; do case \"$a\" in\n\
+             --descriptor_set_out=*) : > \"${a#--descriptor_set_out=}\" ;;\n\
+             --python_out=*) o=\"${a#--python_out=}\" ;;\n\
+             --mypy_out=*) s=\"${a#--mypy_out=}\" ;;\n\
+             *.proto) mkdir -p \"$o/api\" \"$s/api\"; : > \"$o/api/orders_pb2.py\"; : > \"$s/api/orders_pb2.pyi\" ;;\n\
+             esac; done",
+        );
+        let cfg = write_config(
+            dir.path(),
+            &format!(
+                "inputs = [\"api/*.proto\"]\nmypy = true\nmypy_out = {:?}\n",
+                stubs.display().to_string()
+            ),
+        );
+
+        let report = build_with(
+            &cfg,
+            &BuildOptions {
+                dry_run: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(!out.exists());
+        assert!(!stubs.exists());
+        // The scratch tree was still postprocessed: one __init__.py per root
+        // and package
+        assert_eq!(report.packages_created, 4);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn check_only_reports_stale_output_without_touching_it() {
+        let dir = tempdir().unwrap();
+        let proto = dir.path().join("proto");
+        let out = dir.path().join("generated");
+        fs::create_dir_all(proto.join("api")).unwrap();
+        fs::write(proto.join("api/orders.proto"), "syntax = \"proto3\";\n").unwrap();
+        // Writes an empty descriptor set and one module per input proto into --python_out
+        fake_protoc(
+            dir.path(),
+            "for a in \"$@\"; do case \"$a\" in\n\
+             --descriptor_set_out=*) : > \"${a#--descriptor_set_out=}\" ;;\n\
+             --python_out=*) o=\"${a#--python_out=}\" ;;\n\
+             --docs_out=*) d=\"${a#--docs_out=}\"; mkdir -p \"$d\"; echo 'v1' > \"$d/index.txt\" ;;\n\
+             *.proto) n=$(basename \"$a\" .proto); mkdir -p \"$o/api\"; echo 'x = 1' > \"$o/api/$n\"_pb2.py ;;\n\
+             esac; done",
+        );
+        let cfg = write_config(
+            dir.path(),
+            "inputs = [\"api/*.proto\"]\n\n\
+             [[tool.python_proto_importer.plugin]]\nname = \"docs\"\nout = \"docs\"\n",
+        );
+        let check = BuildOptions {
+            check_only: true,
+            ..Default::default()
+        };
+
+        build_with(
+            &cfg,
+            &BuildOptions {
+                no_verify: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        build_with(&cfg, &check).unwrap();
+
+        // The proto was edited without regenerating, and an __init__.py got lost
+        fs::write(out.join("api/orders_pb2.py"), "x = 0\n").unwrap();
+        fs::remove_file(out.join("api/__init__.py")).unwrap();
+        // Plugin outputs are compared too, and not regenerated in place
+        fs::write(out.join("docs/index.txt"), "v0\n").unwrap();
+        let msg = build_with(&cfg, &check).unwrap_err().to_string();
+        assert!(msg.contains("stale (3 files differ"), "{msg}");
+        assert!(msg.contains(&format!(
+            "changed: {}",
+            out.join("docs/index.txt").display()
+        )));
+        assert_eq!(
+            fs::read_to_string(out.join("docs/index.txt")).unwrap(),
+            "v0\n"
+        );
+        assert!(msg.contains(&format!(
+            "changed: {}",
+            out.join("api/orders_pb2.py").display()
+        )));
+        assert!(msg.contains(&format!(
+            "missing: {}",
+            out.join("api/__init__.py").display()
+        )));
+        assert_eq!(
+            fs::read_to_string(out.join("api/orders_pb2.py")).unwrap(),
+            "x = 0\n"
+        );
+        assert!(!out.join("api/__init__.py").exists());
+    }
+
+    #[test]
+    fn unresolved_includes_and_inputs_are_reported_together() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("proto")).unwrap();
+        let config = dir.path().join("pyproject.toml");
+        let proto = dir.path().join("proto");
+        let missing = dir.path().join("protos");
+        fs::write(
+            &config,
+            format!(
+                "[tool.python_proto_importer]\nout = {:?}\ninclude = [{:?}, {:?}]\ninputs = [\"*.proto\", \"api/*.proto\"]\n",
+                dir.path().join("generated").display().to_string(),
+                proto.display().to_string(),
+                missing.display().to_string()
+            ),
+        )
+        .unwrap();
+        let cfg = AppConfig::load(Some(&config)).unwrap();
+
+        let msg = build_with(&cfg, &BuildOptions::default())
+            .unwrap_err()
+            .to_string();
+        assert!(
+            msg.contains(&format!(
+                "include path is not a directory: {}",
+                missing.display()
+            )),
+            "{msg}"
+        );
+        assert!(msg.contains("matches no proto under the include paths: *.proto"));
+        assert!(msg.contains("matches no proto under the include paths: api/*.proto"));
+        assert!(msg.contains("--allow-empty-inputs"));
+
+        let options = BuildOptions {
+            allow_empty_inputs: true,
+            ..Default::default()
+        };
+        let msg = build_with(&cfg, &options).unwrap_err().to_string();
+        assert!(msg.contains("include path is not a directory"), "{msg}");
+        assert!(!msg.contains("inputs pattern"), "{msg}");
     }
-    Ok(())
 }
@@ -0,0 +1,206 @@
+use crate::commands::build::{BuildOptions, build_with};
+use crate::config::AppConfig;
+use crate::utils::normalize;
+use anyhow::{Context, Result, bail};
+use glob::Pattern;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Build only if the configured target has changed proto inputs.
+///
+/// Changed files are taken from `git diff --name-only <since>` (working tree
+/// against `since`, default `HEAD`) plus untracked files. The target is
+/// considered changed when any of those files is a `.proto` under one of its
+/// `include` paths or matches one of its `inputs` globs. Unchanged targets are
+/// skipped with a log instead of being rebuilt.
+///
+/// # Arguments
+///
+/// * `pyproject` - Optional path to the pyproject.toml file
+/// * `since` - Git ref to diff against; `None` means `HEAD`
+/// * `options` - Passed through to [`build_with`]
+pub fn build_changed(
+    pyproject: Option<&str>,
+    since: Option<&str>,
+    options: &BuildOptions,
+) -> Result<()> {
+    let cfg = AppConfig::load(pyproject.map(Path::new)).context("failed to load config")?;
+    let cwd = std::env::current_dir().context("failed to get current directory")?;
+    let since = since.unwrap_or("HEAD");
+    let changed = git_changed_files(&cwd, since)?;
+    tracing::debug!("{} files changed since {}", changed.len(), since);
+
+    if !target_is_affected(&cfg, &cwd, &changed) {
+        tracing::info!(
+            out = %cfg.out.display(),
+            "no changed protos since {}; skipping target",
+            since
+        );
+        return Ok(());
+    }
+    build_with(&cfg, options)?;
+    Ok(())
+}
+
+/// List files changed relative to `since`, as absolute paths.
+fn git_changed_files(cwd: &Path, since: &str) -> Result<Vec<PathBuf>> {
+    let toplevel = git(cwd, &["rev-parse", "--show-toplevel"])
+        .context("--changed requires a git repository")?;
+    let root = PathBuf::from(toplevel.trim());
+
+    let diff = git(cwd, &["diff", "--name-only", since, "--"])?;
+    let untracked = git(
+        cwd,
+        &["ls-files", "--others", "--exclude-standard", "--full-name"],
+    )?;
+    Ok(diff
+        .lines()
+        .chain(untracked.lines())
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| root.join(l.trim()))
+        .collect())
+}
+
+fn git(cwd: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(cwd)
+        .output()
+        .context("failed to run git")?;
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Decide whether any of `changed` (absolute paths) affects `cfg`.
+///
+/// Relative `include` entries are resolved against `base`, and relative
+/// `inputs` against the config directory under it.
+pub fn target_is_affected(cfg: &AppConfig, base: &Path, changed: &[PathBuf]) -> bool {
+    let includes: Vec<PathBuf> = cfg
+        .include
+        .iter()
+        .map(|inc| normalize(&base.join(inc)))
+        .collect();
+    let inputs: Vec<Pattern> = cfg
+        .inputs
+        .iter()
+        .filter_map(|p| {
+            Pattern::new(&normalize(&base.join(&cfg.config_dir).join(p)).to_string_lossy()).ok()
+        })
+        .collect();
+
+    changed.iter().map(|p| normalize(p)).any(|file| {
+        inputs.iter().any(|pat| pat.matches_path(&file))
+            || (file.extension().is_some_and(|e| e == "proto")
+                && includes.iter().any(|inc| file.starts_with(inc)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Backend, PostProcess, PyTyped};
+
+    fn create_test_config(include: &[&str], inputs: &[&str]) -> AppConfig {
+        AppConfig {
+            backend: Backend::Protoc,
+            python_exe: "python3".to_string(),
+            include: include.iter().map(PathBuf::from).collect(),
+            inputs: inputs.iter().map(|s| s.to_string()).collect(),
+            config_dir: PathBuf::new(),
+            out: PathBuf::from("generated"),
+            grpc_out: None,
+            mypy_out: None,
+            generate_mypy: false,
+            generate_mypy_grpc: false,
+            plugins: vec![],
+            protoc_path: None,
+            extra_protoc_args: vec![],
+            generation_timeout_secs: None,
+            timeout_secs: None,
+            require_package: false,
+            warn_unused_includes: false,
+            cleanup_on_failure: true,
+            buf_gen_yaml: None,
+            state_dir: PathBuf::from(".proto-importer"),
+            postprocess: PostProcess {
+                relative_imports: true,
+                fix_pyi: true,
+                create_package: true,
+                package_root_init: true,
+                init_reexports: false,
+                namespace_packages: false,
+                emit_package_metadata: false,
+                py_typed: PyTyped::Off,
+                emit_all: false,
+                all_exclude: vec![],
+                exclude_google: true,
+                pyright_header: false,
+                module_suffixes: vec!["_pb2.py".into()],
+                module_transform: None,
+                exclude: vec![],
+            },
+            verify: None,
+            doctor: Default::default(),
+        }
+    }
+
+    fn paths(files: &[&str]) -> Vec<PathBuf> {
+        files.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn proto_under_include_is_affected() {
+        let cfg = create_test_config(&["proto"], &["proto/api/**/*.proto"]);
+        let base = Path::new("/repo/svc");
+        // Imported dependency outside the inputs glob but on the include path
+        assert!(target_is_affected(
+            &cfg,
+            base,
+            &paths(&["/repo/svc/proto/common/types.proto"])
+        ));
+        assert!(target_is_affected(
+            &cfg,
+            base,
+            &paths(&["/repo/svc/proto/api/v1/service.proto"])
+        ));
+    }
+
+    #[test]
+    fn unrelated_changes_are_skipped() {
+        let cfg = create_test_config(&["proto"], &["proto/**/*.proto"]);
+        let base = Path::new("/repo/svc");
+        assert!(!target_is_affected(
+            &cfg,
+            base,
+            &paths(&[
+                "/repo/svc/src/main.rs",
+                "/repo/other/proto/x.proto",
+                "/repo/svc/proto/README.md",
+            ])
+        ));
+        assert!(!target_is_affected(&cfg, base, &[]));
+    }
+
+    #[test]
+    fn parent_relative_include_is_resolved() {
+        let cfg = create_test_config(&["../shared"], &["../shared/**/*.proto"]);
+        let base = Path::new("/repo/svc");
+        assert!(target_is_affected(
+            &cfg,
+            base,
+            &paths(&["/repo/shared/common.proto"])
+        ));
+        assert!(!target_is_affected(
+            &cfg,
+            base,
+            &paths(&["/repo/svc/shared/common.proto"])
+        ));
+    }
+}
@@ -1,5 +1,5 @@
 use crate::config::AppConfig;
-use crate::verification::import_test::verify;
+use crate::verification::{VerifyReport, verify};
 use anyhow::{Context, Result};
 use std::path::Path;
 
@@ -46,7 +46,16 @@ use std::path::Path;
 /// ```
 pub fn check(pyproject: Option<&str>) -> Result<()> {
     let cfg = AppConfig::load(pyproject.map(Path::new)).context("failed to load config")?;
-    verify(&cfg)
+    check_with(&cfg)?;
+    Ok(())
+}
+
+/// Verify the existing output of an already loaded configuration.
+///
+/// Library counterpart of [`check`]; failures beyond what the config
+/// tolerates are returned as a [`VerifyFailed`](crate::verification::VerifyFailed) error.
+pub fn check_with(cfg: &AppConfig) -> Result<VerifyReport> {
+    verify(cfg)
 }
 
 #[cfg(test)]
@@ -61,7 +70,7 @@ mod tests {
         let mut file = fs::File::create(&config_file)?;
         writeln!(file, "[tool.python_proto_importer]")?;
         writeln!(file, "out = \"{}\"", out_dir)?;
-        writeln!(file, "proto_path = [\"proto\"]")?;
+        writeln!(file, "include = [\"proto\"]")?;
         writeln!(file, "python_exe = \"python3\"")?;
         Ok(config_file.to_string_lossy().to_string())
     }
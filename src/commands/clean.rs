@@ -1,37 +1,52 @@
-use crate::config::AppConfig;
+use crate::config::{AppConfig, PyTyped};
+use crate::generator::cache;
+use crate::postprocess::fds::collect_generated_basenames_from_bytes;
+use crate::postprocess::is_generated_init;
 use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use std::collections::HashSet;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
 
-/// Execute the clean command to remove the generated output directory.
+/// Execute the clean command to remove generated artifacts.
 ///
-/// This command removes the entire output directory and all its contents, as
-/// specified in the pyproject.toml configuration. It provides a safety mechanism
-/// by requiring explicit confirmation to prevent accidental deletion.
+/// By default only what the tool generates is removed from the output
+/// directory: `_pb2`/`_pb2_grpc` modules and stubs, `__init__.py` files that
+/// are empty or only re-export generated modules, and `__pycache__`
+/// directories, plus empty `py.typed` markers when `py_typed` writes them.
+/// Directories left empty by that are pruned, so hand-written modules,
+/// `__init__.py` files with other content and directories the user created
+/// survive.
+/// With `all`, the entire output directory is removed instead. `grpc_out` and
+/// `mypy_out` are cleaned the same way as `out`. The tool's `state_dir`
+/// (build cache and other state) is removed in both modes.
 ///
 /// # Arguments
 ///
 /// * `pyproject` - Optional path to the pyproject.toml file. If None, uses "pyproject.toml"
 /// * `yes` - Safety flag that must be true to actually perform the deletion
+/// * `all` - Remove the whole output directory rather than only generated files
 ///
 /// # Returns
 ///
 /// Returns `Ok(())` if the operation completes successfully, or an error if:
 /// - Configuration cannot be loaded
 /// - The safety flag (`yes`) is false when the directory exists
-/// - Directory removal fails due to permissions or other filesystem issues
+/// - File or directory removal fails due to permissions or other filesystem issues
 ///
 /// # Safety Features
 ///
 /// - **Confirmation Required**: Refuses to delete without explicit `yes` flag
 /// - **No-op for Missing**: Succeeds silently if the output directory doesn't exist
-/// - **Complete Removal**: Recursively removes all files and subdirectories
+/// - **Precise by Default**: When the last build saved its FileDescriptorSet,
+///   only modules named after its protos are removed
 ///
 /// # Use Cases
 ///
 /// - **Fresh Start**: Clear all generated code before regeneration
 /// - **CI Cleanup**: Ensure clean environment between builds
-/// - **Development**: Reset state during iteration
+/// - **Shared Output**: Keep hand-written code that lives next to generated modules
 ///
 /// # Example
 ///
@@ -39,26 +54,227 @@ use std::path::Path;
 /// use python_proto_importer::commands::clean;
 ///
 /// // Safe call - will refuse to delete without confirmation
-/// let result = clean(None, false);
+/// let result = clean(None, false, false);
 /// assert!(result.is_err()); // Expects error without --yes
 ///
 /// // Actual deletion with confirmation
-/// clean(None, true)?; // Removes the configured output directory
+/// clean(None, true, false)?; // Removes generated files from the output directory
 /// # Ok::<(), anyhow::Error>(())
 /// ```
-pub fn clean(pyproject: Option<&str>, yes: bool) -> Result<()> {
+pub fn clean(pyproject: Option<&str>, yes: bool, all: bool) -> Result<()> {
     let cfg = AppConfig::load(pyproject.map(Path::new)).context("failed to load config")?;
-    let out = &cfg.out;
-    if out.exists() {
-        if !yes {
-            bail!("refusing to remove {} without --yes", out.display());
+    clean_with(&cfg, yes, all)
+}
+
+/// Clean `out` and remove `state_dir` of an already loaded configuration.
+///
+/// Library counterpart of [`clean`], with the same `yes` confirmation.
+pub fn clean_with(cfg: &AppConfig, yes: bool, all: bool) -> Result<()> {
+    let roots = cfg.output_roots();
+    for dir in roots.iter().chain([&cfg.state_dir]) {
+        if dir.exists() && !yes {
+            bail!("refusing to remove {} without --yes", dir.display());
+        }
+    }
+
+    let targets = clean_targets(cfg, all)?;
+    // The roots themselves may be pruned below
+    let report_files = !all && roots.iter().any(|r| r.exists());
+    let mut files = 0usize;
+    for target in &targets {
+        let path = &target.path;
+        match target.kind {
+            TargetKind::File => {
+                tracing::debug!("removing {}", path.display());
+                fs::remove_file(path)
+                    .with_context(|| format!("failed to remove {}", path.display()))?;
+                files += 1;
+            }
+            TargetKind::Tree => {
+                tracing::info!("removing {}", path.display());
+                fs::remove_dir_all(path)
+                    .with_context(|| format!("failed to remove {}", path.display()))?;
+            }
+            TargetKind::EmptyDir => {
+                fs::remove_dir(path)
+                    .with_context(|| format!("failed to remove {}", path.display()))?;
+            }
+        }
+    }
+    if report_files {
+        tracing::info!(
+            "removed {} generated files from {}",
+            files,
+            roots
+                .iter()
+                .map(|r| r.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Print what `clean` would remove without removing anything.
+///
+/// Paths are printed one per line, or with `json` as an array of
+/// `{"path", "kind"}` objects, in the order `clean` would remove them.
+/// No `--yes` is needed since nothing is deleted.
+pub fn clean_dry_run(pyproject: Option<&str>, all: bool, json: bool) -> Result<()> {
+    let cfg = AppConfig::load(pyproject.map(Path::new)).context("failed to load config")?;
+    let targets = clean_targets(&cfg, all)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&targets)?);
+    } else {
+        for target in &targets {
+            println!("{}", target.path.display());
         }
-        tracing::info!("removing {}", out.display());
-        fs::remove_dir_all(out).with_context(|| format!("failed to remove {}", out.display()))?;
     }
     Ok(())
 }
 
+/// A path removed by `clean`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CleanTarget {
+    pub path: PathBuf,
+    pub kind: TargetKind,
+}
+
+/// How a [`CleanTarget`] is removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetKind {
+    /// A generated file
+    File,
+    /// A directory removed with everything in it: `out` with `all`,
+    /// `__pycache__` directories and the `state_dir`
+    Tree,
+    /// A directory left empty once the files before it are removed
+    EmptyDir,
+}
+
+/// Everything [`clean_with`] removes, in removal order.
+pub fn clean_targets(cfg: &AppConfig, all: bool) -> Result<Vec<CleanTarget>> {
+    let mut targets = Vec::new();
+    let basenames = match cache::saved_fds(&cfg.state_dir) {
+        Some(bytes) if !all => Some(generated_basenames(cfg, &bytes)?),
+        _ => None,
+    };
+    let py_typed = cfg.postprocess.py_typed != PyTyped::Off;
+    for root in cfg.output_roots().into_iter().filter(|r| r.exists()) {
+        if all {
+            targets.push(CleanTarget {
+                path: root,
+                kind: TargetKind::Tree,
+            });
+        } else {
+            targets.extend(generated_targets(&root, basenames.as_ref(), py_typed)?);
+        }
+    }
+    if cfg.state_dir.exists() {
+        targets.push(CleanTarget {
+            path: cfg.state_dir.clone(),
+            kind: TargetKind::Tree,
+        });
+    }
+    Ok(targets)
+}
+
+/// Module basenames the saved FDS generates, after `module_transform`.
+fn generated_basenames(cfg: &AppConfig, fds: &[u8]) -> Result<HashSet<String>> {
+    let names = collect_generated_basenames_from_bytes(fds)
+        .context("collect basenames from saved FDS failed")?;
+    Ok(match &cfg.postprocess.module_transform {
+        Some(transform) => names.iter().map(|b| transform.apply(b)).collect(),
+        None => names,
+    })
+}
+
+/// Generated files under `root`, then the directories they leave empty.
+///
+/// Directories that were already empty are kept, except `root` itself.
+///
+/// A `.py`/`.pyi` file counts as generated if its module name is in
+/// `basenames`, or, without a saved FDS, ends in `_pb2` or `_pb2_grpc`.
+/// Empty `py.typed` markers count when the config writes them.
+pub(crate) fn generated_targets(
+    root: &Path,
+    basenames: Option<&HashSet<String>>,
+    py_typed: bool,
+) -> Result<Vec<CleanTarget>> {
+    let is_generated = |name: &str| {
+        let Some(stem) = name
+            .strip_suffix(".pyi")
+            .or_else(|| name.strip_suffix(".py"))
+        else {
+            return false;
+        };
+        if stem == "__init__" {
+            return false;
+        }
+        match basenames {
+            Some(names) => names.contains(stem),
+            None => stem.ends_with("_pb2") || stem.ends_with("_pb2_grpc"),
+        }
+    };
+
+    let mut targets = Vec::new();
+    let mut removed: HashSet<PathBuf> = HashSet::new();
+    let mut dirs = Vec::new();
+    let mut walker = WalkDir::new(root).into_iter();
+    while let Some(entry) = walker.next() {
+        let entry = entry.with_context(|| format!("failed to walk {}", root.display()))?;
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy();
+        if entry.file_type().is_dir() {
+            if name == "__pycache__" {
+                targets.push(CleanTarget {
+                    path: path.to_path_buf(),
+                    kind: TargetKind::Tree,
+                });
+                removed.insert(path.to_path_buf());
+                walker.skip_current_dir();
+            } else {
+                dirs.push(path.to_path_buf());
+            }
+            continue;
+        }
+        // Keep `__init__.py` files with anything the tool did not write
+        let remove = is_generated(&name)
+            || (name == "__init__.py"
+                && fs::read_to_string(path).is_ok_and(|c| is_generated_init(&c)))
+            || (py_typed && name == "py.typed" && entry.metadata().is_ok_and(|m| m.len() == 0));
+        if remove {
+            targets.push(CleanTarget {
+                path: path.to_path_buf(),
+                kind: TargetKind::File,
+            });
+            removed.insert(path.to_path_buf());
+        }
+    }
+
+    // Deepest first, so parents see their children already gone. Only
+    // directories that held something removed are pruned (and `root` itself),
+    // so empty directories the user created stay
+    for dir in dirs.into_iter().rev() {
+        let entries: Vec<PathBuf> = fs::read_dir(&dir)
+            .with_context(|| format!("failed to read {}", dir.display()))?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<std::io::Result<_>>()
+            .with_context(|| format!("failed to read {}", dir.display()))?;
+        let empty = entries.iter().all(|e| removed.contains(e));
+        let held_target = dir == root || !entries.is_empty();
+        if empty && held_target {
+            removed.insert(dir.clone());
+            targets.push(CleanTarget {
+                path: dir,
+                kind: TargetKind::EmptyDir,
+            });
+        }
+    }
+    Ok(targets)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -71,7 +287,7 @@ mod tests {
         let mut file = fs::File::create(&config_file)?;
         writeln!(file, "[tool.python_proto_importer]")?;
         writeln!(file, "out = \"{}\"", out_dir)?;
-        writeln!(file, "proto_path = [\"proto\"]")?;
+        writeln!(file, "include = [\"proto\"]")?;
         writeln!(file, "python_exe = \"python3\"")?;
         Ok(config_file.to_string_lossy().to_string())
     }
@@ -85,7 +301,7 @@ mod tests {
         let config_file =
             create_test_config_file(temp_dir.path(), &out_dir.to_string_lossy()).unwrap();
 
-        let result = clean(Some(&config_file), false);
+        let result = clean(Some(&config_file), false, false);
 
         assert!(result.is_err());
         assert!(
@@ -106,7 +322,7 @@ mod tests {
         let config_file =
             create_test_config_file(temp_dir.path(), &out_dir.to_string_lossy()).unwrap();
 
-        let result = clean(Some(&config_file), true);
+        let result = clean(Some(&config_file), true, false);
 
         assert!(result.is_ok());
         assert!(!out_dir.exists()); // Directory should be removed
@@ -120,7 +336,7 @@ mod tests {
         let config_file =
             create_test_config_file(temp_dir.path(), &out_dir.to_string_lossy()).unwrap();
 
-        let result = clean(Some(&config_file), true);
+        let result = clean(Some(&config_file), true, false);
 
         // Should succeed even if directory doesn't exist
         assert!(result.is_ok());
@@ -141,9 +357,141 @@ mod tests {
         let config_file =
             create_test_config_file(temp_dir.path(), &out_dir.to_string_lossy()).unwrap();
 
-        let result = clean(Some(&config_file), true);
+        let result = clean(Some(&config_file), true, true);
 
         assert!(result.is_ok());
         assert!(!out_dir.exists()); // Directory and all contents should be removed
     }
+
+    #[test]
+    fn test_clean_keeps_hand_written_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let out_dir = temp_dir.path().join("output");
+        let api = out_dir.join("api");
+        let gen_only = out_dir.join("gen_only");
+        let user_dir = out_dir.join("fixtures/empty");
+        fs::create_dir_all(api.join("__pycache__")).unwrap();
+        fs::create_dir_all(&gen_only).unwrap();
+        fs::create_dir_all(&user_dir).unwrap();
+        for f in ["a_pb2.py", "a_pb2.pyi", "a_pb2_grpc.py", "__init__.py"] {
+            fs::write(api.join(f), "").unwrap();
+        }
+        fs::write(api.join("__pycache__/a_pb2.cpython-312.pyc"), "").unwrap();
+        fs::write(api.join("helpers.py"), "# hand-written\n").unwrap();
+        fs::write(api.join("py.typed"), "").unwrap();
+        fs::write(out_dir.join("__init__.py"), "from .api import helpers\n").unwrap();
+        fs::write(gen_only.join("b_pb2.py"), "").unwrap();
+        fs::write(gen_only.join("__init__.py"), "").unwrap();
+
+        let config_file =
+            create_test_config_file(temp_dir.path(), &out_dir.to_string_lossy()).unwrap();
+
+        clean(Some(&config_file), true, false).unwrap();
+
+        assert!(api.join("helpers.py").exists());
+        assert!(api.join("py.typed").exists());
+        assert!(out_dir.join("__init__.py").exists());
+        for f in [
+            "a_pb2.py",
+            "a_pb2.pyi",
+            "a_pb2_grpc.py",
+            "__init__.py",
+            "__pycache__",
+        ] {
+            assert!(!api.join(f).exists(), "{f} should be removed");
+        }
+        // Directories holding only generated files are pruned
+        assert!(!gen_only.exists());
+        assert!(user_dir.is_dir());
+    }
+
+    #[test]
+    fn test_clean_uses_saved_descriptor_set() {
+        use prost::Message;
+        use prost_types::{FileDescriptorProto, FileDescriptorSet};
+
+        let temp_dir = TempDir::new().unwrap();
+        let out_dir = temp_dir.path().join("output");
+        fs::create_dir(&out_dir).unwrap();
+        fs::write(out_dir.join("a_pb2.py"), "").unwrap();
+        // Looks generated, but no proto in the last build produced it
+        fs::write(out_dir.join("vendored_pb2.py"), "").unwrap();
+
+        let fds = FileDescriptorSet {
+            file: vec![FileDescriptorProto {
+                name: Some("a.proto".into()),
+                ..Default::default()
+            }],
+        };
+        let state_dir = temp_dir.path().join(".proto-importer");
+        cache::write_generation(&state_dir, "fp", &fds.encode_to_vec()).unwrap();
+
+        let config_file =
+            create_test_config_file(temp_dir.path(), &out_dir.to_string_lossy()).unwrap();
+
+        clean(Some(&config_file), true, false).unwrap();
+
+        assert!(!out_dir.join("a_pb2.py").exists());
+        assert!(out_dir.join("vendored_pb2.py").exists());
+        assert!(!state_dir.exists());
+    }
+
+    #[test]
+    fn test_clean_removes_state_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        let out_dir = temp_dir.path().join("output");
+        fs::create_dir(&out_dir).unwrap();
+        // Default state dir sits next to the config file
+        let state_dir = temp_dir.path().join(".proto-importer");
+        fs::create_dir(&state_dir).unwrap();
+        fs::write(state_dir.join("build-cache.json"), "{}").unwrap();
+
+        let config_file =
+            create_test_config_file(temp_dir.path(), &out_dir.to_string_lossy()).unwrap();
+
+        clean(Some(&config_file), true, false).unwrap();
+
+        assert!(!out_dir.exists());
+        assert!(!state_dir.exists());
+    }
+
+    #[test]
+    fn test_clean_dry_run_lists_targets_without_removing() {
+        let temp_dir = TempDir::new().unwrap();
+        let out_dir = temp_dir.path().join("output");
+        fs::create_dir_all(out_dir.join("api")).unwrap();
+        fs::write(out_dir.join("api/a_pb2.py"), "").unwrap();
+        fs::write(out_dir.join("helpers.py"), "").unwrap();
+        let state_dir = temp_dir.path().join(".proto-importer");
+        fs::create_dir(&state_dir).unwrap();
+
+        let config_file =
+            create_test_config_file(temp_dir.path(), &out_dir.to_string_lossy()).unwrap();
+        let cfg = AppConfig::load(Some(Path::new(&config_file))).unwrap();
+
+        let targets = clean_targets(&cfg, false).unwrap();
+        let listed: Vec<(PathBuf, TargetKind)> =
+            targets.iter().map(|t| (t.path.clone(), t.kind)).collect();
+        assert_eq!(
+            listed,
+            vec![
+                (out_dir.join("api/a_pb2.py"), TargetKind::File),
+                (out_dir.join("api"), TargetKind::EmptyDir),
+                (state_dir.clone(), TargetKind::Tree),
+            ]
+        );
+        let json = serde_json::to_value(&targets[1]).unwrap();
+        assert_eq!(json["kind"], "empty_dir");
+
+        assert_eq!(
+            clean_targets(&cfg, true).unwrap()[0],
+            CleanTarget {
+                path: out_dir.clone(),
+                kind: TargetKind::Tree
+            }
+        );
+        clean_dry_run(Some(&config_file), true, true).unwrap();
+        assert!(out_dir.join("api/a_pb2.py").exists());
+        assert!(state_dir.exists());
+    }
 }
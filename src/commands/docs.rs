@@ -0,0 +1,376 @@
+use crate::config::{AppConfig, Backend};
+use crate::generator::buf::BufRunner;
+use crate::generator::protoc::ProtocRunner;
+use anyhow::{Context, Result};
+use prost_reflect::{
+    DescriptorPool, EnumDescriptor, FieldDescriptor, FileDescriptor, Kind, MessageDescriptor,
+    ServiceDescriptor,
+};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+/// Output format of the `docs` command.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DocsFormat {
+    /// Markdown with one table per message, enum and service
+    #[default]
+    #[value(name = "md")]
+    Markdown,
+}
+
+/// Execute the docs command to write a schema reference for the configured protos.
+///
+/// The inputs are compiled into a FileDescriptorSet with source info (no code
+/// is generated and `out` is untouched), and every message, field, enum and
+/// service of the protos found under the `include` paths (for buf, the files
+/// of the buf module) is rendered along with its comments. Imports resolved
+/// from elsewhere, such as the well-known types, are left out.
+///
+/// # Arguments
+///
+/// * `pyproject` - Optional path to the pyproject.toml file
+/// * `format` - Output format
+/// * `out` - File to write the reference to; parent directories are created
+///
+/// # Example
+///
+/// ```no_run
+/// use python_proto_importer::commands::docs::{DocsFormat, docs};
+/// use std::path::Path;
+///
+/// docs(None, DocsFormat::Markdown, Path::new("docs/schema.md"))?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn docs(pyproject: Option<&str>, format: DocsFormat, out: &Path) -> Result<()> {
+    let cfg = AppConfig::load(pyproject.map(Path::new)).context("failed to load config")?;
+    let fds_bytes = match cfg.backend {
+        Backend::Protoc => ProtocRunner::new(&cfg).descriptor_set()?,
+        Backend::Buf => BufRunner::new(&cfg).descriptor_set()?,
+    };
+    let rendered = match format {
+        DocsFormat::Markdown => render_markdown(&fds_bytes, documented(&cfg))?,
+    };
+    if let Some(parent) = out.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).with_context(|| format!("create {}", parent.display()))?;
+    }
+    fs::write(out, rendered).with_context(|| format!("write {}", out.display()))?;
+    tracing::info!("schema reference written: {}", out.display());
+    Ok(())
+}
+
+/// Which files of the descriptor set to document.
+///
+/// protoc names files relative to the include path they were found under, so
+/// the ones found there are the configured protos. buf names them relative to
+/// its module roots instead, and [`BufRunner::descriptor_set`] already leaves
+/// the imports out, so every file is documented.
+fn documented(cfg: &AppConfig) -> impl Fn(&str) -> bool + '_ {
+    move |name| match cfg.backend {
+        Backend::Buf => true,
+        Backend::Protoc => cfg.include.iter().any(|inc| inc.join(name).is_file()),
+    }
+}
+
+/// Render the files of a FileDescriptorSet accepted by `document` as Markdown.
+///
+/// Leading comments from the set's source info become descriptions; files are
+/// ordered by name.
+pub fn render_markdown(fds_bytes: &[u8], document: impl Fn(&str) -> bool) -> Result<String> {
+    let pool = DescriptorPool::decode(fds_bytes).context("failed to decode FileDescriptorSet")?;
+    let mut files: Vec<FileDescriptor> = pool.files().filter(|f| document(f.name())).collect();
+    files.sort_by(|a, b| a.name().cmp(b.name()));
+
+    let mut md = String::from("# Schema Reference\n");
+    for file in files {
+        let comments = Comments::new(&file);
+        write!(md, "\n## `{}`\n", file.name())?;
+        if !file.package_name().is_empty() {
+            write!(md, "\nPackage: `{}`\n", file.package_name())?;
+        }
+        for message in file.messages() {
+            render_message(&mut md, &comments, &message)?;
+        }
+        for enum_ in file.enums() {
+            render_enum(&mut md, &comments, &enum_)?;
+        }
+        for service in file.services() {
+            render_service(&mut md, &comments, &service)?;
+        }
+    }
+    Ok(md)
+}
+
+fn render_message(md: &mut String, comments: &Comments, message: &MessageDescriptor) -> Result<()> {
+    // Map entries are shown as `map<K, V>` field types instead
+    if message.is_map_entry() {
+        return Ok(());
+    }
+    write!(md, "\n### Message `{}`\n\n", message.full_name())?;
+    if let Some(text) = comments.get(message.path()) {
+        write!(md, "{text}\n\n")?;
+    }
+    if message.fields().len() > 0 {
+        md.push_str("| Field | Number | Type | Description |\n|---|---|---|---|\n");
+        for field in message.fields() {
+            writeln!(
+                md,
+                "| `{}` | {} | `{}` | {} |",
+                field.name(),
+                field.number(),
+                field_type(&field),
+                comments.cell(field.path())
+            )?;
+        }
+    }
+    for nested in message.child_messages() {
+        render_message(md, comments, &nested)?;
+    }
+    for enum_ in message.child_enums() {
+        render_enum(md, comments, &enum_)?;
+    }
+    Ok(())
+}
+
+fn render_enum(md: &mut String, comments: &Comments, enum_: &EnumDescriptor) -> Result<()> {
+    write!(md, "\n### Enum `{}`\n\n", enum_.full_name())?;
+    if let Some(text) = comments.get(enum_.path()) {
+        write!(md, "{text}\n\n")?;
+    }
+    md.push_str("| Value | Number | Description |\n|---|---|---|\n");
+    for value in enum_.values() {
+        writeln!(
+            md,
+            "| `{}` | {} | {} |",
+            value.name(),
+            value.number(),
+            comments.cell(value.path())
+        )?;
+    }
+    Ok(())
+}
+
+fn render_service(md: &mut String, comments: &Comments, service: &ServiceDescriptor) -> Result<()> {
+    write!(md, "\n### Service `{}`\n\n", service.full_name())?;
+    if let Some(text) = comments.get(service.path()) {
+        write!(md, "{text}\n\n")?;
+    }
+    md.push_str("| Method | Request | Response | Description |\n|---|---|---|---|\n");
+    for method in service.methods() {
+        let stream = |streaming: bool| if streaming { "stream " } else { "" };
+        writeln!(
+            md,
+            "| `{}` | `{}{}` | `{}{}` | {} |",
+            method.name(),
+            stream(method.is_client_streaming()),
+            method.input().full_name(),
+            stream(method.is_server_streaming()),
+            method.output().full_name(),
+            comments.cell(method.path())
+        )?;
+    }
+    Ok(())
+}
+
+/// Proto type of a field as written in the schema, e.g. `repeated string`.
+fn field_type(field: &FieldDescriptor) -> String {
+    if field.is_map()
+        && let Kind::Message(entry) = field.kind()
+    {
+        return format!(
+            "map<{}, {}>",
+            kind_name(&entry.map_entry_key_field().kind()),
+            kind_name(&entry.map_entry_value_field().kind())
+        );
+    }
+    let name = kind_name(&field.kind());
+    if field.is_list() {
+        format!("repeated {name}")
+    } else {
+        name
+    }
+}
+
+fn kind_name(kind: &Kind) -> String {
+    match kind {
+        Kind::Message(m) => m.full_name().to_string(),
+        Kind::Enum(e) => e.full_name().to_string(),
+        // Scalar variants are the proto keywords in CamelCase, e.g. `Uint64`
+        scalar => format!("{:?}", scalar).to_lowercase(),
+    }
+}
+
+/// Leading comments of a file, keyed by source info path.
+struct Comments(HashMap<Vec<i32>, String>);
+
+impl Comments {
+    fn new(file: &FileDescriptor) -> Self {
+        let locations = file
+            .file_descriptor_proto()
+            .source_code_info
+            .iter()
+            .flat_map(|info| &info.location);
+        Self(
+            locations
+                .filter_map(|loc| {
+                    let text = loc.leading_comments.as_deref()?;
+                    let text = text
+                        .lines()
+                        .map(str::trim)
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                        .trim()
+                        .to_string();
+                    (!text.is_empty()).then(|| (loc.path.clone(), text))
+                })
+                .collect(),
+        )
+    }
+
+    fn get(&self, path: &[i32]) -> Option<&str> {
+        self.0.get(path).map(String::as_str)
+    }
+
+    /// The comment flattened onto one line for a table cell.
+    fn cell(&self, path: &[i32]) -> String {
+        self.get(path)
+            .map(|text| text.replace('\n', " ").replace('|', "\\|"))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::Message;
+    use prost_types::field_descriptor_proto::{Label, Type};
+    use prost_types::source_code_info::Location;
+    use prost_types::{
+        DescriptorProto, EnumDescriptorProto, EnumValueDescriptorProto, FieldDescriptorProto,
+        FileDescriptorProto, FileDescriptorSet, MethodDescriptorProto, ServiceDescriptorProto,
+        SourceCodeInfo,
+    };
+
+    /// Descriptors protoc produces for:
+    ///
+    /// ```proto
+    /// syntax = "proto3";
+    /// package shop.v1;
+    /// // A customer order.
+    /// message Order {
+    ///   // Order identifier, unique per shop.
+    ///   string id = 1;
+    ///   repeated Status history = 2;
+    /// }
+    /// enum Status {
+    ///   // Not yet paid.
+    ///   PENDING = 0;
+    /// }
+    /// service Orders {
+    ///   // Look up one order.
+    ///   rpc Get(Order) returns (Order);
+    /// }
+    /// ```
+    fn shop_fds() -> Vec<u8> {
+        let named = |n: &str| Some(n.to_string());
+        let comment = |path: Vec<i32>, text: &str| Location {
+            path,
+            leading_comments: Some(text.to_string()),
+            ..Default::default()
+        };
+        FileDescriptorSet {
+            file: vec![FileDescriptorProto {
+                name: named("shop/v1/order.proto"),
+                package: named("shop.v1"),
+                syntax: named("proto3"),
+                message_type: vec![DescriptorProto {
+                    name: named("Order"),
+                    field: vec![
+                        FieldDescriptorProto {
+                            name: named("id"),
+                            number: Some(1),
+                            label: Some(Label::Optional as i32),
+                            r#type: Some(Type::String as i32),
+                            json_name: named("id"),
+                            ..Default::default()
+                        },
+                        FieldDescriptorProto {
+                            name: named("history"),
+                            number: Some(2),
+                            label: Some(Label::Repeated as i32),
+                            r#type: Some(Type::Enum as i32),
+                            type_name: named(".shop.v1.Status"),
+                            json_name: named("history"),
+                            ..Default::default()
+                        },
+                    ],
+                    ..Default::default()
+                }],
+                enum_type: vec![EnumDescriptorProto {
+                    name: named("Status"),
+                    value: vec![EnumValueDescriptorProto {
+                        name: named("PENDING"),
+                        number: Some(0),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                service: vec![ServiceDescriptorProto {
+                    name: named("Orders"),
+                    method: vec![MethodDescriptorProto {
+                        name: named("Get"),
+                        input_type: named(".shop.v1.Order"),
+                        output_type: named(".shop.v1.Order"),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                source_code_info: Some(SourceCodeInfo {
+                    location: vec![
+                        comment(vec![4, 0], " A customer order.\n"),
+                        comment(vec![4, 0, 2, 0], " Order identifier, unique per shop.\n"),
+                        comment(vec![5, 0, 2, 0], " Not yet paid.\n"),
+                        comment(vec![6, 0, 2, 0], " Look up one order.\n"),
+                    ],
+                }),
+                ..Default::default()
+            }],
+        }
+        .encode_to_vec()
+    }
+
+    #[test]
+    fn markdown_describes_fields_with_comments() {
+        let md = render_markdown(&shop_fds(), |_| true).unwrap();
+        assert!(md.contains("## `shop/v1/order.proto`\n\nPackage: `shop.v1`"));
+        assert!(md.contains("### Message `shop.v1.Order`\n\nA customer order.\n"));
+        assert!(md.contains("| `id` | 1 | `string` | Order identifier, unique per shop. |"));
+        assert!(md.contains("| `history` | 2 | `repeated shop.v1.Status` |  |"));
+        assert!(md.contains("| `PENDING` | 0 | Not yet paid. |"));
+        assert!(md.contains("| `Get` | `shop.v1.Order` | `shop.v1.Order` | Look up one order. |"));
+
+        let md = render_markdown(&shop_fds(), |name| name != "shop/v1/order.proto").unwrap();
+        assert_eq!(md, "# Schema Reference\n");
+    }
+
+    #[test]
+    fn buf_files_are_documented_regardless_of_include() {
+        let dir = tempfile::tempdir().unwrap();
+        // A buf module rooted at proto/, with the default include of "."
+        std::fs::create_dir_all(dir.path().join("proto/shop/v1")).unwrap();
+        std::fs::write(dir.path().join("proto/shop/v1/order.proto"), "").unwrap();
+        let config = dir.path().join("pyproject.toml");
+        std::fs::write(&config, "[tool.python_proto_importer]\nbackend = \"buf\"\n").unwrap();
+        let mut cfg = AppConfig::load(Some(&config)).unwrap();
+        cfg.include = vec![dir.path().to_path_buf()];
+
+        let md = render_markdown(&shop_fds(), documented(&cfg)).unwrap();
+        assert!(md.contains("## `shop/v1/order.proto`"));
+
+        // protoc would have named it relative to an include path
+        cfg.backend = Backend::Protoc;
+        let md = render_markdown(&shop_fds(), documented(&cfg)).unwrap();
+        assert_eq!(md, "# Schema Reference\n");
+    }
+}
@@ -1,7 +1,16 @@
 pub mod build;
+pub mod changed;
 pub mod check;
 pub mod clean;
+pub mod docs;
+pub mod rewrite;
+pub mod stale;
+pub mod watch;
 
-pub use build::build;
-pub use check::check;
-pub use clean::clean;
+pub use build::{BuildOptions, BuildReport, PassSummary, build, build_with};
+pub use changed::build_changed;
+pub use check::{check, check_with};
+pub use clean::{clean, clean_with};
+pub use docs::docs;
+pub use rewrite::rewrite;
+pub use watch::watch;
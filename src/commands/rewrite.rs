@@ -0,0 +1,84 @@
+use crate::config::{AppConfig, VerifyProfile};
+use crate::postprocess::apply::rewrite_user_files;
+use crate::verification::determine_package_structure;
+use anyhow::{Context, Result, bail};
+use std::path::Path;
+
+/// Execute the rewrite command to update user files that import generated modules.
+///
+/// Applies the same import rewriting used on the generated tree to scripts and
+/// notebooks elsewhere in the project, so `import api.foo_pb2` becomes an
+/// absolute import through the package the output directory is importable as.
+///
+/// # Arguments
+///
+/// * `pyproject` - Optional path to the pyproject.toml file. If None, uses "pyproject.toml"
+/// * `path` - File or directory of user `.py`/`.ipynb` files to rewrite
+/// * `package` - Dotted package `out` is imported as. Defaults to `verify.top_package`
+///   for the installed verify profile, otherwise to the package detected from `out`
+/// * `dry_run` - If true, log diffs instead of writing files
+///
+/// # Example
+///
+/// ```no_run
+/// use python_proto_importer::commands::rewrite;
+/// use std::path::Path;
+///
+/// rewrite(None, Path::new("notebooks"), None, false)?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn rewrite(
+    pyproject: Option<&str>,
+    path: &Path,
+    package: Option<&str>,
+    dry_run: bool,
+) -> Result<()> {
+    let cfg = AppConfig::load(pyproject.map(Path::new)).context("failed to load config")?;
+    if !cfg.out.exists() {
+        bail!(
+            "output directory does not exist: {} (run 'build' first)",
+            cfg.out.display()
+        );
+    }
+    if !path.exists() {
+        bail!("rewrite path not found: {}", path.display());
+    }
+
+    let package = match package {
+        Some(p) => p.to_string(),
+        None => default_package(&cfg)?,
+    };
+    tracing::info!(
+        "rewriting imports under {} against {} as package '{}'",
+        path.display(),
+        cfg.out.display(),
+        package
+    );
+    let modified = rewrite_user_files(
+        path,
+        &cfg.out,
+        &package,
+        cfg.postprocess.exclude_google,
+        dry_run,
+    )?;
+    if dry_run {
+        tracing::info!("dry-run: {} files would be modified", modified);
+    } else {
+        tracing::info!("rewrote imports in {} files", modified);
+    }
+    Ok(())
+}
+
+/// The dotted name user code imports `out` through, mirroring verification.
+fn default_package(cfg: &AppConfig) -> Result<String> {
+    if let Some(VerifyProfile::Installed { top_package }) = cfg.verify.as_ref().map(|v| &v.profile)
+    {
+        return Ok(top_package.clone());
+    }
+    if cfg.postprocess.out_is_import_root() {
+        return Ok(String::new());
+    }
+    let out_abs = cfg.out.canonicalize().unwrap_or_else(|_| cfg.out.clone());
+    let (_, package_name) = determine_package_structure(&out_abs)?;
+    Ok(package_name)
+}
@@ -0,0 +1,117 @@
+use crate::commands::clean::{TargetKind, generated_targets};
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// How a committed file differs from a fresh build.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Staleness {
+    /// The fresh build writes different content
+    Changed,
+    /// The fresh build writes the file but it is not committed
+    Missing,
+    /// A generated file the fresh build no longer writes, e.g. after a proto was removed
+    Unexpected,
+}
+
+/// A file under a committed output root that is out of date.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaleFile {
+    /// Path under the committed root
+    pub path: PathBuf,
+    /// How it differs from the fresh build
+    pub kind: Staleness,
+}
+
+/// Compare the fully postprocessed `fresh` tree against the `committed` one.
+///
+/// Every file of `fresh` must exist in `committed` with the same content.
+/// Files only in `committed` are reported when `clean` would treat them as
+/// generated (with `py_typed` markers counting when the config writes them),
+/// so hand-written modules next to the generated ones are left alone.
+///
+/// # Returns
+///
+/// The stale files ordered by path.
+pub(crate) fn stale_files(
+    fresh: &Path,
+    committed: &Path,
+    py_typed: bool,
+) -> Result<Vec<StaleFile>> {
+    let mut stale = Vec::new();
+    for entry in WalkDir::new(fresh).into_iter() {
+        let entry = entry.with_context(|| format!("failed to walk {}", fresh.display()))?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let rel = entry.path().strip_prefix(fresh).unwrap_or(entry.path());
+        let path = committed.join(rel);
+        let expected =
+            fs::read(entry.path()).with_context(|| format!("read {}", entry.path().display()))?;
+        let kind = match fs::read(&path) {
+            Ok(actual) if actual == expected => continue,
+            Ok(_) => Staleness::Changed,
+            Err(_) => Staleness::Missing,
+        };
+        stale.push(StaleFile { path, kind });
+    }
+    if committed.exists() {
+        for target in generated_targets(committed, None, py_typed)? {
+            if target.kind != TargetKind::File {
+                continue;
+            }
+            let rel = target.path.strip_prefix(committed).unwrap_or(&target.path);
+            if !fresh.join(rel).exists() {
+                stale.push(StaleFile {
+                    path: target.path,
+                    kind: Staleness::Unexpected,
+                });
+            }
+        }
+    }
+    stale.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(stale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn reports_changed_missing_and_leftover_generated_files() {
+        let dir = tempdir().unwrap();
+        let fresh = dir.path().join("fresh");
+        let committed = dir.path().join("committed");
+        for root in [&fresh, &committed] {
+            fs::create_dir_all(root.join("api")).unwrap();
+            fs::write(root.join("api/__init__.py"), "").unwrap();
+            fs::write(root.join("api/same_pb2.py"), "x = 1\n").unwrap();
+        }
+        fs::write(fresh.join("api/orders_pb2.py"), "from . import same_pb2\n").unwrap();
+        fs::write(committed.join("api/orders_pb2.py"), "import same_pb2\n").unwrap();
+        fs::write(fresh.join("api/new_pb2.py"), "").unwrap();
+        fs::write(committed.join("api/removed_pb2.py"), "").unwrap();
+        fs::write(committed.join("api/helpers.py"), "").unwrap();
+
+        let stale = stale_files(&fresh, &committed, false).unwrap();
+        assert_eq!(
+            stale,
+            vec![
+                StaleFile {
+                    path: committed.join("api/new_pb2.py"),
+                    kind: Staleness::Missing,
+                },
+                StaleFile {
+                    path: committed.join("api/orders_pb2.py"),
+                    kind: Staleness::Changed,
+                },
+                StaleFile {
+                    path: committed.join("api/removed_pb2.py"),
+                    kind: Staleness::Unexpected,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,294 @@
+use crate::commands::build::{BuildOptions, BuildReport, build_with};
+use crate::config::AppConfig;
+use anyhow::{Context, Result};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use walkdir::WalkDir;
+
+/// How long proto changes must stay quiet before a rebuild starts.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Rebuild whenever a `.proto` file under the configured `include` paths changes.
+///
+/// Runs an initial build, then watches the include directories (rather than
+/// the initially matched files, so newly created protos are picked up) and
+/// re-runs [`build_with`] once changes have settled for [`DEBOUNCE`]. Hidden
+/// directories, the output roots and `state_dir` are not watched. A failed
+/// rebuild is reported and the loop keeps running until the process is
+/// interrupted (Ctrl-C).
+///
+/// # Arguments
+///
+/// * `pyproject` - Optional path to the pyproject.toml file
+/// * `no_verify` - Skip verification on every rebuild to keep the loop fast
+pub fn watch(pyproject: Option<&str>, no_verify: bool) -> Result<()> {
+    let cfg = AppConfig::load(pyproject.map(Path::new)).context("failed to load config")?;
+    let options = BuildOptions {
+        no_verify,
+        ..Default::default()
+    };
+    rebuild(&cfg, &options);
+
+    // Created after the first build, so the output roots exist and can be excluded
+    let mut ignored = cfg.output_roots();
+    ignored.push(cfg.state_dir.clone());
+    let mut watcher = ProtoWatcher::new(&cfg.include, &ignored)?;
+    tracing::info!(
+        "watching {} for .proto changes (Ctrl-C to stop)",
+        cfg.include
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    loop {
+        let mut changed = watcher.changes(None)?;
+        if changed.is_empty() {
+            continue;
+        }
+        // Debounce: editors often write a file in several steps
+        loop {
+            let more = watcher.changes(Some(DEBOUNCE))?;
+            if more.is_empty() {
+                break;
+            }
+            changed.extend(more);
+        }
+        for path in &changed {
+            tracing::debug!("changed: {}", path.display());
+        }
+        tracing::info!("{} proto files changed; rebuilding", changed.len());
+        rebuild(&cfg, &options);
+    }
+}
+
+/// Run one build and log a one-line summary; errors do not stop the watch loop.
+fn rebuild(cfg: &AppConfig, options: &BuildOptions) {
+    let started = Instant::now();
+    // Touching a proto without changing it hits the build cache
+    match build_with(cfg, options) {
+        Ok(report) => tracing::info!("{}", summarize(&report, started.elapsed())),
+        Err(e) => tracing::error!(
+            "rebuild failed after {:.1}s: {:#}",
+            started.elapsed().as_secs_f64(),
+            e
+        ),
+    }
+}
+
+fn summarize(report: &BuildReport, elapsed: Duration) -> String {
+    if report.up_to_date {
+        return format!(
+            "up to date ({:.1}s): {} modules verified",
+            elapsed.as_secs_f64(),
+            report.modules_verified
+        );
+    }
+    format!(
+        "rebuilt in {:.1}s: {} __init__.py created, {} files rewritten, {} modules verified",
+        elapsed.as_secs_f64(),
+        report.packages_created,
+        report.files_rewritten,
+        report.modules_verified
+    )
+}
+
+/// Reports added, modified and removed `.proto` files under a set of roots.
+///
+/// Every directory under the roots gets a non-recursive watch, except hidden
+/// ones (`.git`, `.venv`, ...) and the `ignored` trees, so their contents
+/// never generate events. Directories created later are watched as they
+/// appear.
+pub struct ProtoWatcher {
+    watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    ignored: Vec<PathBuf>,
+    watched: HashSet<PathBuf>,
+}
+
+impl ProtoWatcher {
+    pub fn new(roots: &[PathBuf], ignored: &[PathBuf]) -> Result<Self> {
+        let (tx, events) = mpsc::channel();
+        let watcher = notify::recommended_watcher(tx).context("failed to start file watcher")?;
+        let mut this = Self {
+            watcher,
+            events,
+            ignored: ignored.iter().map(|p| absolute(p)).collect(),
+            watched: HashSet::new(),
+        };
+        for root in roots {
+            this.watch_tree(&absolute(root))?;
+        }
+        Ok(this)
+    }
+
+    /// Wait up to `timeout` (forever when None) for file events and return
+    /// the protos they touch; empty when none arrived or none were protos.
+    pub fn changes(&mut self, timeout: Option<Duration>) -> Result<BTreeSet<PathBuf>> {
+        let first = match timeout {
+            None => self
+                .events
+                .recv()
+                .map_err(|_| RecvTimeoutError::Disconnected),
+            Some(timeout) => self.events.recv_timeout(timeout),
+        };
+        let first = match first {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => return Ok(BTreeSet::new()),
+            Err(RecvTimeoutError::Disconnected) => anyhow::bail!("file watcher stopped"),
+        };
+        let pending: Vec<_> = std::iter::once(first)
+            .chain(self.events.try_iter())
+            .collect();
+        let mut changed = BTreeSet::new();
+        for event in pending {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    tracing::warn!("file watcher error: {}", e);
+                    continue;
+                }
+            };
+            if matches!(event.kind, EventKind::Access(_)) {
+                continue;
+            }
+            for path in event.paths {
+                if path.is_dir() {
+                    // Protos may have been written before the watch was added
+                    changed.extend(self.watch_tree(&path)?);
+                } else if path.extension().is_some_and(|ext| ext == "proto")
+                    && !self.is_ignored(&path)
+                {
+                    changed.insert(path);
+                }
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Watch `root` and the directories under it that are not skipped.
+    ///
+    /// # Returns
+    ///
+    /// The protos already present in the newly watched directories.
+    fn watch_tree(&mut self, root: &Path) -> Result<Vec<PathBuf>> {
+        let dirs: Vec<PathBuf> = WalkDir::new(root)
+            .into_iter()
+            .filter_entry(|e| {
+                e.file_type().is_dir()
+                    && (e.depth() == 0 || !is_hidden(e.path()))
+                    && !self.is_ignored(e.path())
+            })
+            .filter_map(Result::ok)
+            .map(|e| e.into_path())
+            .collect();
+        let mut protos = Vec::new();
+        for dir in dirs {
+            if !self.watched.insert(dir.clone()) {
+                continue;
+            }
+            self.watcher
+                .watch(&dir, RecursiveMode::NonRecursive)
+                .with_context(|| format!("failed to watch {}", dir.display()))?;
+            let entries = std::fs::read_dir(&dir).into_iter().flatten().flatten();
+            protos.extend(
+                entries
+                    .map(|e| e.path())
+                    .filter(|p| p.is_file() && p.extension().is_some_and(|ext| ext == "proto")),
+            );
+        }
+        Ok(protos)
+    }
+
+    fn is_ignored(&self, path: &Path) -> bool {
+        self.ignored.iter().any(|ignored| path.starts_with(ignored))
+    }
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n.starts_with('.'))
+}
+
+/// `path` resolved like the paths notify reports, for prefix checks.
+fn absolute(path: &Path) -> PathBuf {
+    path.canonicalize()
+        .or_else(|_| std::path::absolute(path))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    /// Collect changes until none arrive for a while.
+    fn settle(watcher: &mut ProtoWatcher) -> Vec<PathBuf> {
+        let mut changed = watcher.changes(Some(Duration::from_secs(2))).unwrap();
+        loop {
+            let more = watcher.changes(Some(Duration::from_millis(300))).unwrap();
+            if more.is_empty() {
+                return changed.into_iter().collect();
+            }
+            changed.extend(more);
+        }
+    }
+
+    #[test]
+    fn reports_proto_changes_outside_hidden_and_ignored_dirs() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join("proto")).unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::create_dir_all(root.join("generated")).unwrap();
+        fs::write(root.join("proto/a.proto"), "syntax = \"proto3\";\n").unwrap();
+
+        let mut watcher =
+            ProtoWatcher::new(std::slice::from_ref(&root), &[root.join("generated")]).unwrap();
+        assert!(
+            watcher
+                .changes(Some(Duration::from_millis(100)))
+                .unwrap()
+                .is_empty()
+        );
+
+        // New directories are picked up; non-protos and skipped trees are not
+        fs::write(root.join(".git/x.proto"), "").unwrap();
+        fs::write(root.join("generated/y.proto"), "").unwrap();
+        fs::write(root.join("proto/notes.txt"), "x").unwrap();
+        fs::create_dir_all(root.join("proto/api")).unwrap();
+        fs::write(root.join("proto/api/b.proto"), "syntax = \"proto3\";\n").unwrap();
+        assert_eq!(settle(&mut watcher), vec![root.join("proto/api/b.proto")]);
+
+        fs::write(
+            root.join("proto/a.proto"),
+            "syntax = \"proto3\";\npackage a;\n",
+        )
+        .unwrap();
+        fs::remove_file(root.join("proto/api/b.proto")).unwrap();
+        assert_eq!(
+            settle(&mut watcher),
+            vec![root.join("proto/a.proto"), root.join("proto/api/b.proto")]
+        );
+    }
+
+    #[test]
+    fn summary_reports_counts() {
+        let report = BuildReport {
+            packages_created: 2,
+            files_rewritten: 5,
+            modules_verified: 7,
+            ..Default::default()
+        };
+        assert_eq!(
+            summarize(&report, Duration::from_millis(1300)),
+            "rebuilt in 1.3s: 2 __init__.py created, 5 files rewritten, 7 modules verified"
+        );
+    }
+}
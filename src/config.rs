@@ -1,7 +1,14 @@
+use crate::postprocess::exports::DEFAULT_ALL_EXCLUDE;
+use crate::utils::normalize;
 use anyhow::{Context, Result, bail};
+use regex::Regex;
 use serde::Deserialize;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Default state directory name, created next to the config file.
+pub const DEFAULT_STATE_DIR: &str = ".proto-importer";
 
 /// Code generation backend selection.
 ///
@@ -12,7 +19,7 @@ pub enum Backend {
     /// This is the currently supported and default backend.
     Protoc,
     /// Use buf generate for code generation.
-    /// This backend is planned for future versions but not yet implemented.
+    /// Requires `buf_gen_yaml` and the `buf` CLI on PATH (or at `BUF`).
     Buf,
 }
 
@@ -34,16 +41,72 @@ pub struct AppConfig {
     /// Glob patterns for proto files to compile.
     /// Only files matching these patterns will be processed.
     pub inputs: Vec<String>,
+    /// Directory relative `inputs` are matched from: the config file's
+    /// directory when it was found above the cwd, otherwise empty (the cwd).
+    pub config_dir: PathBuf,
     /// Output directory for generated Python files.
     pub out: PathBuf,
+    /// Output directory for `grpc_python` modules when they should not go to
+    /// `out`.
+    pub grpc_out: Option<PathBuf>,
+    /// Output directory for the `mypy`/`mypy_grpc` stubs when they should not
+    /// go to `out`, e.g. to ship stubs in a separate distribution.
+    pub mypy_out: Option<PathBuf>,
     /// Whether to generate mypy type stubs (.pyi files) using mypy-protobuf.
     pub generate_mypy: bool,
     /// Whether to generate gRPC mypy stubs (_grpc.pyi files) using mypy-grpc.
     pub generate_mypy_grpc: bool,
+    /// Additional protoc plugins from `[[tool.python_proto_importer.plugin]]`.
+    /// See [`AppConfig::protoc_plugins`] for the full list protoc runs.
+    pub plugins: Vec<Plugin>,
+    /// Standalone protoc binary to run instead of `python -m grpc_tools.protoc`.
+    /// When None, grpc_tools from `python_exe` is used. Unless this is a path,
+    /// the `PROTOC` environment variable overrides it when the compiler is run.
+    pub protoc_path: Option<PathBuf>,
+    /// Extra arguments appended verbatim to the protoc command line, after the
+    /// output flags and before the input files.
+    pub extra_protoc_args: Vec<String>,
+    /// Kill protoc if generation takes longer than this many seconds.
+    /// Falls back to `timeout_secs` when None.
+    pub generation_timeout_secs: Option<u64>,
+    /// Kill any subprocess (protoc, import tests, `mypy_cmd`/`pyright_cmd`)
+    /// running longer than this many seconds. When None, they may run indefinitely.
+    pub timeout_secs: Option<u64>,
+    /// Fail the build when a proto has no `package` declaration instead of
+    /// only warning about it.
+    pub require_package: bool,
+    /// Warn about `include` paths that no compiled proto or dependency was
+    /// resolved from.
+    pub warn_unused_includes: bool,
+    /// Remove the files a failed protoc/buf run left behind in the output
+    /// directories.
+    pub cleanup_on_failure: bool,
+    /// Path to the buf.gen.yaml template used by the buf backend.
+    pub buf_gen_yaml: Option<PathBuf>,
+    /// Directory holding tool state such as the build cache, kept outside `out`
+    /// so the generated tree stays clean. Defaults to `.proto-importer/` next
+    /// to the config file. `clean` deletes it wholesale, so it may not be or
+    /// contain the config file's directory or an output root.
+    pub state_dir: PathBuf,
     /// Post-processing configuration options.
     pub postprocess: PostProcess,
     /// Optional verification configuration (type checking commands).
     pub verify: Option<Verify>,
+    /// Toolchain requirements checked by `doctor`.
+    pub doctor: Doctor,
+}
+
+/// A protoc plugin invocation, rendered as `--<name>_out` and `--<name>_opt`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plugin {
+    /// Plugin name; protoc runs `protoc-gen-<name>` unless it is built in.
+    pub name: String,
+    /// Output directory as configured. [`AppConfig::protoc_plugins`] joins it
+    /// onto `cfg.out`, so relative paths land under `cfg.out` and absolute
+    /// ones are kept.
+    pub out: PathBuf,
+    /// Values passed to the plugin, one `--<name>_opt` flag each.
+    pub opt: Vec<String>,
 }
 
 /// Post-processing configuration options.
@@ -60,6 +123,29 @@ pub struct PostProcess {
     /// Create __init__.py files in all directories to make packages importable.
     /// Set to false for namespace packages (PEP 420).
     pub create_package: bool,
+    /// Also create `__init__.py` at the `out` root itself.
+    /// Set to false when `out` is a PYTHONPATH root holding several top-level packages.
+    pub package_root_init: bool,
+    /// Write `from . import <module>` lines for the generated modules of each
+    /// package into its `__init__.py`. Requires `create_package`.
+    pub init_reexports: bool,
+    /// Treat generated directories as PEP 420 implicit namespace packages:
+    /// no `__init__.py` is written, and verification puts `out` itself on
+    /// PYTHONPATH so packages import by their own names, as they would when
+    /// merged across several installed distributions.
+    pub namespace_packages: bool,
+    /// Write a minimal setuptools `pyproject.toml` into `out` so the generated
+    /// package can be pip-installed directly.
+    pub emit_package_metadata: bool,
+    /// Where to write `py.typed` markers so type checkers use the generated
+    /// stubs and inline types.
+    pub py_typed: PyTyped,
+    /// Write `__all__` into generated modules, listing the public names
+    /// declared by their protos.
+    pub emit_all: bool,
+    /// Glob patterns for names left out of the generated `__all__`.
+    /// Defaults to protobuf internals: `DESCRIPTOR` and `_*`.
+    pub all_exclude: Vec<glob::Pattern>,
     /// Exclude google.protobuf imports from relative import conversion.
     pub exclude_google: bool,
     /// Add Pyright suppression headers to generated _pb2.py and _pb2_grpc.py files.
@@ -67,6 +153,40 @@ pub struct PostProcess {
     /// File suffixes to process during post-processing.
     /// Default includes _pb2.py, _pb2.pyi, _pb2_grpc.py, _pb2_grpc.pyi.
     pub module_suffixes: Vec<String>,
+    /// Optional regex substitution applied to every generated module basename.
+    pub module_transform: Option<ModuleTransform>,
+    /// Glob patterns, relative to `out`, for files that import rewriting leaves
+    /// untouched; `__init__.py` is not created where it would match either.
+    pub exclude: Vec<glob::Pattern>,
+}
+
+impl PostProcess {
+    /// Whether `out` is a PYTHONPATH root holding top-level packages rather
+    /// than a package itself, so its modules import without an `out` prefix.
+    pub fn out_is_import_root(&self) -> bool {
+        self.namespace_packages || (self.create_package && !self.package_root_init)
+    }
+}
+
+/// Regex substitution used to rename generated modules.
+///
+/// Applied to module basenames such as `service_v1_pb2`; files are renamed on
+/// disk and every reference to the old name is updated.
+#[derive(Debug, Clone)]
+pub struct ModuleTransform {
+    /// Pattern matched against the module basename.
+    pub pattern: Regex,
+    /// Replacement string; supports `$1`-style capture group references.
+    pub replacement: String,
+}
+
+impl ModuleTransform {
+    /// Apply the substitution to a module basename.
+    pub fn apply(&self, basename: &str) -> String {
+        self.pattern
+            .replace_all(basename, self.replacement.as_str())
+            .into_owned()
+    }
 }
 
 /// Verification configuration for optional type checking.
@@ -82,6 +202,53 @@ pub struct Verify {
     /// Command to run pyright type checking. If None, pyright verification is skipped.
     /// Example: ["pyright", "generated/**/*.pyi"]
     pub pyright_cmd: Option<Vec<String>>,
+    /// Maximum number of parallel python processes for per-module fallback
+    /// import tests. Defaults to the number of available CPUs.
+    pub jobs: Option<usize>,
+    /// Where the import dry-run imports the generated modules from.
+    pub profile: VerifyProfile,
+    /// Fraction of modules (0.0-1.0) allowed to fail the import dry-run before
+    /// verify fails; failures under it are reported as warnings. None means any
+    /// failure is fatal.
+    pub max_failure_ratio: Option<f64>,
+    /// Import each module in its own process with `open` and
+    /// `threading.Thread.start` instrumented, failing if either is called.
+    pub no_side_effects: bool,
+    /// Also run one generated module as `python -m <package>.<module>`,
+    /// preferring one with a relative import, and fail if that breaks.
+    pub verify_dash_m: bool,
+    /// Working directory of the import check's python processes, for modules
+    /// that read files relative to the cwd at import. None inherits the cwd.
+    pub verify_cwd: Option<PathBuf>,
+}
+
+/// Toolchain requirements from `[tool.python_proto_importer.doctor]`.
+#[derive(Debug, Clone, Default)]
+pub struct Doctor {
+    /// Oldest protoc release `doctor` accepts, e.g. `"3.21"` or `"25.1"`.
+    pub min_protoc: Option<String>,
+}
+
+/// Which packages under `out` get a `py.typed` marker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PyTyped {
+    /// No markers (default unless mypy stubs are generated).
+    Off,
+    /// Only the top-level package: `out` itself, or each package directly
+    /// under it when `out` is an import root.
+    Root,
+    /// Every package directory under `out`.
+    All,
+}
+
+/// Layout used by the import dry-run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyProfile {
+    /// Import straight from `out` via PYTHONPATH (default).
+    Source,
+    /// Copy `out` into a temporary site directory as the dotted `top_package`
+    /// and import from there, as an installed wheel would be.
+    Installed { top_package: String },
 }
 
 // --- Raw TOML structures ---
@@ -93,46 +260,103 @@ struct PyProject {
 #[derive(Deserialize)]
 struct ToolSection {
     #[serde(rename = "python_proto_importer")]
-    python_proto_importer: Option<ImporterRoot>,
-}
-
-#[derive(Deserialize)]
-struct ImporterRoot {
-    #[serde(flatten)]
-    core: ImporterCore,
-    verify: Option<VerifyToml>,
+    python_proto_importer: Option<ImporterCore>,
 }
 
+// `deny_unknown_fields` does not work through `#[serde(flatten)]`, so the
+// importer table (including `verify`) is a single flat struct.
 #[allow(dead_code)]
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 struct ImporterCore {
     backend: Option<String>,
     python_exe: Option<String>,
+    python_exe_candidates: Option<Vec<String>>,
     include: Option<Vec<String>>, // paths/globs
     inputs: Option<Vec<String>>,  // globs
     out: Option<String>,
+    grpc_out: Option<String>,
+    mypy_out: Option<String>,
     mypy: Option<bool>,
     mypy_grpc: Option<bool>,
+    plugin: Option<Vec<PluginToml>>,
+    protoc_path: Option<String>,
+    extra_protoc_args: Option<Vec<String>>,
+    generation_timeout_secs: Option<u64>,
+    timeout_secs: Option<u64>,
+    require_package: Option<bool>,
+    warn_unused_includes: Option<bool>,
+    cleanup_on_failure: Option<bool>,
     buf_gen_yaml: Option<String>,
+    state_dir: Option<String>,
     postprocess: Option<PostProcessToml>,
+    verify: Option<VerifyToml>,
+    doctor: Option<DoctorToml>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PluginToml {
+    name: String,
+    out: Option<String>,
+    opt: Option<Vec<String>>,
 }
 
 #[allow(dead_code)]
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 struct PostProcessToml {
     relative_imports: Option<bool>,
     fix_pyi: Option<bool>,
     create_package: Option<bool>,
+    package_root_init: Option<bool>,
+    init_reexports: Option<bool>,
+    namespace_packages: Option<bool>,
+    emit_package_metadata: Option<bool>,
+    py_typed: Option<PyTypedToml>,
+    emit_all: Option<bool>,
+    all_exclude: Option<Vec<String>>,
     exclude_google: Option<bool>,
     pyright_header: Option<bool>,
     module_suffixes: Option<Vec<String>>,
+    module_transform: Option<ModuleTransformToml>,
+    exclude: Option<Vec<String>>,
+}
+
+/// `py_typed = true | false | "all"`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PyTypedToml {
+    Flag(bool),
+    Mode(String),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ModuleTransformToml {
+    pattern: String,
+    replacement: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DoctorToml {
+    min_protoc: Option<String>,
 }
 
 #[allow(dead_code)]
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 struct VerifyToml {
     mypy_cmd: Option<Vec<String>>,
     pyright_cmd: Option<Vec<String>>,
+    jobs: Option<usize>,
+    profile: Option<String>,
+    top_package: Option<String>,
+    max_failure_ratio: Option<f64>,
+    no_side_effects: Option<bool>,
+    verify_dash_m: Option<bool>,
+    verify_cwd: Option<String>,
 }
 
 impl AppConfig {
@@ -144,7 +368,12 @@ impl AppConfig {
     /// # Arguments
     ///
     /// * `pyproject_path` - Optional path to the pyproject.toml file.
-    ///   If None, looks for "pyproject.toml" in the current directory.
+    ///   If None, walks up from the current directory to the first
+    ///   `pyproject.toml` containing `[tool.python_proto_importer]` (see
+    ///   [`find_pyproject`]). When that file lives in a parent directory,
+    ///   `out`, `include`, `buf_gen_yaml`, `state_dir` and a relative
+    ///   `protoc_path` are resolved relative to it, and `inputs` are matched
+    ///   from it (see [`config_dir`](Self::config_dir)).
     ///
     /// # Returns
     ///
@@ -168,22 +397,57 @@ impl AppConfig {
     /// # Ok::<(), anyhow::Error>(())
     /// ```
     pub fn load(pyproject_path: Option<&Path>) -> Result<Self> {
+        let cwd = std::env::current_dir().context("failed to get current directory")?;
+        Self::load_from(pyproject_path, &cwd)
+    }
+
+    /// [`load`](Self::load) with `cwd` as the directory the config search
+    /// starts from.
+    fn load_from(pyproject_path: Option<&Path>, cwd: &Path) -> Result<Self> {
+        // Base directory for relative paths when the config was found above cwd
+        let mut base_dir: Option<PathBuf> = None;
         let path = match pyproject_path {
             Some(p) => p.to_path_buf(),
-            None => PathBuf::from("pyproject.toml"),
+            None => {
+                let found = find_pyproject(cwd)?;
+                match found.parent() {
+                    Some(dir) if dir != cwd => {
+                        tracing::debug!("using {}", found.display());
+                        base_dir = Some(dir.to_path_buf());
+                        found
+                    }
+                    _ => PathBuf::from("pyproject.toml"),
+                }
+            }
+        };
+        let rebase = |p: PathBuf| match &base_dir {
+            Some(dir) => dir.join(p),
+            None => p,
         };
         let content = fs::read_to_string(&path)
             .with_context(|| format!("failed to read {}", path.display()))?;
-        let root: PyProject = toml::from_str(&content).context("failed to parse pyproject.toml")?;
+        let root: PyProject = toml::Deserializer::parse(&content)
+            .map_err(|e| describe_toml_error(&path, &content, None, e))
+            .and_then(|de| {
+                serde_path_to_error::deserialize(de).map_err(|e| {
+                    let key_path = e.path().to_string();
+                    describe_toml_error(&path, &content, Some(&key_path), e.into_inner())
+                })
+            })?;
         let Some(tool) = root.tool else {
-            bail!("[tool.python_proto_importer] not found");
+            bail!(
+                "[tool.python_proto_importer] not found in {}",
+                path.display()
+            );
         };
         let Some(importer) = tool.python_proto_importer else {
-            bail!("[tool.python_proto_importer] not found");
+            bail!(
+                "[tool.python_proto_importer] not found in {}",
+                path.display()
+            );
         };
 
         let backend = match importer
-            .core
             .backend
             .as_deref()
             .unwrap_or("protoc")
@@ -195,44 +459,182 @@ impl AppConfig {
             other => bail!("unsupported backend: {}", other),
         };
 
-        let python_exe = importer
-            .core
-            .python_exe
-            .unwrap_or_else(|| "python3".to_string());
+        let python_exe = match importer.python_exe_candidates {
+            // Try an explicit python_exe first, then the fallbacks in order
+            Some(candidates) => {
+                let candidates: Vec<String> =
+                    importer.python_exe.into_iter().chain(candidates).collect();
+                let selected = select_interpreter(&candidates, |c| which::which(c).is_ok())?;
+                tracing::info!("using python interpreter: {}", selected);
+                selected
+            }
+            None => importer.python_exe.unwrap_or_else(|| "python3".to_string()),
+        };
         let mut include = importer
-            .core
             .include
             .unwrap_or_default()
             .into_iter()
-            .map(PathBuf::from)
+            .map(|p| rebase(PathBuf::from(p)))
             .collect::<Vec<_>>();
 
-        // If include is empty, use current directory as default
+        // If include is empty, use the config's directory as default
         if include.is_empty() {
-            include.push(PathBuf::from("."));
+            include.push(rebase(PathBuf::from(".")));
         }
-        let inputs = importer.core.inputs.unwrap_or_default();
-        let out = importer
-            .core
-            .out
-            .map(PathBuf::from)
-            .unwrap_or_else(|| PathBuf::from("generated/python"));
+        // Kept relative so they can also match under the include paths
+        let inputs = importer.inputs.unwrap_or_default();
+        let out = rebase(
+            importer
+                .out
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("generated/python")),
+        );
 
-        let generate_mypy = importer.core.mypy.unwrap_or(false);
-        let generate_mypy_grpc = importer.core.mypy_grpc.unwrap_or(false);
+        let grpc_out = importer.grpc_out.map(|p| rebase(PathBuf::from(p)));
+        let mypy_out = importer.mypy_out.map(|p| rebase(PathBuf::from(p)));
+        if matches!(backend, Backend::Buf) && (grpc_out.is_some() || mypy_out.is_some()) {
+            bail!(
+                "grpc_out and mypy_out are not supported with backend = \"buf\"; set the outputs in buf.gen.yaml"
+            );
+        }
 
-        let pp = importer.core.postprocess.unwrap_or(PostProcessToml {
+        let generate_mypy = importer.mypy.unwrap_or(false);
+        let generate_mypy_grpc = importer.mypy_grpc.unwrap_or(false);
+        let mut plugins = Vec::new();
+        for p in importer.plugin.unwrap_or_default() {
+            if p.name.is_empty()
+                || !p
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                bail!("invalid plugin name: {:?}", p.name);
+            }
+            plugins.push(Plugin {
+                out: PathBuf::from(p.out.unwrap_or_default()),
+                name: p.name,
+                opt: p.opt.unwrap_or_default(),
+            });
+        }
+        let protoc_path = importer.protoc_path.map(|p| {
+            let p = PathBuf::from(p);
+            // A bare name is looked up on PATH, not next to the config
+            if p.components().count() > 1 {
+                rebase(p)
+            } else {
+                p
+            }
+        });
+        let extra_protoc_args = importer.extra_protoc_args.unwrap_or_default();
+        // The descriptor set is written to a temp file we read back; `-o` is
+        // protoc's short form of the same flag
+        if let Some(arg) = extra_protoc_args
+            .iter()
+            .find(|a| a.starts_with("--descriptor_set_out") || a.starts_with("-o"))
+        {
+            bail!(
+                "extra_protoc_args must not set the descriptor set output (got {:?}); it is managed by python-proto-importer",
+                arg
+            );
+        }
+        let generation_timeout_secs = importer.generation_timeout_secs;
+        if generation_timeout_secs == Some(0) {
+            bail!("generation_timeout_secs must be greater than 0");
+        }
+        let timeout_secs = importer.timeout_secs;
+        if timeout_secs == Some(0) {
+            bail!("timeout_secs must be greater than 0");
+        }
+        let buf_gen_yaml = importer.buf_gen_yaml.map(|p| rebase(PathBuf::from(p)));
+        let state_dir = match importer.state_dir {
+            Some(dir) => rebase(PathBuf::from(dir)),
+            None => path
+                .parent()
+                .unwrap_or(Path::new(""))
+                .join(DEFAULT_STATE_DIR),
+        };
+
+        let pp = importer.postprocess.unwrap_or(PostProcessToml {
             relative_imports: Some(true),
             fix_pyi: Some(true),
             create_package: Some(true),
+            package_root_init: Some(true),
+            init_reexports: Some(false),
+            namespace_packages: Some(false),
+            emit_package_metadata: Some(false),
+            py_typed: None,
+            emit_all: Some(false),
+            all_exclude: None,
             exclude_google: Some(true),
             pyright_header: Some(false),
             module_suffixes: None,
+            module_transform: None,
+            exclude: None,
         });
+        let module_transform = match pp.module_transform {
+            Some(t) => Some(ModuleTransform {
+                pattern: Regex::new(&t.pattern).with_context(|| {
+                    format!(
+                        "invalid postprocess.module_transform pattern: {}",
+                        t.pattern
+                    )
+                })?,
+                replacement: t.replacement,
+            }),
+            None => None,
+        };
+        let namespace_packages = pp.namespace_packages.unwrap_or(false);
+        if namespace_packages && pp.create_package == Some(true) {
+            bail!("postprocess.namespace_packages cannot be combined with create_package = true");
+        }
+        let init_reexports = pp.init_reexports.unwrap_or(false);
+        if init_reexports && (namespace_packages || pp.create_package == Some(false)) {
+            bail!("postprocess.init_reexports requires create_package = true");
+        }
+        let exclude = pp
+            .exclude
+            .unwrap_or_default()
+            .iter()
+            .map(|p| {
+                glob::Pattern::new(p)
+                    .with_context(|| format!("invalid postprocess.exclude pattern: {}", p))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let all_exclude = match pp.all_exclude {
+            Some(patterns) => patterns,
+            None => DEFAULT_ALL_EXCLUDE.iter().map(|p| p.to_string()).collect(),
+        }
+        .iter()
+        .map(|p| {
+            glob::Pattern::new(p)
+                .with_context(|| format!("invalid postprocess.all_exclude pattern: {}", p))
+        })
+        .collect::<Result<Vec<_>>>()?;
+        let py_typed = match pp.py_typed {
+            // Stubs are only picked up by downstream type checkers with a marker
+            None if generate_mypy || generate_mypy_grpc => PyTyped::Root,
+            None | Some(PyTypedToml::Flag(false)) => PyTyped::Off,
+            Some(PyTypedToml::Flag(true)) => PyTyped::Root,
+            Some(PyTypedToml::Mode(mode)) if mode == "all" => PyTyped::All,
+            Some(PyTypedToml::Mode(mode)) => {
+                bail!(
+                    "invalid postprocess.py_typed: {:?} (expected true, false or \"all\")",
+                    mode
+                )
+            }
+        };
         let postprocess = PostProcess {
             relative_imports: pp.relative_imports.unwrap_or(true),
             fix_pyi: pp.fix_pyi.unwrap_or(true),
-            create_package: pp.create_package.unwrap_or(true),
+            // Namespace packages must not get an `__init__.py`
+            create_package: pp.create_package.unwrap_or(!namespace_packages),
+            package_root_init: pp.package_root_init.unwrap_or(true),
+            init_reexports,
+            namespace_packages,
+            emit_package_metadata: pp.emit_package_metadata.unwrap_or(false),
+            py_typed,
+            emit_all: pp.emit_all.unwrap_or(false),
+            all_exclude,
             exclude_google: pp.exclude_google.unwrap_or(true),
             pyright_header: pp.pyright_header.unwrap_or(false),
             module_suffixes: pp.module_suffixes.unwrap_or_else(|| {
@@ -243,25 +645,244 @@ impl AppConfig {
                     "_pb2_grpc.pyi".into(),
                 ]
             }),
+            module_transform,
+            exclude,
         };
 
-        let verify = importer.verify.map(|v| Verify {
-            mypy_cmd: v.mypy_cmd,
-            pyright_cmd: v.pyright_cmd,
-        });
+        if importer.verify.as_ref().and_then(|v| v.jobs) == Some(0) {
+            bail!("verify.jobs must be at least 1");
+        }
+        let verify = match importer.verify {
+            Some(v) => {
+                let profile = match v.profile.as_deref().unwrap_or("source") {
+                    "source" => VerifyProfile::Source,
+                    "installed" => {
+                        let Some(top_package) = v.top_package else {
+                            bail!("verify.profile = \"installed\" requires verify.top_package");
+                        };
+                        if top_package.is_empty()
+                            || !top_package
+                                .split('.')
+                                .all(|part| !part.is_empty() && !part.contains(['/', '\\']))
+                        {
+                            bail!("invalid verify.top_package: {:?}", top_package);
+                        }
+                        VerifyProfile::Installed { top_package }
+                    }
+                    other => bail!("unsupported verify.profile: {}", other),
+                };
+                if let Some(r) = v.max_failure_ratio
+                    && !(0.0..=1.0).contains(&r)
+                {
+                    bail!(
+                        "verify.max_failure_ratio must be between 0.0 and 1.0, got {}",
+                        r
+                    );
+                }
+                Some(Verify {
+                    mypy_cmd: v.mypy_cmd,
+                    pyright_cmd: v.pyright_cmd,
+                    jobs: v.jobs,
+                    profile,
+                    max_failure_ratio: v.max_failure_ratio,
+                    no_side_effects: v.no_side_effects.unwrap_or(false),
+                    verify_dash_m: v.verify_dash_m.unwrap_or(false),
+                    verify_cwd: v.verify_cwd.map(|p| rebase(PathBuf::from(p))),
+                })
+            }
+            None => None,
+        };
+
+        let config_dir = base_dir.clone().unwrap_or_default();
+        // `clean` removes the state dir with everything in it
+        let config_root = path.parent().unwrap_or(Path::new(""));
+        for (what, dir) in [
+            ("the config directory", config_root),
+            ("out", out.as_path()),
+        ]
+        .into_iter()
+        .chain(grpc_out.as_deref().map(|d| ("grpc_out", d)))
+        .chain(mypy_out.as_deref().map(|d| ("mypy_out", d)))
+        {
+            if lexical_absolute(dir).starts_with(lexical_absolute(&state_dir)) {
+                bail!(
+                    "state_dir {} must not be or contain {} ({}), since clean removes it",
+                    state_dir.display(),
+                    what,
+                    dir.display()
+                );
+            }
+        }
 
         Ok(Self {
             backend,
             python_exe,
             include,
             inputs,
+            config_dir,
             out,
+            grpc_out,
+            mypy_out,
             generate_mypy,
             generate_mypy_grpc,
+            plugins,
+            protoc_path,
+            extra_protoc_args,
+            generation_timeout_secs,
+            timeout_secs,
+            require_package: importer.require_package.unwrap_or(false),
+            warn_unused_includes: importer.warn_unused_includes.unwrap_or(false),
+            cleanup_on_failure: importer.cleanup_on_failure.unwrap_or(true),
+            buf_gen_yaml,
+            state_dir,
             postprocess,
             verify,
+            doctor: Doctor {
+                min_protoc: importer.doctor.and_then(|d| d.min_protoc),
+            },
         })
     }
+
+    /// Every plugin protoc runs, in command-line order.
+    ///
+    /// The built-in `python` and `grpc_python` generators come first, then the
+    /// `mypy`/`mypy_grpc` plugins when those flags are set, then the
+    /// user-configured [`plugins`](Self::plugins).
+    pub fn protoc_plugins(&self) -> Vec<Plugin> {
+        let into = |name: &str, out: &Option<PathBuf>| Plugin {
+            name: name.to_string(),
+            out: out.clone().unwrap_or_else(|| self.out.clone()),
+            opt: Vec::new(),
+        };
+        let mut plugins = vec![into("python", &None), into("grpc_python", &self.grpc_out)];
+        if self.generate_mypy {
+            plugins.push(into("mypy", &self.mypy_out));
+        }
+        if self.generate_mypy_grpc {
+            plugins.push(into("mypy_grpc", &self.mypy_out));
+        }
+        plugins.extend(self.plugins.iter().map(|p| Plugin {
+            out: self.out.join(&p.out),
+            ..p.clone()
+        }));
+        plugins
+    }
+
+    /// The directories postprocessing runs over: `out`, then `grpc_out` and
+    /// `mypy_out` when set to a different directory.
+    pub fn output_roots(&self) -> Vec<PathBuf> {
+        let mut roots = vec![self.out.clone()];
+        for root in [&self.grpc_out, &self.mypy_out].into_iter().flatten() {
+            if !roots.contains(root) {
+                roots.push(root.clone());
+            }
+        }
+        roots
+    }
+
+    /// The per-subprocess timeout from `timeout_secs`.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_secs.map(Duration::from_secs)
+    }
+}
+
+/// Pick the first interpreter in `candidates` for which `is_available` holds.
+fn select_interpreter(
+    candidates: &[String],
+    is_available: impl Fn(&str) -> bool,
+) -> Result<String> {
+    if let Some(found) = candidates.iter().find(|c| is_available(c)) {
+        return Ok(found.clone());
+    }
+    bail!(
+        "no python interpreter found; tried: {}",
+        candidates.join(", ")
+    )
+}
+
+/// Find the nearest `pyproject.toml` that configures this tool.
+///
+/// Walks from `start` up to the filesystem root, like cargo does for
+/// `Cargo.toml`, and returns the first `pyproject.toml` that contains a
+/// `[tool.python_proto_importer]` section. Files without the section (for
+/// example a nested package's own pyproject) are skipped. The error lists
+/// every directory that was searched.
+pub fn find_pyproject(start: &Path) -> Result<PathBuf> {
+    let mut searched = Vec::new();
+    for dir in start.ancestors() {
+        let candidate = dir.join("pyproject.toml");
+        if let Ok(content) = fs::read_to_string(&candidate)
+            && has_importer_section(&content)
+        {
+            return Ok(candidate);
+        }
+        searched.push(format!("  {}", dir.display()));
+    }
+    bail!(
+        "no pyproject.toml with [tool.python_proto_importer] found; searched:\n{}",
+        searched.join("\n")
+    )
+}
+
+fn has_importer_section(content: &str) -> bool {
+    match toml::from_str::<toml::Table>(content) {
+        Ok(table) => table
+            .get("tool")
+            .and_then(|t| t.get("python_proto_importer"))
+            .is_some(),
+        // Let `load` report the parse error instead of silently walking past it
+        Err(_) => content.contains("[tool.python_proto_importer"),
+    }
+}
+
+/// `path` made absolute against the cwd with `.` and `..` resolved lexically,
+/// for comparing configured directories that may not exist yet.
+fn lexical_absolute(path: &Path) -> PathBuf {
+    let path = if path.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        path
+    };
+    normalize(&std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()))
+}
+
+/// Turn a TOML deserialization error into a message naming the file, and for
+/// unknown keys also the offending key and the table it appears in.
+///
+/// `key_path` is the dotted path serde was deserializing when the error
+/// occurred (see `serde_path_to_error`), so dotted keys and inline tables name
+/// the table the key really belongs to, not the nearest `[header]`.
+fn describe_toml_error(
+    path: &Path,
+    content: &str,
+    key_path: Option<&str>,
+    err: toml::de::Error,
+) -> anyhow::Error {
+    let message = err.message().trim();
+    let Some(rest) = message.strip_prefix("unknown field `") else {
+        return anyhow::Error::new(err).context(format!("failed to parse {}", path.display()));
+    };
+    let (key, expected) = rest.split_once('`').unwrap_or((rest, ""));
+    let offset = err.span().map(|s| s.start).unwrap_or(0).min(content.len());
+    let line = content[..offset].matches('\n').count() + 1;
+    // The path ends in the unknown key itself; the rest names its table
+    let section = key_path
+        .map(|p| p.strip_suffix(key).map_or(p, |t| t.trim_end_matches('.')))
+        .filter(|p| !p.is_empty() && *p != ".")
+        .unwrap_or("<root>");
+    let expected = expected.trim_start_matches(',').trim();
+    anyhow::anyhow!(
+        "{}:{}: unknown key `{}` in [{}]{}",
+        path.display(),
+        line,
+        key,
+        section,
+        if expected.is_empty() {
+            String::new()
+        } else {
+            format!(" ({expected})")
+        }
+    )
 }
 
 #[cfg(test)]
@@ -292,15 +913,19 @@ inputs = ["proto/**/*.proto"]
         assert_eq!(config.out, PathBuf::from("generated/python"));
         assert!(!config.generate_mypy);
         assert!(!config.generate_mypy_grpc);
+        assert!(config.protoc_path.is_none());
+        assert!(config.buf_gen_yaml.is_none());
         assert!(config.postprocess.relative_imports);
         assert!(config.postprocess.fix_pyi);
         assert!(config.postprocess.create_package);
+        assert!(config.postprocess.package_root_init);
         assert!(config.postprocess.exclude_google);
         assert!(!config.postprocess.pyright_header);
         assert_eq!(
             config.postprocess.module_suffixes,
             vec!["_pb2.py", "_pb2.pyi", "_pb2_grpc.py", "_pb2_grpc.pyi"]
         );
+        assert!(config.postprocess.module_transform.is_none());
         assert!(config.verify.is_none());
     }
 
@@ -319,11 +944,14 @@ inputs = ["proto/**/*.proto", "common/**/*.proto"]
 out = "src/generated"
 mypy = true
 mypy_grpc = true
+protoc_path = "/usr/bin/protoc"
+buf_gen_yaml = "buf.gen.yaml"
 
 [tool.python_proto_importer.postprocess]
 relative_imports = false
 fix_pyi = false
 create_package = false
+package_root_init = false
 exclude_google = false
 pyright_header = true
 module_suffixes = ["_pb2.py", "_grpc.py"]
@@ -331,6 +959,7 @@ module_suffixes = ["_pb2.py", "_grpc.py"]
 [tool.python_proto_importer.verify]
 mypy_cmd = ["mypy", "--strict"]
 pyright_cmd = ["pyright", "generated"]
+jobs = 4
 "#,
         )
         .unwrap();
@@ -347,9 +976,12 @@ pyright_cmd = ["pyright", "generated"]
         assert_eq!(config.out, PathBuf::from("src/generated"));
         assert!(config.generate_mypy);
         assert!(config.generate_mypy_grpc);
+        assert_eq!(config.protoc_path, Some(PathBuf::from("/usr/bin/protoc")));
+        assert_eq!(config.buf_gen_yaml, Some(PathBuf::from("buf.gen.yaml")));
         assert!(!config.postprocess.relative_imports);
         assert!(!config.postprocess.fix_pyi);
         assert!(!config.postprocess.create_package);
+        assert!(!config.postprocess.package_root_init);
         assert!(!config.postprocess.exclude_google);
         assert!(config.postprocess.pyright_header);
         assert_eq!(
@@ -360,6 +992,7 @@ pyright_cmd = ["pyright", "generated"]
         let verify = config.verify.unwrap();
         assert_eq!(verify.mypy_cmd.unwrap(), vec!["mypy", "--strict"]);
         assert_eq!(verify.pyright_cmd.unwrap(), vec!["pyright", "generated"]);
+        assert_eq!(verify.jobs, Some(4));
     }
 
     #[test]
@@ -471,6 +1104,311 @@ inputs = ["proto/**/*.proto"]
         assert!(result.unwrap_err().to_string().contains("failed to parse"));
     }
 
+    #[test]
+    fn select_interpreter_takes_first_available_in_order() {
+        let candidates: Vec<String> = ["python3.12", "python3", "python"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let available = |names: &'static [&'static str]| move |c: &str| names.contains(&c);
+
+        assert_eq!(
+            select_interpreter(&candidates, available(&["python", "python3"])).unwrap(),
+            "python3"
+        );
+        assert_eq!(
+            select_interpreter(&candidates, available(&["python"])).unwrap(),
+            "python"
+        );
+        assert_eq!(
+            select_interpreter(&candidates, available(&["python3.12", "python"])).unwrap(),
+            "python3.12"
+        );
+        let err = select_interpreter(&candidates, available(&[])).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("tried: python3.12, python3, python")
+        );
+    }
+
+    #[test]
+    fn python_exe_candidates_resolved_at_load() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("pyproject.toml");
+        fs::write(
+            &config_path,
+            r#"
+[tool.python_proto_importer]
+python_exe = "proto-importer-missing-python"
+python_exe_candidates = ["proto-importer-missing-python3", "sh"]
+"#,
+        )
+        .unwrap();
+        let config = AppConfig::load(Some(&config_path)).unwrap();
+        assert_eq!(config.python_exe, "sh");
+    }
+
+    #[test]
+    fn plugin_entries_resolve_out_against_cfg_out() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("pyproject.toml");
+        fs::write(
+            &config_path,
+            r#"
+[tool.python_proto_importer]
+out = "gen"
+mypy = true
+
+[[tool.python_proto_importer.plugin]]
+name = "connect-python"
+out = "connect"
+opt = ["naming=pep8"]
+
+[[tool.python_proto_importer.plugin]]
+name = "doc"
+out = "/tmp/docs"
+"#,
+        )
+        .unwrap();
+        let config = AppConfig::load(Some(&config_path)).unwrap();
+        let plugins = config.protoc_plugins();
+        let names: Vec<_> = plugins.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["python", "grpc_python", "mypy", "connect-python", "doc"]
+        );
+        assert_eq!(plugins[3].out, PathBuf::from("gen/connect"));
+        assert_eq!(plugins[3].opt, vec!["naming=pep8"]);
+        assert_eq!(plugins[4].out, PathBuf::from("/tmp/docs"));
+        assert!(plugins[4].opt.is_empty());
+
+        fs::write(
+            &config_path,
+            "[[tool.python_proto_importer.plugin]]\nname = \"bad name\"\n",
+        )
+        .unwrap();
+        assert!(
+            AppConfig::load(Some(&config_path))
+                .unwrap_err()
+                .to_string()
+                .contains("invalid plugin name")
+        );
+    }
+
+    #[test]
+    fn grpc_and_stub_outputs_can_be_split_from_out() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("pyproject.toml");
+        fs::write(
+            &config_path,
+            r#"
+[tool.python_proto_importer]
+out = "gen"
+mypy_out = "stubs"
+mypy = true
+mypy_grpc = true
+"#,
+        )
+        .unwrap();
+        let config = AppConfig::load(Some(&config_path)).unwrap();
+        let outs: Vec<_> = config
+            .protoc_plugins()
+            .into_iter()
+            .map(|p| (p.name, p.out))
+            .collect();
+        assert_eq!(
+            outs,
+            vec![
+                ("python".to_string(), PathBuf::from("gen")),
+                ("grpc_python".to_string(), PathBuf::from("gen")),
+                ("mypy".to_string(), PathBuf::from("stubs")),
+                ("mypy_grpc".to_string(), PathBuf::from("stubs")),
+            ]
+        );
+        assert_eq!(
+            config.output_roots(),
+            vec![PathBuf::from("gen"), PathBuf::from("stubs")]
+        );
+
+        fs::write(
+            &config_path,
+            "[tool.python_proto_importer]\nbackend = \"buf\"\ngrpc_out = \"grpc\"\n",
+        )
+        .unwrap();
+        assert!(
+            AppConfig::load(Some(&config_path))
+                .unwrap_err()
+                .to_string()
+                .contains("not supported with backend = \"buf\"")
+        );
+    }
+
+    #[test]
+    fn extra_protoc_args_cannot_override_descriptor_set() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("pyproject.toml");
+        fs::write(
+            &config_path,
+            "[tool.python_proto_importer]\nextra_protoc_args = [\"--experimental_allow_proto3_optional\"]\n",
+        )
+        .unwrap();
+        let config = AppConfig::load(Some(&config_path)).unwrap();
+        assert_eq!(
+            config.extra_protoc_args,
+            vec!["--experimental_allow_proto3_optional"]
+        );
+
+        for bad in ["--descriptor_set_out=x.pb", "-ox.pb"] {
+            fs::write(
+                &config_path,
+                format!(
+                    "[tool.python_proto_importer]\nextra_protoc_args = [{:?}]\n",
+                    bad
+                ),
+            )
+            .unwrap();
+            let err = AppConfig::load(Some(&config_path)).unwrap_err();
+            assert!(err.to_string().contains("descriptor set output"), "{err}");
+        }
+    }
+
+    #[test]
+    fn namespace_packages_disable_init_creation() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("pyproject.toml");
+        fs::write(
+            &config_path,
+            "[tool.python_proto_importer.postprocess]\nnamespace_packages = true\n",
+        )
+        .unwrap();
+        let config = AppConfig::load(Some(&config_path)).unwrap();
+        assert!(config.postprocess.namespace_packages);
+        assert!(!config.postprocess.create_package);
+        assert!(config.postprocess.out_is_import_root());
+
+        fs::write(
+            &config_path,
+            "[tool.python_proto_importer.postprocess]\nnamespace_packages = true\ncreate_package = true\n",
+        )
+        .unwrap();
+        let err = AppConfig::load(Some(&config_path)).unwrap_err();
+        assert!(err.to_string().contains("namespace_packages"));
+    }
+
+    #[test]
+    fn state_dir_defaults_next_to_config_and_is_configurable() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("pyproject.toml");
+        fs::write(&config_path, "[tool.python_proto_importer]\n").unwrap();
+        let config = AppConfig::load(Some(&config_path)).unwrap();
+        assert_eq!(config.state_dir, dir.path().join(".proto-importer"));
+
+        fs::write(
+            &config_path,
+            "[tool.python_proto_importer]\nstate_dir = \"build/state\"\n",
+        )
+        .unwrap();
+        let config = AppConfig::load(Some(&config_path)).unwrap();
+        assert_eq!(config.state_dir, PathBuf::from("build/state"));
+    }
+
+    #[test]
+    fn state_dir_may_not_contain_the_project_or_out() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("pyproject.toml");
+        let load = |extra: &str| {
+            fs::write(
+                &config_path,
+                format!(
+                    "[tool.python_proto_importer]
+out = {:?}
+{extra}",
+                    dir.path().join("build/gen").display().to_string()
+                ),
+            )
+            .unwrap();
+            AppConfig::load(Some(&config_path))
+        };
+        let state_dir = |p: &Path| {
+            format!(
+                "state_dir = {:?}
+",
+                p.display().to_string()
+            )
+        };
+
+        for bad in [
+            dir.path().to_path_buf(),
+            dir.path().join("sub/.."),
+            dir.path().join("build"),
+            dir.path().join("build/gen"),
+        ] {
+            let err = load(&state_dir(&bad)).unwrap_err().to_string();
+            assert!(err.contains("must not be or contain"), "{err}");
+        }
+        let err = load(&format!(
+            "mypy_out = {:?}
+{}",
+            dir.path().join("stubs").display().to_string(),
+            state_dir(&dir.path().join("stubs"))
+        ))
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("mypy_out"), "{err}");
+        // Next to out, or inside it, is fine
+        assert!(load(&state_dir(&dir.path().join("build/state"))).is_ok());
+        assert!(load(&state_dir(&dir.path().join("build/gen/.state"))).is_ok());
+    }
+
+    #[test]
+    fn find_pyproject_walks_up_past_unrelated_configs() {
+        let dir = tempdir().unwrap();
+        let root_config = dir.path().join("pyproject.toml");
+        fs::write(
+            &root_config,
+            "[tool.python_proto_importer]\ninputs = [\"proto/**/*.proto\"]\n",
+        )
+        .unwrap();
+        // A nested pyproject for some other tool must not stop the search
+        let nested = dir.path().join("pkg/sub");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(
+            dir.path().join("pkg/pyproject.toml"),
+            "[tool.ruff]\nline-length = 100\n",
+        )
+        .unwrap();
+
+        assert_eq!(find_pyproject(&nested).unwrap(), root_config);
+        assert_eq!(find_pyproject(dir.path()).unwrap(), root_config);
+    }
+
+    #[test]
+    fn load_from_subdirectory_resolves_against_config_dir() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("pyproject.toml"),
+            "[tool.python_proto_importer]\nout = \"gen\"\ninclude = [\"proto\"]\ninputs = [\"api/*.proto\"]\nprotoc_path = \"tools/protoc\"\n",
+        )
+        .unwrap();
+        fs::create_dir_all(dir.path().join("proto/api")).unwrap();
+        fs::write(dir.path().join("proto/api/orders.proto"), "").unwrap();
+        let sub = dir.path().join("services/orders");
+        fs::create_dir_all(&sub).unwrap();
+
+        let config = AppConfig::load_from(None, &sub).unwrap();
+        assert_eq!(config.out, dir.path().join("gen"));
+        assert_eq!(config.include, vec![dir.path().join("proto")]);
+        assert_eq!(config.inputs, vec!["api/*.proto"]);
+        assert_eq!(config.config_dir, dir.path());
+        assert_eq!(config.state_dir, dir.path().join(DEFAULT_STATE_DIR));
+        assert_eq!(config.protoc_path, Some(dir.path().join("tools/protoc")));
+        // The include-relative input matches although the cwd is elsewhere
+        assert_eq!(
+            crate::generator::protoc::ProtocRunner::new(&config).expand_inputs(),
+            vec![dir.path().join("proto/api/orders.proto")]
+        );
+    }
+
     #[test]
     fn load_default_path() {
         let dir = tempdir().unwrap();
@@ -515,4 +1453,303 @@ mypy_cmd = ["mypy"]
         assert_eq!(verify.mypy_cmd.unwrap(), vec!["mypy"]);
         assert!(verify.pyright_cmd.is_none());
     }
+
+    #[test]
+    fn verify_jobs_zero_fails() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("pyproject.toml");
+        fs::write(
+            &config_path,
+            r#"
+[tool.python_proto_importer]
+inputs = ["proto/**/*.proto"]
+
+[tool.python_proto_importer.verify]
+jobs = 0
+"#,
+        )
+        .unwrap();
+
+        let result = AppConfig::load(Some(&config_path));
+        assert!(result.is_err());
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("verify.jobs must be at least 1")
+        );
+    }
+
+    #[test]
+    fn verify_profile_installed_parsed() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("pyproject.toml");
+        fs::write(
+            &config_path,
+            r#"
+[tool.python_proto_importer]
+inputs = ["proto/**/*.proto"]
+
+[tool.python_proto_importer.verify]
+profile = "installed"
+top_package = "mypkg.proto"
+"#,
+        )
+        .unwrap();
+
+        let config = AppConfig::load(Some(&config_path)).unwrap();
+        assert_eq!(
+            config.verify.unwrap().profile,
+            VerifyProfile::Installed {
+                top_package: "mypkg.proto".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn verify_profile_installed_requires_top_package() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("pyproject.toml");
+        fs::write(
+            &config_path,
+            r#"
+[tool.python_proto_importer]
+inputs = ["proto/**/*.proto"]
+
+[tool.python_proto_importer.verify]
+profile = "installed"
+"#,
+        )
+        .unwrap();
+
+        let err = AppConfig::load(Some(&config_path)).unwrap_err();
+        assert!(err.to_string().contains("requires verify.top_package"));
+    }
+
+    #[test]
+    fn verify_max_failure_ratio_out_of_range_fails() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("pyproject.toml");
+        fs::write(
+            &config_path,
+            r#"
+[tool.python_proto_importer]
+inputs = ["proto/**/*.proto"]
+
+[tool.python_proto_importer.verify]
+max_failure_ratio = 1.5
+"#,
+        )
+        .unwrap();
+
+        let err = AppConfig::load(Some(&config_path)).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("verify.max_failure_ratio must be between 0.0 and 1.0")
+        );
+    }
+
+    #[test]
+    fn unknown_postprocess_key_names_key_and_section() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("pyproject.toml");
+        fs::write(
+            &config_path,
+            r#"
+[tool.python_proto_importer]
+inputs = ["proto/**/*.proto"]
+
+[tool.python_proto_importer.postprocess]
+relative_import = true
+"#,
+        )
+        .unwrap();
+
+        let msg = AppConfig::load(Some(&config_path)).unwrap_err().to_string();
+        assert!(msg.contains("unknown key `relative_import`"), "{msg}");
+        assert!(
+            msg.contains("[tool.python_proto_importer.postprocess]"),
+            "{msg}"
+        );
+        assert!(msg.contains("relative_imports"), "{msg}");
+        assert!(
+            msg.contains(&format!("{}:6", config_path.display())),
+            "{msg}"
+        );
+    }
+
+    #[test]
+    fn unknown_dotted_and_inline_keys_name_their_table() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("pyproject.toml");
+        fs::write(
+            &config_path,
+            r#"
+[tool.python_proto_importer]
+inputs = ["proto/**/*.proto"]
+postprocess.relative_import = true
+"#,
+        )
+        .unwrap();
+        let msg = AppConfig::load(Some(&config_path)).unwrap_err().to_string();
+        assert!(
+            msg.contains(
+                "4: unknown key `relative_import` in [tool.python_proto_importer.postprocess]"
+            ),
+            "{msg}"
+        );
+        assert!(msg.contains("relative_imports"), "{msg}");
+
+        fs::write(
+            &config_path,
+            r#"
+[tool.python_proto_importer]
+doctor = { min_proto = "3.21" }
+"#,
+        )
+        .unwrap();
+        let msg = AppConfig::load(Some(&config_path)).unwrap_err().to_string();
+        assert!(
+            msg.contains("unknown key `min_proto` in [tool.python_proto_importer.doctor]"),
+            "{msg}"
+        );
+    }
+
+    #[test]
+    fn unknown_top_level_and_verify_keys_rejected() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("pyproject.toml");
+        fs::write(
+            &config_path,
+            r#"
+[tool.python_proto_importer]
+input = ["proto/**/*.proto"]
+"#,
+        )
+        .unwrap();
+        let msg = AppConfig::load(Some(&config_path)).unwrap_err().to_string();
+        assert!(
+            msg.contains("unknown key `input` in [tool.python_proto_importer]"),
+            "{msg}"
+        );
+
+        fs::write(
+            &config_path,
+            r#"
+[tool.python_proto_importer]
+inputs = ["proto/**/*.proto"]
+
+[tool.python_proto_importer.verify]
+mypy = ["mypy"]
+"#,
+        )
+        .unwrap();
+        let msg = AppConfig::load(Some(&config_path)).unwrap_err().to_string();
+        assert!(
+            msg.contains("unknown key `mypy` in [tool.python_proto_importer.verify]"),
+            "{msg}"
+        );
+    }
+
+    #[test]
+    fn other_tool_sections_are_ignored() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("pyproject.toml");
+        fs::write(
+            &config_path,
+            r#"
+[project]
+name = "demo"
+
+[tool.ruff]
+line-length = 100
+
+[tool.python_proto_importer]
+inputs = ["proto/**/*.proto"]
+"#,
+        )
+        .unwrap();
+        assert!(AppConfig::load(Some(&config_path)).is_ok());
+    }
+
+    #[test]
+    fn module_transform_parsed() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("pyproject.toml");
+        fs::write(
+            &config_path,
+            r#"
+[tool.python_proto_importer]
+inputs = ["proto/**/*.proto"]
+
+[tool.python_proto_importer.postprocess]
+module_transform = { pattern = "_v[0-9]+(_pb2)", replacement = "$1" }
+"#,
+        )
+        .unwrap();
+
+        let config = AppConfig::load(Some(&config_path)).unwrap();
+        let transform = config.postprocess.module_transform.unwrap();
+        assert_eq!(transform.apply("service_v1_pb2"), "service_pb2");
+        assert_eq!(transform.apply("service_v2_pb2_grpc"), "service_pb2_grpc");
+        assert_eq!(transform.apply("common_pb2"), "common_pb2");
+    }
+
+    #[test]
+    fn module_transform_invalid_regex_fails() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("pyproject.toml");
+        fs::write(
+            &config_path,
+            r#"
+[tool.python_proto_importer]
+inputs = ["proto/**/*.proto"]
+
+[tool.python_proto_importer.postprocess]
+module_transform = { pattern = "_v(", replacement = "" }
+"#,
+        )
+        .unwrap();
+
+        let result = AppConfig::load(Some(&config_path));
+        assert!(result.is_err());
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("invalid postprocess.module_transform pattern")
+        );
+    }
+
+    #[test]
+    fn py_typed_defaults_to_stub_generation() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("pyproject.toml");
+        let load = |body: &str| {
+            fs::write(
+                &config_path,
+                format!("[tool.python_proto_importer]\n{body}"),
+            )
+            .unwrap();
+            AppConfig::load(Some(&config_path)).map(|c| c.postprocess.py_typed)
+        };
+
+        assert_eq!(load("").unwrap(), PyTyped::Off);
+        assert_eq!(load("mypy = true\n").unwrap(), PyTyped::Root);
+        assert_eq!(
+            load("mypy = true\n[tool.python_proto_importer.postprocess]\npy_typed = false\n")
+                .unwrap(),
+            PyTyped::Off
+        );
+        assert_eq!(
+            load("[tool.python_proto_importer.postprocess]\npy_typed = \"all\"\n").unwrap(),
+            PyTyped::All
+        );
+        let err =
+            load("[tool.python_proto_importer.postprocess]\npy_typed = \"some\"\n").unwrap_err();
+        assert!(
+            err.to_string().contains("invalid postprocess.py_typed"),
+            "{err}"
+        );
+    }
 }
@@ -1,7 +1,10 @@
-use crate::config::AppConfig;
+use crate::config::{AppConfig, Backend};
+use crate::generator::protoc::ProtocRunner;
+use crate::utils::find_tool;
 use crate::verification::{determine_package_structure, determine_package_structure_legacy};
-use anyhow::{Result, bail};
-use std::path::Path;
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use std::collections::BTreeMap;
 use std::process::Command;
 use which::which;
 
@@ -21,6 +24,9 @@ fn check(cmd: &str) -> Option<String> {
         .and_then(|p| p.to_str().map(|s| s.to_string()))
 }
 
+/// Environment variables that point at a specific binary instead of the one on PATH.
+const TOOL_ENV: [(&str, &str); 2] = [("protoc", "PROTOC"), ("buf", "BUF")];
+
 /// Run environment diagnostics and display system information.
 ///
 /// This function performs a comprehensive check of the development environment,
@@ -34,7 +40,15 @@ fn check(cmd: &str) -> Option<String> {
 /// - System tools (protoc, buf)
 ///
 /// The function also attempts to load and validate a pyproject.toml configuration
-/// to provide targeted recommendations based on the current project setup.
+/// to provide targeted recommendations based on the current project setup,
+/// including whether generation runs through `grpc_tools.protoc` or a
+/// standalone `protoc_path` binary. With `doctor.min_protoc` configured, the
+/// protoc generation runs is asked for its version and an older one fails
+/// the check.
+///
+/// # Arguments
+///
+/// * `json` - If true, prints a [`DoctorReport`] as JSON instead of the text report
 ///
 /// # Returns
 ///
@@ -47,105 +61,72 @@ fn check(cmd: &str) -> Option<String> {
 /// use python_proto_importer::doctor;
 ///
 /// fn main() -> anyhow::Result<()> {
-///     doctor::run()
+///     doctor::run(false)
 /// }
 /// ```
-pub fn run() -> Result<()> {
-    println!("== Tool presence ==");
-
-    let py_runner = check("uv")
-        .or_else(|| check("python3"))
-        .or_else(|| check("python"))
-        .unwrap_or_default();
+pub fn run(json: bool) -> Result<()> {
+    let report = DoctorReport::collect();
+    let grpc_tools_found = report.grpc_tools;
+    let mypy_protobuf_found = report.python_packages["mypy-protobuf"].found;
+    let mypy_grpc_found = report.python_packages["mypy-grpc"].found;
 
-    if let Some(uv_path) = check("uv") {
-        let uv_ver = cmd_version(&uv_path, &["--version"]).unwrap_or_else(|| "unknown".into());
-        println!("{:<14}: {} ({})", "uv", uv_path, uv_ver.trim());
-    } else {
-        println!("{:<14}: not found", "uv");
-    }
-    if let Some(py3) = check("python3") {
-        let py_ver = cmd_version(&py3, &["--version"]).unwrap_or_else(|| "unknown".into());
-        println!("{:<14}: {} ({})", "python3", py3, py_ver.trim());
-    } else if let Some(py) = check("python") {
-        let py_ver = cmd_version(&py, &["--version"]).unwrap_or_else(|| "unknown".into());
-        println!("{:<14}: {} ({})", "python", py, py_ver.trim());
-    } else {
-        println!("{:<14}: not found", "python");
+    if !json {
+        print_tools(&report);
     }
 
-    let (grpc_tools_found, grpc_tools_ver) = probe_python_pkg(&py_runner, "grpcio-tools");
-    println!(
-        "{:<14}: {}{}",
-        "grpc_tools",
-        if grpc_tools_found {
-            "found"
-        } else {
-            "not found"
-        },
-        grpc_tools_ver
-            .as_deref()
-            .map(|v| format!(" ({})", v))
-            .unwrap_or_default()
-    );
+    let loaded = AppConfig::load(None).ok();
+    let uses_grpc_tools = loaded
+        .as_ref()
+        .is_none_or(|cfg| ProtocRunner::new(cfg).protoc().is_none());
+    let min_protoc = loaded.as_ref().and_then(|cfg| {
+        let min = cfg.doctor.min_protoc.as_deref()?;
+        matches!(cfg.backend, Backend::Protoc).then(|| (min, check_min_protoc(cfg, min)))
+    });
 
-    let (mypy_protobuf_found, mypy_protobuf_ver) = probe_python_pkg(&py_runner, "mypy-protobuf");
-    println!(
-        "{:<14}: {}{}",
-        "mypy-protobuf",
-        if mypy_protobuf_found {
-            "found"
-        } else {
-            "not found"
-        },
-        mypy_protobuf_ver
-            .as_deref()
-            .map(|v| format!(" ({})", v))
-            .unwrap_or_default()
-    );
-    let (mypy_grpc_found, mypy_grpc_ver) = probe_python_pkg(&py_runner, "mypy-grpc");
-    println!(
-        "{:<14}: {}{}",
-        "mypy-grpc",
-        if mypy_grpc_found {
-            "found"
-        } else {
-            "not found"
-        },
-        mypy_grpc_ver
-            .as_deref()
-            .map(|v| format!(" ({})", v))
-            .unwrap_or_default()
-    );
-
-    if let Some(p) = check("protoc") {
-        let v = cmd_version(&p, &["--version"]).unwrap_or_else(|| "unknown".into());
-        println!("{:<14}: {} ({})", "protoc", p, v.trim());
-    } else {
-        println!("{:<14}: not found", "protoc");
-    }
-    if let Some(p) = check("buf") {
-        let v = cmd_version(&p, &["--version"]).unwrap_or_else(|| "unknown".into());
-        println!("{:<14}: {} ({})", "buf", p, v.trim());
-    } else {
-        println!("{:<14}: not found", "buf");
-    }
-
-    if let Some(p) = check("mypy") {
-        let v = cmd_version(&p, &["--version"]).unwrap_or_else(|| "unknown".into());
-        println!("{:<14}: {} ({})", "mypy", p, v.trim());
-    } else {
-        println!("{:<14}: not found", "mypy");
-    }
-    if let Some(p) = check("pyright") {
-        let v = cmd_version(&p, &["--version"]).unwrap_or_else(|| "unknown".into());
-        println!("{:<14}: {} ({})", "pyright", p, v.trim());
-    } else {
-        println!("{:<14}: not found", "pyright");
+    if json {
+        let mut report = report;
+        report.protoc_mode = loaded
+            .as_ref()
+            .map(|cfg| match ProtocRunner::new(cfg).protoc() {
+                Some(p) => format!("protoc ({})", p.display()),
+                None => format!("grpc_tools.protoc (via {})", cfg.python_exe),
+            });
+        report.protoc_version = min_protoc
+            .as_ref()
+            .and_then(|(_, checked)| checked.as_ref().ok().cloned());
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        if uses_grpc_tools && !grpc_tools_found {
+            bail!(
+                "grpc_tools.protoc not found. Install with 'uv add grpcio-tools' or 'pip install grpcio-tools'"
+            );
+        }
+        if let Some((_, Err(e))) = min_protoc {
+            return Err(e);
+        }
+        return Ok(());
     }
-
-    if let Ok(cfg) = AppConfig::load(Some(Path::new("pyproject.toml"))) {
+    if let Some(cfg) = &loaded {
         println!("\n== Based on pyproject.toml ==");
+        match ProtocRunner::new(cfg).protoc() {
+            Some(p) => {
+                println!("{:<14}: standalone protoc ({})", "protoc mode", p.display());
+                if !p.exists() && check(&p.to_string_lossy()).is_none() {
+                    println!("hint: protoc_path {} does not exist", p.display());
+                }
+            }
+            None => println!(
+                "{:<14}: grpc_tools.protoc (via {})",
+                "protoc mode", cfg.python_exe
+            ),
+        }
+        if let Some((min, checked)) = &min_protoc {
+            println!(
+                "{:<14}: {} (doctor.min_protoc = {})",
+                "protoc version",
+                checked.as_deref().unwrap_or("too old or unknown"),
+                min
+            );
+        }
         if cfg.generate_mypy && !mypy_protobuf_found {
             println!(
                 "hint: mypy-protobuf is required (install via 'uv add mypy-protobuf' or 'pip install mypy-protobuf')"
@@ -157,12 +138,12 @@ pub fn run() -> Result<()> {
             );
         }
         if let Some(v) = &cfg.verify {
-            if v.mypy_cmd.is_some() && check("mypy").is_none() {
+            if v.mypy_cmd.is_some() && !report.tools["mypy"].found {
                 println!(
                     "hint: mypy CLI not found (install via 'uv add mypy' or 'pip install mypy')"
                 );
             }
-            if v.pyright_cmd.is_some() && check("pyright").is_none() {
+            if v.pyright_cmd.is_some() && !report.tools["pyright"].found {
                 println!(
                     "hint: pyright CLI not found (install via 'uv add pyright' or 'npm i -g pyright')"
                 );
@@ -170,14 +151,17 @@ pub fn run() -> Result<()> {
         }
     }
 
-    if !grpc_tools_found {
+    if uses_grpc_tools && !grpc_tools_found {
         bail!(
             "grpc_tools.protoc not found. Install with 'uv add grpcio-tools' or 'pip install grpcio-tools'"
         );
     }
+    if let Some((_, Err(e))) = min_protoc {
+        return Err(e);
+    }
 
     // Check package structure if pyproject.toml is found
-    if let Ok(cfg) = AppConfig::load(Some(Path::new("pyproject.toml"))) {
+    if let Some(cfg) = &loaded {
         println!("\n== Package structure analysis ==");
 
         let out_abs = cfg.out.canonicalize().unwrap_or_else(|_| cfg.out.clone());
@@ -258,6 +242,195 @@ pub fn run() -> Result<()> {
     Ok(())
 }
 
+/// Presence of an executable on PATH, or at the path its environment
+/// variable (`PROTOC`, `BUF`) names.
+#[derive(Debug, Serialize)]
+pub struct ToolStatus {
+    pub found: bool,
+    pub path: Option<String>,
+    /// Version parsed from `--version`, e.g. `3.21.12` for `libprotoc 3.21.12`
+    pub version: Option<String>,
+}
+
+impl ToolStatus {
+    fn probe(cmd: &str) -> Self {
+        let path = match TOOL_ENV.iter().find(|(name, _)| *name == cmd) {
+            Some((_, var)) => find_tool(var, cmd).and_then(|p| p.to_str().map(str::to_string)),
+            None => check(cmd),
+        };
+        match path {
+            Some(path) => Self {
+                found: true,
+                version: cmd_version(&path, &["--version"])
+                    .map(|v| parse_version(&v).unwrap_or_else(|| v.trim().to_string())),
+                path: Some(path),
+            },
+            None => Self {
+                found: false,
+                path: None,
+                version: None,
+            },
+        }
+    }
+}
+
+/// Presence of a Python distribution in the selected interpreter.
+#[derive(Debug, Serialize)]
+pub struct PackageStatus {
+    pub found: bool,
+    pub version: Option<String>,
+}
+
+/// Everything `doctor` checks, as emitted by `doctor --json`.
+#[derive(Debug, Serialize)]
+pub struct DoctorReport {
+    /// Executables keyed by name (`uv`, `python3`, `python`, `protoc`, ...)
+    pub tools: BTreeMap<String, ToolStatus>,
+    /// Python distributions keyed by distribution name
+    pub python_packages: BTreeMap<String, PackageStatus>,
+    /// Whether `grpc_tools.protoc` is importable
+    pub grpc_tools: bool,
+    /// Active protoc mode from pyproject.toml, if one was loaded
+    pub protoc_mode: Option<String>,
+    /// Version of the protoc generation runs, when `doctor.min_protoc` is
+    /// configured and satisfied
+    pub protoc_version: Option<String>,
+}
+
+impl DoctorReport {
+    fn collect() -> Self {
+        let tools: BTreeMap<String, ToolStatus> = [
+            "uv", "python3", "python", "protoc", "buf", "mypy", "pyright",
+        ]
+        .into_iter()
+        .map(|name| (name.to_string(), ToolStatus::probe(name)))
+        .collect();
+
+        let py_runner = ["uv", "python3", "python"]
+            .iter()
+            .find_map(|name| tools[*name].path.clone())
+            .unwrap_or_default();
+        let python_packages: BTreeMap<String, PackageStatus> =
+            ["grpcio-tools", "mypy-protobuf", "mypy-grpc"]
+                .into_iter()
+                .map(|dist| {
+                    let (found, version) = probe_python_pkg(&py_runner, dist);
+                    (dist.to_string(), PackageStatus { found, version })
+                })
+                .collect();
+        let grpc_tools = python_packages["grpcio-tools"].found;
+
+        Self {
+            tools,
+            python_packages,
+            grpc_tools,
+            protoc_mode: None,
+            protoc_version: None,
+        }
+    }
+}
+
+/// Check the protoc generation runs against `doctor.min_protoc`.
+///
+/// # Returns
+///
+/// The detected version, or an error when it is older than `min` or cannot
+/// be determined.
+fn check_min_protoc(cfg: &AppConfig, min: &str) -> Result<String> {
+    let Some(required) = protoc_release(min) else {
+        bail!(
+            "doctor.min_protoc must be a version such as \"3.21\", got {:?}",
+            min
+        );
+    };
+    let output = ProtocRunner::new(cfg)
+        .version()
+        .context("failed to determine the protoc version for doctor.min_protoc")?;
+    let Some(found) = protoc_release(&output) else {
+        bail!("could not parse a protoc version from {:?}", output);
+    };
+    let version = parse_version(&output).unwrap_or(output);
+    if found < required {
+        bail!(
+            "protoc {} is older than doctor.min_protoc = \"{}\"",
+            version,
+            min
+        );
+    }
+    Ok(version)
+}
+
+/// The first version-like token of a `--version` output, e.g. `3.21.12` from
+/// `libprotoc 3.21.12` or `1.8.0` from `mypy 1.8.0 (compiled: yes)`.
+fn parse_version(output: &str) -> Option<String> {
+    output
+        .split_whitespace()
+        .find(|token| token.starts_with(|c: char| c.is_ascii_digit()))
+        .map(|token| token.trim_end_matches([',', ';', ')']).to_string())
+}
+
+/// Comparable protobuf release of a protoc version.
+///
+/// protoc reported `3.N.P` up to release 21 and `N.P` since (`libprotoc
+/// 3.21.12` and `libprotoc 22.0` are consecutive releases), so a leading `3`
+/// is dropped. Suffixes such as `-rc1` are ignored.
+fn protoc_release(version: &str) -> Option<(u32, u32)> {
+    let token = parse_version(version)?;
+    let mut parts = token.split('.').map(|part| {
+        let digits: String = part.chars().take_while(char::is_ascii_digit).collect();
+        digits.parse::<u32>().ok()
+    });
+    let major = parts.next()??;
+    let minor = parts.next().flatten().unwrap_or(0);
+    let patch = parts.next().flatten().unwrap_or(0);
+    Some(match major {
+        3 => (minor, patch),
+        0..3 => (0, 0),
+        _ => (major, minor),
+    })
+}
+
+/// Print the aligned tool table used by the default text output.
+fn print_tools(report: &DoctorReport) {
+    fn tool_line(label: &str, status: &ToolStatus) {
+        match &status.path {
+            Some(path) => println!(
+                "{:<14}: {} ({})",
+                label,
+                path,
+                status.version.as_deref().unwrap_or("unknown")
+            ),
+            None => println!("{:<14}: not found", label),
+        }
+    }
+    fn package_line(label: &str, status: &PackageStatus) {
+        println!(
+            "{:<14}: {}{}",
+            label,
+            if status.found { "found" } else { "not found" },
+            status
+                .version
+                .as_deref()
+                .map(|v| format!(" ({})", v))
+                .unwrap_or_default()
+        );
+    }
+
+    println!("== Tool presence ==");
+    tool_line("uv", &report.tools["uv"]);
+    if report.tools["python3"].found {
+        tool_line("python3", &report.tools["python3"]);
+    } else {
+        tool_line("python", &report.tools["python"]);
+    }
+    package_line("grpc_tools", &report.python_packages["grpcio-tools"]);
+    package_line("mypy-protobuf", &report.python_packages["mypy-protobuf"]);
+    package_line("mypy-grpc", &report.python_packages["mypy-grpc"]);
+    for name in ["protoc", "buf", "mypy", "pyright"] {
+        tool_line(name, &report.tools[name]);
+    }
+}
+
 fn cmd_version(bin: &str, args: &[&str]) -> Option<String> {
     let out = Command::new(bin).args(args).output().ok()?;
     if out.status.success() {
@@ -309,3 +482,89 @@ fn probe_python_pkg(py_runner: &str, dist_name: &str) -> (bool, Option<String>)
     }
     (false, None)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_serializes_tools_and_grpc_tools() {
+        let mut tools = BTreeMap::new();
+        tools.insert(
+            "protoc".to_string(),
+            ToolStatus {
+                found: true,
+                path: Some("/usr/bin/protoc".to_string()),
+                version: Some("libprotoc 27.0".to_string()),
+            },
+        );
+        tools.insert(
+            "buf".to_string(),
+            ToolStatus {
+                found: false,
+                path: None,
+                version: None,
+            },
+        );
+        let report = DoctorReport {
+            tools,
+            python_packages: BTreeMap::new(),
+            grpc_tools: true,
+            protoc_mode: None,
+            protoc_version: None,
+        };
+
+        let value = serde_json::to_value(&report).unwrap();
+        assert_eq!(value["tools"]["protoc"]["found"], true);
+        assert_eq!(value["tools"]["protoc"]["path"], "/usr/bin/protoc");
+        assert_eq!(value["tools"]["buf"]["found"], false);
+        assert!(value["tools"]["buf"]["path"].is_null());
+        assert_eq!(value["grpc_tools"], true);
+    }
+
+    #[test]
+    fn protoc_versions_compare_across_numbering_schemes() {
+        assert_eq!(
+            parse_version("libprotoc 3.21.12\n").as_deref(),
+            Some("3.21.12")
+        );
+        assert_eq!(
+            parse_version("mypy 1.8.0 (compiled: yes)").as_deref(),
+            Some("1.8.0")
+        );
+        assert_eq!(parse_version("no digits here"), None);
+
+        assert_eq!(protoc_release("libprotoc 3.21.12"), Some((21, 12)));
+        assert_eq!(protoc_release("libprotoc 25.1"), Some((25, 1)));
+        assert_eq!(protoc_release("libprotoc 26.0-rc1"), Some((26, 0)));
+        assert_eq!(protoc_release("3.21"), Some((21, 0)));
+        assert_eq!(protoc_release("22"), Some((22, 0)));
+        assert_eq!(protoc_release("libprotoc"), None);
+
+        // 3.20 < 3.21 < 22.0 < 25.1
+        assert!(protoc_release("libprotoc 3.20.3") < protoc_release("3.21"));
+        assert!(protoc_release("3.21") < protoc_release("libprotoc 22.0"));
+        assert!(protoc_release("libprotoc 25.1") >= protoc_release("3.25"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn min_protoc_fails_on_older_compiler() {
+        let dir = tempfile::tempdir().unwrap();
+        let protoc = crate::utils::fake_protoc(dir.path(), "echo 'libprotoc 3.20.3'");
+        let pyproject = dir.path().join("pyproject.toml");
+        std::fs::write(
+            &pyproject,
+            "[tool.python_proto_importer]\ninputs = []\n\n[tool.python_proto_importer.doctor]\nmin_protoc = \"3.21\"\n",
+        )
+        .unwrap();
+        let mut cfg = AppConfig::load(Some(&pyproject)).unwrap();
+        assert_eq!(cfg.doctor.min_protoc.as_deref(), Some("3.21"));
+        cfg.protoc_path = Some(protoc);
+
+        assert_eq!(check_min_protoc(&cfg, "3.20").unwrap(), "3.20.3");
+        let err = check_min_protoc(&cfg, "3.21").unwrap_err().to_string();
+        assert!(err.contains("protoc 3.20.3 is older than doctor.min_protoc = \"3.21\""));
+        assert!(check_min_protoc(&cfg, "latest").is_err());
+    }
+}
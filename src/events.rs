@@ -0,0 +1,192 @@
+//! Streaming build/verify events for IDE integration.
+//!
+//! Pipeline steps report progress with [`emit!`], which records a `tracing`
+//! event under the [`TARGET`] target. [`EventsLayer`] picks those up and
+//! appends each one as a line of JSON to the `--events-file`, flushing after
+//! every event so consumers can tail the file while the build runs. Without
+//! the layer installed the events are filtered out and cost nothing.
+//!
+//! Each line carries `kind` plus event-specific fields:
+//!
+//! - `phase_started` / `phase_completed`: `phase`, and on completion `ok` and `duration_ms`
+//! - `file_rewritten`: `path` of a generated file whose imports were rewritten
+//! - `module_verified`: `module`, `ok`, and `error` when the import failed
+//! - `postprocess_pass`: `pass`, its file `count`, and `skipped` when it could not run
+//! - `error`: `message` of the error the command failed with
+
+use serde_json::{Map, Value};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// `tracing` target reserved for pipeline events.
+pub const TARGET: &str = "proto_importer::events";
+
+/// Emit a pipeline event: `emit!("kind", field = value, ...)`.
+macro_rules! emit {
+    ($kind:literal $(, $($fields:tt)*)?) => {
+        tracing::info!(target: $crate::events::TARGET, kind = $kind $(, $($fields)*)?)
+    };
+}
+pub(crate) use emit;
+
+/// Run `f` as the named pipeline phase, bracketed by start/completion events.
+pub(crate) fn phase<T>(name: &str, f: impl FnOnce() -> anyhow::Result<T>) -> anyhow::Result<T> {
+    emit!("phase_started", phase = name);
+    let started = Instant::now();
+    let result = f();
+    emit!(
+        "phase_completed",
+        phase = name,
+        ok = result.is_ok(),
+        duration_ms = started.elapsed().as_millis() as u64
+    );
+    result
+}
+
+/// Layer writing [`TARGET`] events to `writer` as newline-delimited JSON.
+pub struct EventsLayer<W> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write> EventsLayer<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl<S, W> Layer<S> for EventsLayer<W>
+where
+    S: Subscriber,
+    W: Write + Send + 'static,
+{
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if event.metadata().target() != TARGET {
+            return;
+        }
+        let mut fields = JsonFields(Map::new());
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        fields.0.insert("ts_ms".to_string(), Value::from(ts));
+        event.record(&mut fields);
+        let Ok(line) = serde_json::to_string(&Value::Object(fields.0)) else {
+            return;
+        };
+        if let Ok(mut w) = self.writer.lock() {
+            // A broken events file must not abort the build
+            let _ = writeln!(w, "{}", line).and_then(|_| w.flush());
+        }
+    }
+}
+
+/// Collects the fields of an event into a JSON object.
+pub(crate) struct JsonFields(pub(crate) Map<String, Value>);
+
+impl Visit for JsonFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0.insert(
+            field.name().to_string(),
+            Value::from(format!("{:?}", value)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::build;
+    use std::fs;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn build_streams_phase_and_rewrite_events() {
+        let dir = tempdir().unwrap();
+        let out = dir.path().join("generated");
+        fs::create_dir_all(out.join("api")).unwrap();
+        fs::write(out.join("a_pb2.py"), "# a\n").unwrap();
+        fs::write(out.join("api/b_pb2.py"), "import a_pb2 as a__pb2\n").unwrap();
+        let config = dir.path().join("pyproject.toml");
+        fs::write(
+            &config,
+            format!(
+                "[tool.python_proto_importer]\nout = {:?}\n",
+                out.display().to_string()
+            ),
+        )
+        .unwrap();
+
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry().with(EventsLayer::new(capture.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("ordinary log lines are not events");
+            build(config.to_str(), true, true, false, None, false).unwrap();
+        });
+
+        let text = String::from_utf8(capture.0.lock().unwrap().clone()).unwrap();
+        let events: Vec<Value> = text
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let kinds: Vec<String> = events
+            .iter()
+            .map(|e| {
+                let kind = e["kind"].as_str().unwrap();
+                match e["phase"].as_str() {
+                    Some(phase) => format!("{kind}:{phase}"),
+                    None => kind.to_string(),
+                }
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                "phase_started:postprocess",
+                "file_rewritten",
+                "phase_completed:postprocess",
+                "postprocess_pass",
+                "postprocess_pass",
+            ]
+        );
+        assert_eq!(events[1]["path"], "api/b_pb2.py");
+        assert_eq!(events[2]["ok"], true);
+        assert!(events.iter().all(|e| e["ts_ms"].is_u64()));
+    }
+}
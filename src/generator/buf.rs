@@ -0,0 +1,215 @@
+use crate::config::AppConfig;
+use crate::utils::find_tool;
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tempfile::NamedTempFile;
+
+/// Runs `buf generate` for the buf backend.
+///
+/// Code generation is driven by the `buf_gen_yaml` template, with plugin
+/// outputs rooted at `cfg.out` so postprocessing sees the same layout as the
+/// protoc flow. A FileDescriptorSet is produced separately via `buf build`.
+pub struct BufRunner<'a> {
+    cfg: &'a AppConfig,
+}
+
+impl<'a> BufRunner<'a> {
+    pub fn new(cfg: &'a AppConfig) -> Self {
+        Self { cfg }
+    }
+
+    pub fn generate(&self) -> Result<Vec<u8>> {
+        let Some(template) = self.cfg.buf_gen_yaml.as_deref() else {
+            bail!("buf backend requires `buf_gen_yaml` in [tool.python_proto_importer]");
+        };
+        if !template.exists() {
+            bail!("buf_gen_yaml not found: {}", template.display());
+        }
+        let buf = find_tool("BUF", "buf")
+            .context("buf backend selected but `buf` was not found on PATH (or set BUF)")?;
+
+        // ensure output directory exists
+        fs::create_dir_all(&self.cfg.out).with_context(|| {
+            format!(
+                "failed to create output directory: {}",
+                self.cfg.out.display()
+            )
+        })?;
+
+        tracing::info!("running buf generate");
+        let mut cmd = self.generate_command(&buf, template);
+        run(&mut cmd, "buf generate")?;
+
+        self.build_descriptor_set(&buf, false)
+    }
+
+    /// Build the FileDescriptorSet via `buf build`, which keeps source info
+    /// (comments) by default, without generating code.
+    ///
+    /// Only the files of the buf module are included, not the ones they import.
+    pub fn descriptor_set(&self) -> Result<Vec<u8>> {
+        let buf = find_tool("BUF", "buf")
+            .context("buf backend selected but `buf` was not found on PATH (or set BUF)")?;
+        self.build_descriptor_set(&buf, true)
+    }
+
+    fn build_descriptor_set(&self, buf: &Path, exclude_imports: bool) -> Result<Vec<u8>> {
+        // buf picks the image format from the extension, so keep `.binpb`
+        let fds = tempfile::Builder::new()
+            .suffix(".binpb")
+            .tempfile()
+            .context("create temp file for descriptor set")?;
+        tracing::info!("running buf build");
+        let mut cmd = self.build_command(buf, &fds, exclude_imports);
+        run(&mut cmd, "buf build")?;
+
+        let bytes = fs::read(fds.path()).context("failed to read buf build output")?;
+        Ok(bytes)
+    }
+
+    fn generate_command(&self, buf: &Path, template: &Path) -> Command {
+        let mut cmd = Command::new(buf);
+        cmd.arg("generate")
+            .arg("--template")
+            .arg(template)
+            .arg("--output")
+            .arg(&self.cfg.out);
+        cmd
+    }
+
+    fn build_command(&self, buf: &Path, fds: &NamedTempFile, exclude_imports: bool) -> Command {
+        let mut cmd = Command::new(buf);
+        cmd.arg("build").arg("-o").arg(fds.path());
+        if exclude_imports {
+            cmd.arg("--exclude-imports");
+        }
+        cmd
+    }
+}
+
+fn run(cmd: &mut Command, what: &str) -> Result<()> {
+    let output = cmd
+        .output()
+        .with_context(|| format!("failed to run {what}"))?;
+    if !output.status.success() {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!(
+            "{} failed: status {:?}\nstdout:\n{}\nstderr:\n{}",
+            what,
+            output.status.code(),
+            stdout,
+            stderr
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Backend, PostProcess, PyTyped};
+    use std::path::PathBuf;
+    use tempfile::tempdir;
+
+    fn create_test_config() -> AppConfig {
+        AppConfig {
+            backend: Backend::Buf,
+            python_exe: "python3".to_string(),
+            include: vec![PathBuf::from(".")],
+            inputs: vec![],
+            config_dir: PathBuf::new(),
+            out: PathBuf::from("generated"),
+            grpc_out: None,
+            mypy_out: None,
+            generate_mypy: false,
+            generate_mypy_grpc: false,
+            plugins: vec![],
+            protoc_path: None,
+            extra_protoc_args: vec![],
+            generation_timeout_secs: None,
+            timeout_secs: None,
+            require_package: false,
+            warn_unused_includes: false,
+            cleanup_on_failure: true,
+            buf_gen_yaml: Some(PathBuf::from("buf.gen.yaml")),
+            state_dir: PathBuf::from(".proto-importer"),
+            postprocess: PostProcess {
+                relative_imports: true,
+                fix_pyi: true,
+                create_package: true,
+                package_root_init: true,
+                init_reexports: false,
+                namespace_packages: false,
+                emit_package_metadata: false,
+                py_typed: PyTyped::Off,
+                emit_all: false,
+                all_exclude: vec![],
+                exclude_google: true,
+                pyright_header: false,
+                module_suffixes: vec!["_pb2.py".into()],
+                module_transform: None,
+                exclude: vec![],
+            },
+            verify: None,
+            doctor: Default::default(),
+        }
+    }
+
+    #[test]
+    fn missing_buf_gen_yaml_fails() {
+        let mut config = create_test_config();
+        config.buf_gen_yaml = None;
+        let err = BufRunner::new(&config).generate().unwrap_err();
+        assert!(err.to_string().contains("requires `buf_gen_yaml`"));
+    }
+
+    #[test]
+    fn nonexistent_buf_gen_yaml_fails() {
+        let mut config = create_test_config();
+        config.buf_gen_yaml = Some(PathBuf::from("/nonexistent/buf.gen.yaml"));
+        let err = BufRunner::new(&config).generate().unwrap_err();
+        assert!(err.to_string().contains("buf_gen_yaml not found"));
+    }
+
+    #[test]
+    fn generate_command_outputs_to_cfg_out() {
+        let config = create_test_config();
+        let runner = BufRunner::new(&config);
+        let cmd = runner.generate_command(Path::new("buf"), Path::new("buf.gen.yaml"));
+        let args: Vec<_> = cmd.get_args().filter_map(|a| a.to_str()).collect();
+        assert_eq!(
+            args,
+            vec![
+                "generate",
+                "--template",
+                "buf.gen.yaml",
+                "--output",
+                "generated"
+            ]
+        );
+    }
+
+    #[test]
+    fn build_command_writes_descriptor_set() {
+        let config = create_test_config();
+        let runner = BufRunner::new(&config);
+        let dir = tempdir().unwrap();
+        let fds = tempfile::Builder::new()
+            .suffix(".binpb")
+            .tempfile_in(dir.path())
+            .unwrap();
+        let cmd = runner.build_command(Path::new("buf"), &fds, false);
+        let args: Vec<_> = cmd.get_args().filter_map(|a| a.to_str()).collect();
+        assert_eq!(args[0], "build");
+        assert_eq!(args[1], "-o");
+        assert!(args[2].ends_with(".binpb"));
+        assert_eq!(args.len(), 3);
+
+        let cmd = runner.build_command(Path::new("buf"), &fds, true);
+        let args: Vec<_> = cmd.get_args().filter_map(|a| a.to_str()).collect();
+        assert_eq!(args[3], "--exclude-imports");
+    }
+}
@@ -0,0 +1,275 @@
+use crate::config::{AppConfig, Backend};
+use crate::generator::protoc::ProtocRunner;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// File under the state directory recording the fingerprint of the last build.
+pub const CACHE_FILE: &str = "build-cache.json";
+
+/// File under the state directory recording the last successful generation,
+/// written before postprocessing so `build --resume` can pick up from there.
+pub const GENERATION_FILE: &str = "generation.json";
+
+/// FileDescriptorSet saved alongside [`GENERATION_FILE`].
+pub const FDS_FILE: &str = "generated.fds";
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    fingerprint: String,
+}
+
+/// 64-bit FNV-1a; stable across Rust releases, unlike `DefaultHasher`.
+struct Fnv64(u64);
+
+impl Fnv64 {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= u64::from(*b);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    /// Hash a length-prefixed chunk so adjacent fields cannot run together.
+    fn chunk(&mut self, bytes: &[u8]) {
+        self.write(&(bytes.len() as u64).to_le_bytes());
+        self.write(bytes);
+    }
+}
+
+/// Compute the fingerprint of everything that affects generated output.
+///
+/// Covers the generation and postprocess settings (including `out`,
+/// `module_suffixes` and `exclude_google`, and `require_package` so enabling
+/// it re-checks a cached build) plus the path and contents of every
+/// resolved proto input. Verification settings are excluded since they do not
+/// change what is written to `out`.
+pub fn fingerprint(cfg: &AppConfig) -> Result<String> {
+    let mut h = Fnv64::new();
+    let settings = format!(
+        "{:?}",
+        (
+            cfg.backend,
+            &cfg.python_exe,
+            ProtocRunner::new(cfg).protoc(),
+            &cfg.extra_protoc_args,
+            &cfg.include,
+            &cfg.inputs,
+            (&cfg.out, &cfg.grpc_out, &cfg.mypy_out),
+            cfg.generate_mypy,
+            cfg.generate_mypy_grpc,
+            &cfg.plugins,
+            &cfg.buf_gen_yaml,
+            // Nested since Debug is only implemented for tuples of up to 12
+            (&cfg.postprocess, cfg.require_package),
+        )
+    );
+    h.chunk(settings.as_bytes());
+
+    let mut files = input_files(cfg);
+    if let Some(template) = &cfg.buf_gen_yaml {
+        files.push(template.clone());
+    }
+    files.sort();
+    files.dedup();
+    for file in files {
+        let content = fs::read(&file).with_context(|| format!("read {}", file.display()))?;
+        h.chunk(file.to_string_lossy().as_bytes());
+        h.chunk(&content);
+    }
+    Ok(format!("{:016x}", h.0))
+}
+
+/// Proto files that feed generation for the configured backend.
+///
+/// protoc compiles the expanded `inputs`; buf discovers modules itself, so
+/// every `.proto` under the include paths is considered instead.
+fn input_files(cfg: &AppConfig) -> Vec<PathBuf> {
+    match cfg.backend {
+        Backend::Protoc => ProtocRunner::new(cfg).expand_inputs(),
+        Backend::Buf => cfg
+            .include
+            .iter()
+            .flat_map(|inc| WalkDir::new(inc).into_iter().filter_map(Result::ok))
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.into_path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == "proto"))
+            .collect(),
+    }
+}
+
+/// Return the fingerprint stored under `state_dir`, if any.
+pub fn read(state_dir: &Path) -> Option<String> {
+    let text = fs::read_to_string(state_dir.join(CACHE_FILE)).ok()?;
+    let entry: CacheEntry = serde_json::from_str(&text).ok()?;
+    Some(entry.fingerprint)
+}
+
+/// Record `fingerprint` as the state of the last build, creating `state_dir` if needed.
+pub fn write(state_dir: &Path, fingerprint: &str) -> Result<()> {
+    let entry = CacheEntry {
+        fingerprint: fingerprint.to_string(),
+    };
+    fs::create_dir_all(state_dir)
+        .with_context(|| format!("create state dir {}", state_dir.display()))?;
+    let path = state_dir.join(CACHE_FILE);
+    let text = serde_json::to_string_pretty(&entry)?;
+    fs::write(&path, text).with_context(|| format!("write {}", path.display()))
+}
+
+/// Drop the cache and the saved generation so an interrupted build is never
+/// mistaken for a complete one.
+pub fn invalidate(state_dir: &Path) -> Result<()> {
+    for file in [CACHE_FILE, GENERATION_FILE, FDS_FILE] {
+        let path = state_dir.join(file);
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(e).with_context(|| format!("remove {}", path.display()));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Record a successful generation for `fingerprint` together with its FDS.
+///
+/// The FDS is written first, so a present [`GENERATION_FILE`] always refers
+/// to a complete descriptor set.
+pub fn write_generation(state_dir: &Path, fingerprint: &str, fds: &[u8]) -> Result<()> {
+    fs::create_dir_all(state_dir)
+        .with_context(|| format!("create state dir {}", state_dir.display()))?;
+    let fds_path = state_dir.join(FDS_FILE);
+    fs::write(&fds_path, fds).with_context(|| format!("write {}", fds_path.display()))?;
+    let entry = CacheEntry {
+        fingerprint: fingerprint.to_string(),
+    };
+    let path = state_dir.join(GENERATION_FILE);
+    let text = serde_json::to_string_pretty(&entry)?;
+    fs::write(&path, text).with_context(|| format!("write {}", path.display()))
+}
+
+/// Return the FDS of the saved generation if it was made for `fingerprint`.
+pub fn read_generation(state_dir: &Path, fingerprint: &str) -> Option<Vec<u8>> {
+    let text = fs::read_to_string(state_dir.join(GENERATION_FILE)).ok()?;
+    let entry: CacheEntry = serde_json::from_str(&text).ok()?;
+    if entry.fingerprint != fingerprint {
+        return None;
+    }
+    fs::read(state_dir.join(FDS_FILE)).ok()
+}
+
+/// Return the FDS of the last saved generation regardless of its fingerprint.
+pub fn saved_fds(state_dir: &Path) -> Option<Vec<u8>> {
+    fs::read(state_dir.join(FDS_FILE)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{PostProcess, PyTyped};
+    use tempfile::tempdir;
+
+    fn create_test_config(root: &Path) -> AppConfig {
+        AppConfig {
+            backend: Backend::Protoc,
+            python_exe: "python3".to_string(),
+            include: vec![root.join("proto")],
+            inputs: vec![root.join("proto/*.proto").to_string_lossy().into_owned()],
+            config_dir: PathBuf::new(),
+            out: root.join("generated"),
+            grpc_out: None,
+            mypy_out: None,
+            generate_mypy: false,
+            generate_mypy_grpc: false,
+            plugins: vec![],
+            protoc_path: None,
+            extra_protoc_args: vec![],
+            generation_timeout_secs: None,
+            timeout_secs: None,
+            require_package: false,
+            warn_unused_includes: false,
+            cleanup_on_failure: true,
+            buf_gen_yaml: None,
+            state_dir: root.join(".proto-importer"),
+            postprocess: PostProcess {
+                relative_imports: true,
+                fix_pyi: true,
+                create_package: true,
+                package_root_init: true,
+                init_reexports: false,
+                namespace_packages: false,
+                emit_package_metadata: false,
+                py_typed: PyTyped::Off,
+                emit_all: false,
+                all_exclude: vec![],
+                exclude_google: true,
+                pyright_header: false,
+                module_suffixes: vec!["_pb2.py".into()],
+                module_transform: None,
+                exclude: vec![],
+            },
+            verify: None,
+            doctor: Default::default(),
+        }
+    }
+
+    #[test]
+    fn fingerprint_tracks_inputs_and_config() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("proto")).unwrap();
+        fs::write(dir.path().join("proto/a.proto"), "syntax = \"proto3\";\n").unwrap();
+        let cfg = create_test_config(dir.path());
+
+        let base = fingerprint(&cfg).unwrap();
+        assert_eq!(base, fingerprint(&cfg).unwrap());
+
+        let mut changed = cfg.clone();
+        changed.postprocess.module_suffixes.push("_pb2.pyi".into());
+        assert_ne!(base, fingerprint(&changed).unwrap());
+
+        let mut changed = cfg.clone();
+        changed.postprocess.exclude_google = false;
+        assert_ne!(base, fingerprint(&changed).unwrap());
+
+        let mut changed = cfg.clone();
+        changed.out = dir.path().join("other");
+        assert_ne!(base, fingerprint(&changed).unwrap());
+
+        fs::write(dir.path().join("proto/a.proto"), "syntax = \"proto2\";\n").unwrap();
+        assert_ne!(base, fingerprint(&cfg).unwrap());
+    }
+
+    #[test]
+    fn read_write_invalidate_roundtrip() {
+        let dir = tempdir().unwrap();
+        let state = dir.path().join("state");
+        assert_eq!(read(&state), None);
+        write(&state, "abc").unwrap();
+        assert!(state.join(CACHE_FILE).is_file());
+        assert_eq!(read(&state).as_deref(), Some("abc"));
+        invalidate(&state).unwrap();
+        assert_eq!(read(&state), None);
+        // Invalidating a missing cache is not an error
+        invalidate(&state).unwrap();
+    }
+
+    #[test]
+    fn saved_generation_is_tied_to_fingerprint() {
+        let dir = tempdir().unwrap();
+        let state = dir.path().join("state");
+        assert_eq!(read_generation(&state, "abc"), None);
+        write_generation(&state, "abc", b"fds").unwrap();
+        assert_eq!(read_generation(&state, "abc").as_deref(), Some(&b"fds"[..]));
+        assert_eq!(read_generation(&state, "other"), None);
+        invalidate(&state).unwrap();
+        assert_eq!(read_generation(&state, "abc"), None);
+        assert!(!state.join(FDS_FILE).exists());
+    }
+}
@@ -0,0 +1,126 @@
+use anyhow::{Context, Result, bail};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Resolve `proto` and every proto it transitively imports against `include`.
+///
+/// `proto` may be a path to the file or its name relative to an include path.
+/// Imports are read from the `import "...";` lines (including `public` and
+/// `weak` ones) and resolved against the first include path containing them,
+/// like protoc does; imports found in no include path, such as the
+/// well-known types bundled with grpc_tools, are left for protoc to resolve
+/// and are not returned.
+///
+/// # Returns
+///
+/// The resolved proto files ordered by their include-relative name.
+pub fn proto_closure(include: &[PathBuf], proto: &Path) -> Result<Vec<PathBuf>> {
+    let start = include_relative_name(include, proto)?;
+    let re_import = Regex::new(r#"^\s*import\s+(?:(?:public|weak)\s+)?"([^"]+)"\s*;"#).unwrap();
+
+    let mut resolved: BTreeMap<String, PathBuf> = BTreeMap::new();
+    let mut pending = vec![start];
+    while let Some(name) = pending.pop() {
+        if resolved.contains_key(&name) {
+            continue;
+        }
+        let Some(path) = include
+            .iter()
+            .map(|inc| inc.join(&name))
+            .find(|p| p.is_file())
+        else {
+            tracing::debug!(
+                "import {} is not under any include path; left to protoc",
+                name
+            );
+            continue;
+        };
+        let content =
+            fs::read_to_string(&path).with_context(|| format!("read {}", path.display()))?;
+        pending.extend(
+            content
+                .lines()
+                .filter_map(|line| re_import.captures(line))
+                .map(|caps| caps[1].to_string()),
+        );
+        resolved.insert(name, path);
+    }
+    Ok(resolved.into_values().collect())
+}
+
+/// Name of `proto` relative to the include path it lives under.
+fn include_relative_name(include: &[PathBuf], proto: &Path) -> Result<String> {
+    if proto.is_file() {
+        let abs = proto
+            .canonicalize()
+            .with_context(|| format!("resolve {}", proto.display()))?;
+        for inc in include {
+            let Ok(inc_abs) = inc.canonicalize() else {
+                continue;
+            };
+            if let Ok(rel) = abs.strip_prefix(&inc_abs) {
+                return Ok(rel.to_string_lossy().replace('\\', "/"));
+            }
+        }
+        bail!("{} is not under any include path", proto.display());
+    }
+    if include.iter().any(|inc| inc.join(proto).is_file()) {
+        return Ok(proto.to_string_lossy().replace('\\', "/"));
+    }
+    bail!("proto not found: {}", proto.display())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn closure_follows_imports_across_include_paths() {
+        let dir = tempdir().unwrap();
+        let proto = dir.path().join("proto");
+        let vendor = dir.path().join("vendor");
+        fs::create_dir_all(proto.join("api")).unwrap();
+        fs::create_dir_all(vendor.join("common")).unwrap();
+        fs::write(
+            proto.join("api/orders.proto"),
+            "syntax = \"proto3\";\n\
+             import \"common/money.proto\";\n\
+             import public \"api/status.proto\";\n\
+             import \"google/protobuf/timestamp.proto\";\n\
+             // import \"api/unrelated.proto\";\n",
+        )
+        .unwrap();
+        fs::write(proto.join("api/status.proto"), "syntax = \"proto3\";\n").unwrap();
+        fs::write(proto.join("api/unrelated.proto"), "syntax = \"proto3\";\n").unwrap();
+        // A proto imported from two places is resolved once
+        fs::write(
+            vendor.join("common/money.proto"),
+            "syntax = \"proto3\";\nimport \"api/status.proto\";\n",
+        )
+        .unwrap();
+        let include = vec![proto.clone(), vendor.clone()];
+
+        let expected = vec![
+            proto.join("api/orders.proto"),
+            proto.join("api/status.proto"),
+            vendor.join("common/money.proto"),
+        ];
+        assert_eq!(
+            proto_closure(&include, &proto.join("api/orders.proto")).unwrap(),
+            expected
+        );
+        assert_eq!(
+            proto_closure(&include, Path::new("api/orders.proto")).unwrap(),
+            expected
+        );
+        assert!(
+            proto_closure(&include, Path::new("api/missing.proto"))
+                .unwrap_err()
+                .to_string()
+                .contains("proto not found")
+        );
+    }
+}
@@ -1,17 +1,52 @@
 use crate::config::AppConfig;
+use crate::utils::{env_override, output_with_timeout, timed_out};
 use anyhow::{Context, Result};
 use glob::glob;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::ffi::OsString;
 use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::Duration;
 use tempfile::NamedTempFile;
 
+/// One `compile_commands.json`-style record for a compiled proto file.
+#[derive(Debug, Serialize)]
+pub struct ProtoDbEntry {
+    /// Working directory the invocation runs from
+    pub directory: PathBuf,
+    /// The proto file, as passed to protoc
+    pub file: PathBuf,
+    /// Include paths (`--proto_path`), resolved to absolute paths
+    pub include_paths: Vec<PathBuf>,
+    /// Full protoc command line compiling just this file
+    pub arguments: Vec<String>,
+}
+
 pub struct ProtocRunner<'a> {
     cfg: &'a AppConfig,
+    /// The standalone protoc to run, or None for grpc_tools
+    protoc: Option<PathBuf>,
 }
 
 impl<'a> ProtocRunner<'a> {
     pub fn new(cfg: &'a AppConfig) -> Self {
-        Self { cfg }
+        Self::with_protoc_env(cfg, std::env::var_os("PROTOC"))
+    }
+
+    /// [`ProtocRunner::new`] with the value of the `PROTOC` environment
+    /// variable passed in, so tests do not depend on the environment.
+    ///
+    /// A `protoc_path` configured as a path is used as is. Otherwise a
+    /// non-empty `PROTOC` selects the standalone protoc to run, falling back to
+    /// a bare `protoc_path` name looked up on PATH, then to grpc_tools.
+    pub(crate) fn with_protoc_env(cfg: &'a AppConfig, protoc_env: Option<OsString>) -> Self {
+        let protoc = match &cfg.protoc_path {
+            Some(path) if path.components().count() > 1 => Some(path.clone()),
+            configured => env_override(protoc_env).or_else(|| configured.clone()),
+        };
+        Self { cfg, protoc }
     }
 
     pub fn generate(&self) -> Result<Vec<u8>> {
@@ -19,113 +54,68 @@ impl<'a> ProtocRunner<'a> {
         let fds = NamedTempFile::new().context("create temp file for descriptor set")?;
         let fds_path = fds.path().to_path_buf();
 
-        // ensure output directory exists
-        if let Err(e) = std::fs::create_dir_all(&self.cfg.out) {
-            return Err(e).context(format!(
-                "failed to create output directory: {}",
-                self.cfg.out.display()
-            ));
-        }
-
-        // Include paths
-        let mut args: Vec<String> = Vec::new();
-        for inc in &self.cfg.include {
-            args.push(format!("--proto_path={}", inc.display()));
-        }
-        // Inputs (designed to delegate glob to Python, but v0.1 passes strings directly)
-        let inputs = &self.cfg.inputs;
-
-        // python -m grpc_tools.protoc ...
-        // Use specified python_exe (uv/python3)
-        let py = &self.cfg.python_exe;
-        let mut cmd = Command::new(py);
-
-        // Handle uv-specific command structure
-        if py == "uv" {
-            cmd.arg("run").arg("-m").arg("grpc_tools.protoc");
-        } else {
-            cmd.arg("-m").arg("grpc_tools.protoc");
-        }
-        // Ensure protoc plugins installed in the same env are discoverable
-        if let Some(parent_str) = std::path::Path::new(py)
-            .parent()
-            .and_then(|p| p.to_str())
-            .filter(|s| !s.is_empty())
-            .filter(|s| std::path::Path::new(s).exists())
-        {
-            use std::env;
-            let mut buf = std::ffi::OsString::new();
-            buf.push(parent_str);
-            buf.push(if cfg!(windows) { ";" } else { ":" });
-            if let Some(existing) = env::var_os("PATH") {
-                buf.push(existing);
+        // ensure output directories exist
+        for plugin in self.cfg.protoc_plugins() {
+            if let Err(e) = std::fs::create_dir_all(&plugin.out) {
+                return Err(e).context(format!(
+                    "failed to create output directory: {}",
+                    plugin.out.display()
+                ));
             }
-            cmd.env("PATH", buf);
         }
 
-        // Output directories
-        cmd.arg(format!("--python_out={}", self.cfg.out.display()));
-        cmd.arg(format!("--grpc_python_out={}", self.cfg.out.display()));
-
-        // Optional mypy/mypy_grpc output
-        if self.cfg.generate_mypy {
-            cmd.arg(format!("--mypy_out={}", self.cfg.out.display()));
-        }
-        if self.cfg.generate_mypy_grpc {
-            cmd.arg(format!("--mypy_grpc_out={}", self.cfg.out.display()));
-        }
+        self.run(self.command(&fds_path), &fds_path)
+    }
 
-        // Descriptor set output
+    /// Compile the inputs into a FileDescriptorSet with source info (comments),
+    /// without running any code generation plugins.
+    pub fn descriptor_set(&self) -> Result<Vec<u8>> {
+        let fds = NamedTempFile::new().context("create temp file for descriptor set")?;
+        let fds_path = fds.path().to_path_buf();
+        let mut cmd = self.compiler_command();
+        self.add_include_args(&mut cmd);
         cmd.arg("--include_imports");
+        cmd.arg("--include_source_info");
         cmd.arg(format!("--descriptor_set_out={}", fds_path.display()));
+        cmd.args(self.expand_inputs());
+        self.run(cmd, &fds_path)
+    }
 
-        // Add include paths and process inputs
-        for a in &args {
-            cmd.arg(a);
-        }
-        // Expand globs in inputs (v0.1: perform expansion here)
-        // Filter files to only include those under specified include paths
-        for pattern in inputs {
-            let mut matched_any = false;
-            if let Ok(paths) = glob(pattern) {
-                for entry in paths.flatten() {
-                    // Check if the file is under any of the include paths
-                    let should_include = self.cfg.include.iter().any(|inc_path| {
-                        // Try canonical path comparison first (most accurate)
-                        match (entry.canonicalize(), inc_path.canonicalize()) {
-                            (Ok(entry_canonical), Ok(inc_canonical)) => {
-                                entry_canonical.starts_with(&inc_canonical)
-                            },
-                            _ => {
-                                // Fallback to string-based comparison if canonicalization fails
-                                // This handles cases where files/directories don't exist yet
-                                entry.starts_with(inc_path) ||
-                                // Also try relative path normalization
-                                entry.strip_prefix("./").unwrap_or(&entry).starts_with(inc_path.strip_prefix("./").unwrap_or(inc_path))
-                            }
-                        }
-                    });
-
-                    if should_include {
-                        cmd.arg(entry);
-                        matched_any = true;
-                    }
-                }
-            }
-            if !matched_any {
-                // If no files matched after filtering, don't pass anything
-                // This prevents protoc errors for files outside include paths
-                tracing::debug!("Pattern {} matched no files within include paths", pattern);
-            }
-        }
-
-        tracing::info!("running grpc_tools.protoc");
-        let output = cmd.output().context("failed to run grpc_tools.protoc")?;
+    /// Run a protoc invocation and read back the descriptor set it wrote to `fds_path`.
+    fn run(&self, mut cmd: Command, fds_path: &Path) -> Result<Vec<u8>> {
+        let label = self.label();
+        let timeout = self
+            .cfg
+            .generation_timeout_secs
+            .map(Duration::from_secs)
+            .or(self.cfg.timeout());
+
+        tracing::info!("running {}", label);
+        let Some(output) = output_with_timeout(&mut cmd, timeout)
+            .with_context(|| format!("failed to run {}", label))?
+        else {
+            anyhow::bail!(
+                "{} {} and was killed (configured plugins: {})",
+                label,
+                timed_out(timeout),
+                self.plugins().join(", ")
+            );
+        };
         if !output.status.success() {
             let stdout = String::from_utf8_lossy(&output.stdout);
             let stderr = String::from_utf8_lossy(&output.stderr);
+            if let Some(plugin) = self.failed_plugin(&stderr) {
+                anyhow::bail!(
+                    "protoc plugin `{}` (protoc-gen-{}) failed: status {:?}\nstderr:\n{}",
+                    plugin,
+                    plugin,
+                    output.status.code(),
+                    stderr
+                );
+            }
             anyhow::bail!(
-                "grpc_tools.protoc failed: status {:?}\nstdout:\n{}\nstderr:\n{}",
+                "{} failed: status {:?}\nstdout:\n{}\nstderr:\n{}",
+                label,
                 output.status.code(),
                 stdout,
                 stderr
@@ -133,37 +123,124 @@ impl<'a> ProtocRunner<'a> {
         }
 
         // Read and return FDS
-        let bytes = fs::read(&fds_path).context("failed to read descriptor_set_out")?;
+        let bytes = fs::read(fds_path).context("failed to read descriptor_set_out")?;
         Ok(bytes)
     }
 
-    // Helper method for testing - allows inspection of command without execution
-    #[cfg(test)]
-    pub fn build_command(&self) -> Result<(Command, tempfile::NamedTempFile)> {
-        let fds = NamedTempFile::new().context("create temp file for descriptor set")?;
-        let fds_path = fds.path().to_path_buf();
+    /// Names of the plugins this invocation runs, matching their `--<name>_out` flags.
+    fn plugins(&self) -> Vec<String> {
+        self.cfg
+            .protoc_plugins()
+            .into_iter()
+            .map(|p| p.name)
+            .collect()
+    }
 
-        let mut args: Vec<String> = Vec::new();
-        for inc in &self.cfg.include {
-            args.push(format!("--proto_path={}", inc.display()));
+    /// Run the compiler with `--version`, e.g. `libprotoc 3.21.12`.
+    ///
+    /// This is the protoc generation runs: `protoc_path` (or `PROTOC`) when
+    /// set, otherwise the one bundled with grpc_tools.
+    pub fn version(&self) -> Result<String> {
+        let mut cmd = self.compiler_command();
+        cmd.arg("--version");
+        let timeout = self.cfg.timeout();
+        let Some(output) = output_with_timeout(&mut cmd, timeout)
+            .with_context(|| format!("failed to run {} --version", self.label()))?
+        else {
+            anyhow::bail!("{} --version {}", self.label(), timed_out(timeout));
+        };
+        if !output.status.success() {
+            anyhow::bail!(
+                "{} --version failed: {}",
+                self.label(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
         }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
 
-        let py = &self.cfg.python_exe;
-        let mut cmd = Command::new(py);
+    /// Identify the configured plugin that protoc blamed in `stderr`.
+    ///
+    /// protoc prefixes plugin errors with the output flag, e.g.
+    /// `--mypy_out: protoc-gen-mypy: Plugin failed with status code 1.`
+    fn failed_plugin(&self, stderr: &str) -> Option<String> {
+        self.plugins()
+            .into_iter()
+            .find(|p| stderr.contains(&format!("--{}_out: ", p)))
+    }
 
-        // Handle uv-specific command structure
-        if py == "uv" {
-            cmd.arg("run").arg("-m").arg("grpc_tools.protoc");
-        } else {
-            cmd.arg("-m").arg("grpc_tools.protoc");
+    /// The standalone protoc to run, or None for grpc_tools; see
+    /// [`ProtocRunner::with_protoc_env`].
+    pub fn protoc(&self) -> Option<PathBuf> {
+        self.protoc.clone()
+    }
+
+    /// Human-readable name of the compiler being invoked, for logs and errors.
+    fn label(&self) -> String {
+        match &self.protoc {
+            Some(p) => p.display().to_string(),
+            None => "grpc_tools.protoc".to_string(),
         }
+    }
+
+    /// Assemble the full protoc invocation writing the descriptor set to `fds_path`.
+    fn command(&self, fds_path: &Path) -> Command {
+        let mut cmd = self.base_command();
+
+        // Descriptor set output
+        cmd.arg("--include_imports");
+        cmd.arg(format!("--descriptor_set_out={}", fds_path.display()));
+
+        // Expand globs in inputs (cwd-relative and include-relative)
+        for entry in self.expand_inputs() {
+            cmd.arg(entry);
+        }
+        cmd
+    }
 
-        // PATH handling
-        if let Some(parent_str) = std::path::Path::new(py)
+    /// The compiler, plugin outputs and include paths, without inputs.
+    fn base_command(&self) -> Command {
+        let mut cmd = self.compiler_command();
+
+        // Plugin outputs and options
+        for plugin in self.cfg.protoc_plugins() {
+            cmd.arg(format!("--{}_out={}", plugin.name, plugin.out.display()));
+            for opt in &plugin.opt {
+                cmd.arg(format!("--{}_opt={}", plugin.name, opt));
+            }
+        }
+
+        self.add_include_args(&mut cmd);
+        cmd
+    }
+
+    /// The compiler alone, without any arguments beyond how it is launched.
+    ///
+    /// With `protoc_path` set the standalone binary is run directly; otherwise
+    /// `python -m grpc_tools.protoc` is used via `python_exe`.
+    fn compiler_command(&self) -> Command {
+        let (mut cmd, exe) = match &self.protoc {
+            Some(protoc) => (Command::new(protoc), protoc.as_path()),
+            None => {
+                // python -m grpc_tools.protoc ...
+                // Use specified python_exe (uv/python3)
+                let py = &self.cfg.python_exe;
+                let mut cmd = Command::new(py);
+                // Handle uv-specific command structure
+                if py == "uv" {
+                    cmd.arg("run").arg("-m").arg("grpc_tools.protoc");
+                } else {
+                    cmd.arg("-m").arg("grpc_tools.protoc");
+                }
+                (cmd, Path::new(py))
+            }
+        };
+        // Ensure protoc plugins installed next to the executable are discoverable
+        if let Some(parent_str) = exe
             .parent()
             .and_then(|p| p.to_str())
             .filter(|s| !s.is_empty())
-            .filter(|s| std::path::Path::new(s).exists())
+            .filter(|s| Path::new(s).exists())
         {
             use std::env;
             let mut buf = std::ffi::OsString::new();
@@ -174,50 +251,152 @@ impl<'a> ProtocRunner<'a> {
             }
             cmd.env("PATH", buf);
         }
+        cmd
+    }
 
-        // Output arguments
-        cmd.arg(format!("--python_out={}", self.cfg.out.display()));
-        cmd.arg(format!("--grpc_python_out={}", self.cfg.out.display()));
-
-        if self.cfg.generate_mypy {
-            cmd.arg(format!("--mypy_out={}", self.cfg.out.display()));
-        }
-        if self.cfg.generate_mypy_grpc {
-            cmd.arg(format!("--mypy_grpc_out={}", self.cfg.out.display()));
+    /// Append the include paths and user-supplied flags.
+    fn add_include_args(&self, cmd: &mut Command) {
+        // Include paths
+        for inc in &self.cfg.include {
+            cmd.arg(format!("--proto_path={}", inc.display()));
         }
 
-        cmd.arg("--include_imports");
-        cmd.arg(format!("--descriptor_set_out={}", fds_path.display()));
+        // User-supplied flags, passed through verbatim
+        cmd.args(&self.cfg.extra_protoc_args);
+    }
 
-        for a in &args {
-            cmd.arg(a);
-        }
+    /// Describe each compiled proto like a `compile_commands.json` entry.
+    ///
+    /// Every input gets its resolved include paths and the per-file protoc
+    /// invocation, so proto language servers can mirror the build setup.
+    pub fn proto_db(&self) -> Result<Vec<ProtoDbEntry>> {
+        let directory = std::env::current_dir().context("failed to get current directory")?;
+        let include_paths: Vec<PathBuf> = self
+            .cfg
+            .include
+            .iter()
+            .map(|inc| inc.canonicalize().unwrap_or_else(|_| directory.join(inc)))
+            .collect();
+        let base = self.base_command();
+        let mut prefix = vec![base.get_program().to_string_lossy().into_owned()];
+        prefix.extend(base.get_args().map(|a| a.to_string_lossy().into_owned()));
+
+        Ok(self
+            .expand_inputs()
+            .into_iter()
+            .map(|file| {
+                let mut arguments = prefix.clone();
+                arguments.push(file.to_string_lossy().into_owned());
+                ProtoDbEntry {
+                    directory: directory.clone(),
+                    file,
+                    include_paths: include_paths.clone(),
+                    arguments,
+                }
+            })
+            .collect())
+    }
 
-        // Process inputs with glob expansion and filtering
+    /// Write [`Self::proto_db`] as a JSON array to `path`.
+    pub fn write_proto_db(&self, path: &Path) -> Result<usize> {
+        let entries = self.proto_db()?;
+        let json = serde_json::to_string_pretty(&entries)?;
+        fs::write(path, json).with_context(|| format!("write {}", path.display()))?;
+        Ok(entries.len())
+    }
+
+    /// Expand `inputs` globs into the proto files to pass to protoc.
+    ///
+    /// Each pattern is resolved against the current directory first (keeping only
+    /// files under an include path), then against each include directory in turn so
+    /// that include-relative patterns like `api/*.proto` also match. Matches are
+    /// deduplicated across includes, preserving first-seen order.
+    pub(crate) fn expand_inputs(&self) -> Vec<PathBuf> {
+        let mut seen: HashSet<PathBuf> = HashSet::new();
+        let mut files: Vec<PathBuf> = Vec::new();
         for pattern in &self.cfg.inputs {
-            if let Ok(paths) = glob(pattern) {
-                for entry in paths.flatten() {
-                    let should_include = self.cfg.include.iter().any(|inc_path| {
-                        match (entry.canonicalize(), inc_path.canonicalize()) {
-                            (Ok(entry_canonical), Ok(inc_canonical)) => {
-                                entry_canonical.starts_with(&inc_canonical)
-                            }
-                            _ => {
-                                entry.starts_with(inc_path)
-                                    || entry.strip_prefix("./").unwrap_or(&entry).starts_with(
-                                        inc_path.strip_prefix("./").unwrap_or(inc_path),
-                                    )
-                            }
-                        }
-                    });
-
-                    if should_include {
-                        cmd.arg(&entry);
-                    }
+            let matches = self.expand_pattern(pattern);
+            if matches.is_empty() {
+                // If no files matched after filtering, don't pass anything
+                // This prevents protoc errors for files outside include paths
+                tracing::debug!("Pattern {} matched no files within include paths", pattern);
+            }
+            for entry in matches {
+                let key = entry.canonicalize().unwrap_or_else(|_| entry.clone());
+                if seen.insert(key) {
+                    files.push(entry);
                 }
             }
         }
+        files
+    }
+
+    /// The `inputs` patterns that match no file under the include paths.
+    pub(crate) fn unmatched_inputs(&self) -> Vec<&str> {
+        self.cfg
+            .inputs
+            .iter()
+            .filter(|pattern| self.expand_pattern(pattern).is_empty())
+            .map(String::as_str)
+            .collect()
+    }
 
+    /// Files matching one `inputs` pattern, possibly with duplicates.
+    ///
+    /// Relative patterns are tried both from the config directory and from
+    /// each include path.
+    fn expand_pattern(&self, pattern: &str) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        let rooted = self.cfg.config_dir.join(pattern);
+        if let Some(rooted) = rooted.to_str()
+            && let Ok(paths) = glob(rooted)
+        {
+            // Check if the file is under any of the include paths
+            files.extend(paths.flatten().filter(|entry| self.is_under_include(entry)));
+        }
+        // Absolute patterns are already fully resolved above
+        let include_relative = if Path::new(pattern).is_absolute() {
+            &[][..]
+        } else {
+            &self.cfg.include[..]
+        };
+        for inc in include_relative {
+            let joined = inc.join(pattern);
+            let Some(inc_pattern) = joined.to_str() else {
+                continue;
+            };
+            if let Ok(paths) = glob(inc_pattern) {
+                files.extend(paths.flatten());
+            }
+        }
+        files
+    }
+
+    fn is_under_include(&self, entry: &Path) -> bool {
+        self.cfg.include.iter().any(|inc_path| {
+            // Try canonical path comparison first (most accurate)
+            match (entry.canonicalize(), inc_path.canonicalize()) {
+                (Ok(entry_canonical), Ok(inc_canonical)) => {
+                    entry_canonical.starts_with(&inc_canonical)
+                }
+                _ => {
+                    // Fallback to string-based comparison if canonicalization fails
+                    // This handles cases where files/directories don't exist yet
+                    entry.starts_with(inc_path)
+                        || entry
+                            .strip_prefix("./")
+                            .unwrap_or(entry)
+                            .starts_with(inc_path.strip_prefix("./").unwrap_or(inc_path))
+                }
+            }
+        })
+    }
+
+    // Helper method for testing - allows inspection of command without execution
+    #[cfg(test)]
+    pub fn build_command(&self) -> Result<(Command, tempfile::NamedTempFile)> {
+        let fds = NamedTempFile::new().context("create temp file for descriptor set")?;
+        let cmd = self.command(fds.path());
         Ok((cmd, fds))
     }
 }
@@ -225,7 +404,9 @@ impl<'a> ProtocRunner<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{AppConfig, Backend, PostProcess};
+    use crate::config::{AppConfig, Backend, PostProcess, PyTyped};
+    #[cfg(unix)]
+    use crate::utils::fake_protoc;
     use std::fs;
     use tempfile::tempdir;
 
@@ -235,32 +416,60 @@ mod tests {
             python_exe: "python3".to_string(),
             include: vec![std::path::PathBuf::from(".")],
             inputs: vec!["**/*.proto".to_string()],
+            config_dir: PathBuf::new(),
             out: std::path::PathBuf::from("generated"),
+            grpc_out: None,
+            mypy_out: None,
             generate_mypy: false,
             generate_mypy_grpc: false,
+            plugins: vec![],
+            protoc_path: None,
+            extra_protoc_args: vec![],
+            generation_timeout_secs: None,
+            timeout_secs: None,
+            require_package: false,
+            warn_unused_includes: false,
+            cleanup_on_failure: true,
+            buf_gen_yaml: None,
+            state_dir: PathBuf::from(".proto-importer"),
             postprocess: PostProcess {
                 relative_imports: true,
                 fix_pyi: true,
                 create_package: true,
+                package_root_init: true,
+                init_reexports: false,
+                namespace_packages: false,
+                emit_package_metadata: false,
+                py_typed: PyTyped::Off,
+                emit_all: false,
+                all_exclude: vec![],
                 exclude_google: true,
                 pyright_header: false,
                 module_suffixes: vec!["_pb2.py".into()],
+                module_transform: None,
+                exclude: vec![],
             },
             verify: None,
+            doctor: Default::default(),
         }
     }
 
+    /// A runner that ignores any `PROTOC` in the test environment.
+    fn runner(config: &AppConfig) -> ProtocRunner<'_> {
+        ProtocRunner::with_protoc_env(config, None)
+    }
+
     #[test]
     fn new_runner() {
         let config = create_test_config();
-        let runner = ProtocRunner::new(&config);
+        let runner = runner(&config);
         assert_eq!(runner.cfg.backend as u8, Backend::Protoc as u8);
     }
 
     #[test]
     fn build_command_basic_args() {
         let config = create_test_config();
-        let runner = ProtocRunner::new(&config);
+        let runner = runner(&config);
         let (cmd, _temp) = runner.build_command().unwrap();
 
         let cmd_str = format!("{:?}", cmd);
@@ -280,7 +489,7 @@ mod tests {
         config.generate_mypy = true;
         config.generate_mypy_grpc = true;
 
-        let runner = ProtocRunner::new(&config);
+        let runner = runner(&config);
         let (cmd, _temp) = runner.build_command().unwrap();
 
         let cmd_str = format!("{:?}", cmd);
@@ -296,7 +505,7 @@ mod tests {
             std::path::PathBuf::from("common"),
         ];
 
-        let runner = ProtocRunner::new(&config);
+        let runner = runner(&config);
         let (cmd, _temp) = runner.build_command().unwrap();
 
         let cmd_str = format!("{:?}", cmd);
@@ -309,13 +518,213 @@ mod tests {
         let mut config = create_test_config();
         config.python_exe = "uv".to_string();
 
-        let runner = ProtocRunner::new(&config);
+        let runner = runner(&config);
         let (cmd, _temp) = runner.build_command().unwrap();
 
         let cmd_str = format!("{:?}", cmd);
         assert!(cmd_str.contains("uv"));
     }
 
+    #[test]
+    fn proto_db_lists_each_input_with_includes() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("proto/api")).unwrap();
+        fs::create_dir_all(root.join("common")).unwrap();
+        fs::write(root.join("proto/api/a.proto"), "").unwrap();
+        fs::write(root.join("proto/api/b.proto"), "").unwrap();
+
+        let mut config = create_test_config();
+        config.include = vec![root.join("proto"), root.join("common")];
+        config.inputs = vec![format!("{}/proto/api/*.proto", root.display())];
+        let runner = runner(&config);
+        let out = root.join("db.json");
+        assert_eq!(runner.write_proto_db(&out).unwrap(), 2);
+
+        let db: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&out).unwrap()).unwrap();
+        let entries = db.as_array().unwrap();
+        let files: Vec<&str> = entries
+            .iter()
+            .map(|e| e["file"].as_str().unwrap())
+            .collect();
+        assert!(files[0].ends_with("a.proto"));
+        assert!(files[1].ends_with("b.proto"));
+
+        let proto = root.join("proto").canonicalize().unwrap();
+        let common = root.join("common").canonicalize().unwrap();
+        for entry in entries {
+            let includes: Vec<&str> = entry["include_paths"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|i| i.as_str().unwrap())
+                .collect();
+            assert_eq!(
+                includes,
+                vec![proto.to_str().unwrap(), common.to_str().unwrap()]
+            );
+            let args = entry["arguments"].as_array().unwrap();
+            assert_eq!(args[0], "python3");
+            assert_eq!(args.last().unwrap(), &entry["file"]);
+            assert!(
+                !args
+                    .iter()
+                    .any(|a| a.as_str().unwrap().starts_with("--descriptor_set_out"))
+            );
+        }
+    }
+
+    #[test]
+    fn build_command_renders_configured_plugins() {
+        let mut config = create_test_config();
+        config.generate_mypy = true;
+        config.plugins = vec![
+            crate::config::Plugin {
+                name: "connect-python".to_string(),
+                out: PathBuf::from("connect"),
+                opt: vec!["naming=pep8".to_string(), "grpc".to_string()],
+            },
+            crate::config::Plugin {
+                name: "doc".to_string(),
+                out: PathBuf::from("/abs/docs"),
+                opt: vec![],
+            },
+        ];
+
+        let runner = runner(&config);
+        let (cmd, _temp) = runner.build_command().unwrap();
+        let args: Vec<_> = cmd.get_args().filter_map(|a| a.to_str()).collect();
+        let outs: Vec<&str> = args
+            .iter()
+            .copied()
+            .filter(|a| a.contains("_out=") || a.contains("_opt="))
+            .filter(|a| !a.starts_with("--descriptor_set_out"))
+            .collect();
+        assert_eq!(
+            outs,
+            vec![
+                "--python_out=generated",
+                "--grpc_python_out=generated",
+                "--mypy_out=generated",
+                "--connect-python_out=generated/connect",
+                "--connect-python_opt=naming=pep8",
+                "--connect-python_opt=grpc",
+                "--doc_out=/abs/docs",
+            ]
+        );
+    }
+
+    #[test]
+    fn build_command_passes_extra_args_before_inputs() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.proto"), "syntax = \"proto3\";").unwrap();
+        let mut config = create_test_config();
+        config.include = vec![dir.path().to_path_buf()];
+        config.inputs = vec![format!("{}/*.proto", dir.path().display())];
+        config.extra_protoc_args = vec![
+            "--experimental_allow_proto3_optional".to_string(),
+            "-Ivendor/proto".to_string(),
+        ];
+
+        let runner = runner(&config);
+        let (cmd, _temp) = runner.build_command().unwrap();
+        let args: Vec<_> = cmd.get_args().filter_map(|a| a.to_str()).collect();
+        let pos = |needle: &str| args.iter().position(|a| a.starts_with(needle)).unwrap();
+
+        let extra = pos("--experimental_allow_proto3_optional");
+        assert_eq!(args[extra + 1], "-Ivendor/proto");
+        assert!(pos("--grpc_python_out=") < extra);
+        assert!(extra < pos(&dir.path().join("a.proto").display().to_string()));
+    }
+
+    #[test]
+    fn build_command_standalone_protoc() {
+        let mut config = create_test_config();
+        config.protoc_path = Some(std::path::PathBuf::from("/usr/local/bin/protoc"));
+
+        let runner = runner(&config);
+        let (cmd, _temp) = runner.build_command().unwrap();
+
+        assert_eq!(cmd.get_program(), "/usr/local/bin/protoc");
+        let args: Vec<_> = cmd.get_args().filter_map(|a| a.to_str()).collect();
+        assert!(!args.contains(&"-m"));
+        assert!(!args.contains(&"grpc_tools.protoc"));
+        assert_eq!(args[0], "--python_out=generated");
+        assert!(args.contains(&"--grpc_python_out=generated"));
+        assert!(args.iter().any(|a| a.starts_with("--descriptor_set_out=")));
+        assert_eq!(runner.label(), "/usr/local/bin/protoc");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn protoc_env_selects_the_compiler() {
+        let dir = tempdir().unwrap();
+        let fake = fake_protoc(dir.path(), "echo 'libprotoc 9.9.0'");
+        let env = || Some(OsString::from(&fake));
+        let mut config = create_test_config();
+
+        // Overrides grpc_tools and a bare name looked up on PATH
+        for protoc_path in [None, Some(PathBuf::from("protoc"))] {
+            config.protoc_path = protoc_path;
+            let runner = ProtocRunner::with_protoc_env(&config, env());
+            assert_eq!(runner.version().unwrap(), "libprotoc 9.9.0");
+            let (cmd, _temp) = runner.build_command().unwrap();
+            assert_eq!(cmd.get_program(), fake.as_os_str());
+        }
+
+        // A configured path wins; an empty PROTOC is ignored
+        config.protoc_path = Some(PathBuf::from("/usr/local/bin/protoc"));
+        let runner = ProtocRunner::with_protoc_env(&config, env());
+        assert_eq!(runner.protoc(), config.protoc_path);
+        config.protoc_path = None;
+        let runner = ProtocRunner::with_protoc_env(&config, Some(OsString::new()));
+        assert_eq!(runner.label(), "grpc_tools.protoc");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn generate_kills_slow_plugin_after_timeout() {
+        let dir = tempdir().unwrap();
+        let mut config = create_test_config();
+        config.out = dir.path().join("out");
+        config.generate_mypy = true;
+        // A plugin that hangs keeps protoc from ever returning
+        config.protoc_path = Some(fake_protoc(dir.path(), "sleep 30"));
+        config.generation_timeout_secs = Some(1);
+
+        let started = std::time::Instant::now();
+        let err = runner(&config).generate().unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        let msg = err.to_string();
+        assert!(msg.contains("timed out after 1 seconds"), "{msg}");
+        assert!(
+            msg.contains("configured plugins: python, grpc_python, mypy"),
+            "{msg}"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn generate_names_crashed_plugin() {
+        let dir = tempdir().unwrap();
+        let mut config = create_test_config();
+        config.out = dir.path().join("out");
+        config.generate_mypy = true;
+        config.generate_mypy_grpc = true;
+        config.protoc_path = Some(fake_protoc(
+            dir.path(),
+            "echo '--mypy_grpc_out: protoc-gen-mypy_grpc: Plugin killed by signal 11.' >&2\nexit 1",
+        ));
+
+        let err = runner(&config).generate().unwrap_err();
+        let msg = err.to_string();
+        assert!(
+            msg.contains("protoc plugin `mypy_grpc` (protoc-gen-mypy_grpc) failed"),
+            "{msg}"
+        );
+    }
+
     #[test]
     fn include_path_filtering() {
         let dir = tempdir().unwrap();
@@ -332,7 +741,7 @@ mod tests {
         config.include = vec![proto_dir.clone()];
         config.inputs = vec![format!("{}/**/*.proto", dir.path().display())];
 
-        let runner = ProtocRunner::new(&config);
+        let runner = runner(&config);
         let (cmd, _temp) = runner.build_command().unwrap();
 
         let cmd_str = format!("{:?}", cmd);
@@ -356,7 +765,7 @@ mod tests {
         let original_dir = std::env::current_dir().unwrap();
         std::env::set_current_dir(&dir).unwrap();
 
-        let runner = ProtocRunner::new(&config);
+        let runner = runner(&config);
         let (cmd, _temp) = runner.build_command().unwrap();
 
         let cmd_str = format!("{:?}", cmd);
@@ -365,12 +774,39 @@ mod tests {
         std::env::set_current_dir(&original_dir).unwrap();
     }
 
+    #[test]
+    fn include_relative_inputs() {
+        let dir = tempdir().unwrap();
+        let proto_dir = dir.path().join("proto");
+        fs::create_dir_all(proto_dir.join("api")).unwrap();
+        fs::write(proto_dir.join("api/service.proto"), "syntax = \"proto3\";").unwrap();
+        fs::write(proto_dir.join("api/types.proto"), "syntax = \"proto3\";").unwrap();
+
+        let mut config = create_test_config();
+        // Both includes resolve to the same directory, so matches must be deduplicated
+        config.include = vec![proto_dir.clone(), dir.path().join("proto/../proto")];
+        config.inputs = vec!["api/*.proto".to_string()];
+
+        let runner = runner(&config);
+        let (cmd, _temp) = runner.build_command().unwrap();
+
+        let proto_args: Vec<String> = cmd
+            .get_args()
+            .filter_map(|a| a.to_str())
+            .filter(|s| s.ends_with(".proto"))
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(proto_args.len(), 2);
+        assert!(proto_args.iter().any(|s| s.ends_with("api/service.proto")));
+        assert!(proto_args.iter().any(|s| s.ends_with("api/types.proto")));
+    }
+
     #[test]
     fn empty_glob_pattern() {
         let mut config = create_test_config();
         config.inputs = vec!["nonexistent/**/*.proto".to_string()];
 
-        let runner = ProtocRunner::new(&config);
+        let runner = runner(&config);
         let (cmd, _temp) = runner.build_command().unwrap();
 
         // Check command arguments - should not contain paths ending with .proto
@@ -391,7 +827,7 @@ mod tests {
         let mut config = create_test_config();
         config.python_exe = "/nonexistent/python".to_string();
 
-        let runner = ProtocRunner::new(&config);
+        let runner = runner(&config);
         // Should not panic, should handle gracefully
         let result = runner.build_command();
         assert!(result.is_ok());
@@ -0,0 +1,98 @@
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use walkdir::WalkDir;
+
+/// The files and directories under a set of output roots, taken before a
+/// generator runs so that whatever a failed run wrote can be removed again.
+pub struct OutputSnapshot {
+    roots: Vec<PathBuf>,
+    files: BTreeMap<PathBuf, (Option<SystemTime>, u64)>,
+    dirs: BTreeSet<PathBuf>,
+}
+
+impl OutputSnapshot {
+    /// Record the current state of `roots`, which need not exist yet.
+    pub fn take(roots: Vec<PathBuf>) -> Self {
+        let (files, dirs) = scan(&roots);
+        Self { roots, files, dirs }
+    }
+
+    /// Remove the files created or modified since the snapshot was taken, then
+    /// the directories created since then that are left empty.
+    ///
+    /// Overwritten files are removed rather than kept, since their previous
+    /// content is already gone and the new one may be truncated.
+    ///
+    /// # Returns
+    ///
+    /// The number of files removed.
+    pub fn remove_new_files(&self) -> Result<usize> {
+        let (files, dirs) = scan(&self.roots);
+        let mut removed = 0usize;
+        for (path, stamp) in &files {
+            if self.files.get(path) == Some(stamp) {
+                continue;
+            }
+            fs::remove_file(path).with_context(|| format!("remove {}", path.display()))?;
+            tracing::debug!("removed partial output {}", path.display());
+            removed += 1;
+        }
+        // Deepest first, so emptied parents can go too; non-empty ones stay
+        for dir in dirs.iter().rev().filter(|d| !self.dirs.contains(*d)) {
+            let _ = fs::remove_dir(dir);
+        }
+        Ok(removed)
+    }
+}
+
+type Scan = (
+    BTreeMap<PathBuf, (Option<SystemTime>, u64)>,
+    BTreeSet<PathBuf>,
+);
+
+fn scan(roots: &[PathBuf]) -> Scan {
+    let mut files = BTreeMap::new();
+    let mut dirs = BTreeSet::new();
+    for entry in roots
+        .iter()
+        .flat_map(|root| WalkDir::new(root).into_iter().filter_map(Result::ok))
+    {
+        if entry.file_type().is_dir() {
+            dirs.insert(entry.into_path());
+        } else if let Ok(meta) = entry.metadata() {
+            files.insert(entry.into_path(), (meta.modified().ok(), meta.len()));
+        }
+    }
+    (files, dirs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn removes_only_what_changed_since_the_snapshot() {
+        let dir = tempdir().unwrap();
+        let out = dir.path().join("generated");
+        fs::create_dir_all(out.join("old")).unwrap();
+        fs::write(out.join("old/keep_pb2.py"), "x = 1\n").unwrap();
+        fs::write(out.join("old/stale_pb2.py"), "x = 1\n").unwrap();
+
+        let snapshot = OutputSnapshot::take(vec![out.clone()]);
+        fs::create_dir_all(out.join("api/v1")).unwrap();
+        fs::write(out.join("api/v1/a_pb2.py"), "partial").unwrap();
+        fs::write(out.join("old/stale_pb2.py"), "x = 12\n").unwrap();
+        fs::write(out.join("old/new_pb2.py"), "").unwrap();
+
+        assert_eq!(snapshot.remove_new_files().unwrap(), 3);
+        assert!(out.join("old/keep_pb2.py").is_file());
+        assert!(!out.join("old/stale_pb2.py").exists());
+        assert!(!out.join("old/new_pb2.py").exists());
+        assert!(!out.join("api").exists());
+        assert!(out.join("old").is_dir());
+    }
+}
@@ -20,6 +20,31 @@
 //! ```
 //!
 //! Then run: `proto-importer build`
+//!
+//! ## Library Usage
+//!
+//! The pipeline can also be driven without the CLI. [`build`], [`check`] and
+//! [`clean`] take a loaded [`AppConfig`] and return structured results; import
+//! failures beyond what the config tolerates come back as a [`VerifyFailed`]
+//! error listing the failing modules.
+//!
+//! ```no_run
+//! use python_proto_importer::{AppConfig, VerifyFailed};
+//!
+//! let cfg = AppConfig::load(None)?;
+//! match python_proto_importer::build(&cfg) {
+//!     Ok(report) => println!("{} modules verified", report.modules_verified),
+//!     Err(e) => match e.downcast_ref::<VerifyFailed>() {
+//!         Some(failed) => {
+//!             for f in &failed.failures {
+//!                 eprintln!("{}: {}", f.module, f.error);
+//!             }
+//!         }
+//!         None => return Err(e),
+//!     },
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
 
 #![cfg_attr(feature = "python", allow(clippy::useless_conversion))]
 
@@ -28,17 +53,43 @@ pub(crate) mod cli;
 pub mod commands;
 pub mod config;
 pub mod doctor;
+pub(crate) mod events;
 pub(crate) mod generator {
+    pub mod buf;
+    pub mod cache;
+    pub mod deps;
     pub mod protoc;
+    pub mod snapshot;
 }
 pub mod postprocess;
 pub(crate) mod python;
 pub(crate) mod utils;
 pub mod verification;
 
-// Re-export main CLI functions
 use anyhow::Result;
 
+pub use commands::{BuildOptions, BuildReport, PassSummary};
+pub use config::AppConfig;
+pub use verification::{FailureKind, ModuleFailure, VerifyFailed, VerifyReport};
+
+/// Generate, postprocess and verify the configured target.
+///
+/// Equivalent to `proto-importer build` with default flags; use
+/// [`commands::build_with`] to pass [`BuildOptions`].
+pub fn build(cfg: &AppConfig) -> Result<BuildReport> {
+    commands::build_with(cfg, &BuildOptions::default())
+}
+
+/// Verify the existing generated code, like `proto-importer check`.
+pub fn check(cfg: &AppConfig) -> Result<VerifyReport> {
+    commands::check_with(cfg)
+}
+
+/// Remove generated files from `out` and the `state_dir`, like `proto-importer clean --yes`.
+pub fn clean(cfg: &AppConfig) -> Result<()> {
+    commands::clean_with(cfg, true, false)
+}
+
 /// Main entry point for CLI usage.
 ///
 /// This function initializes the CLI application and processes command-line arguments
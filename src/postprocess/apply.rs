@@ -1,10 +1,12 @@
+use super::rel_imports::{ScannedFile, scan_tree};
 use anyhow::{Context, Result};
 #[allow(unused_imports)]
 use prost_reflect::DescriptorPool;
 use regex::Regex;
+use std::collections::HashMap;
 use std::fs;
-use std::io::Write;
 use std::path::{Component, Path, PathBuf};
+use std::sync::LazyLock;
 use walkdir::WalkDir;
 
 fn path_from_module(root: &Path, module_path: &str, leaf: &str) -> PathBuf {
@@ -31,10 +33,73 @@ fn split_module_qualname(qualified: &str) -> (String, String) {
     }
 }
 
+/// The generated tree imports are resolved against.
+///
+/// `root` holds the file being rewritten; `overlays` are other output roots
+/// installed into the same packages, e.g. `.pyi` stubs generated into
+/// `mypy_out` next to runtime modules in `out`.
+#[derive(Clone, Copy)]
+struct Roots<'a> {
+    root: &'a Path,
+    overlays: &'a [PathBuf],
+    /// Generated modules by dotted name, with their paths relative to the
+    /// output root, from the descriptor set
+    known: Option<&'a HashMap<String, PathBuf>>,
+}
+
+impl Roots<'_> {
+    /// Where module `module_path.leaf` lives under `root`, if a `.py` or
+    /// `.pyi` file for it exists under `root` or any overlay.
+    ///
+    /// With `known` modules the descriptor set decides the path, and a module
+    /// it does not generate never counts, e.g. a leftover file of a proto no
+    /// longer compiled. The file must still exist, since imported protos that
+    /// were not among the inputs are in the set but not generated.
+    ///
+    /// The path is always in `root`'s coordinates, so relative imports are
+    /// computed within the importing file's own tree.
+    fn module_target(&self, module_path: &str, leaf: &str) -> Option<PathBuf> {
+        let target = match self.known {
+            Some(known) => {
+                let module = if module_path.is_empty() {
+                    leaf.to_string()
+                } else {
+                    format!("{module_path}.{leaf}")
+                };
+                self.root.join(known.get(&module)?)
+            }
+            None => path_from_module(self.root, module_path, leaf),
+        };
+        let rel = target.strip_prefix(self.root).ok()?;
+        std::iter::once(self.root)
+            .chain(self.overlays.iter().map(PathBuf::as_path))
+            .map(|r| r.join(rel))
+            .any(|p| p.exists() || p.with_extension("pyi").exists())
+            .then_some(target)
+    }
+}
+
 fn compute_relative_import_prefix(from_dir: &Path, to_dir: &Path) -> Option<(usize, String)> {
-    // Try canonicalize to normalize symlinks and relative segments; fall back to raw paths
-    let canonicalize_or =
-        |p: &Path| -> PathBuf { std::fs::canonicalize(p).unwrap_or_else(|_| p.to_path_buf()) };
+    // Canonicalize to normalize symlinks and relative segments. A directory that
+    // only exists in another output root resolves through its nearest existing
+    // ancestor, so it still lines up with `from_dir`; fall back to raw paths.
+    let canonicalize_or = |p: &Path| -> PathBuf {
+        let mut existing = p;
+        let mut missing = Vec::new();
+        loop {
+            if let Ok(mut c) = std::fs::canonicalize(existing) {
+                c.extend(missing.iter().rev());
+                return c;
+            }
+            match (existing.parent(), existing.file_name()) {
+                (Some(parent), Some(name)) => {
+                    missing.push(name);
+                    existing = parent;
+                }
+                _ => return p.to_path_buf(),
+            }
+        }
+    };
     let from_c = canonicalize_or(from_dir);
     let to_c = canonicalize_or(to_dir);
 
@@ -61,30 +126,105 @@ fn compute_relative_import_prefix(from_dir: &Path, to_dir: &Path) -> Option<(usi
     ))
 }
 
-#[allow(clippy::collapsible_if)]
+/// How rewritten imports refer to modules under the generated root.
+#[derive(Clone, Copy, Debug)]
+pub enum Anchor<'a> {
+    /// Relative to the importing file, for files inside the generated tree.
+    Relative,
+    /// Absolute under the dotted package the generated root is importable as
+    /// (empty when the root itself is on PYTHONPATH), for files outside it.
+    Package(&'a str),
+}
+
+impl Anchor<'_> {
+    /// The `from <pkg>` part for importing a module that lives in `target_dir`.
+    fn import_package(self, file_dir: &Path, target_dir: &Path, root: &Path) -> Option<String> {
+        match self {
+            Anchor::Relative => {
+                let (ups, remainder) = compute_relative_import_prefix(file_dir, target_dir)?;
+                // ups=0 -> "." (current), ups=1 -> ".." (parent)
+                let dots = ".".repeat(ups + 1);
+                Some(if remainder.is_empty() {
+                    dots
+                } else {
+                    format!("{dots}{remainder}")
+                })
+            }
+            Anchor::Package(package) => {
+                let rel = target_dir.strip_prefix(root).ok()?;
+                let parts = std::iter::once(package)
+                    .filter(|p| !p.is_empty())
+                    .map(str::to_string)
+                    .chain(rel.components().filter_map(|c| match c {
+                        Component::Normal(os) => Some(os.to_string_lossy().into_owned()),
+                        _ => None,
+                    }))
+                    .collect::<Vec<_>>();
+                // A top-level module with no package is already imported absolutely
+                (!parts.is_empty()).then(|| parts.join("."))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
 fn rewrite_lines_in_content(
     content: &str,
     file_dir: &Path,
     root: &Path,
     exclude_google: bool,
 ) -> Result<(String, bool)> {
+    let roots = Roots {
+        root,
+        overlays: &[],
+        known: None,
+    };
+    rewrite_imports(content, file_dir, roots, exclude_google, Anchor::Relative)
+}
+
+// `indent` is re-emitted verbatim, so tab or mixed indentation left by
+// formatters survives the rewrite
+static RE_IMPORT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^(?P<indent>\s*)import\s+(?P<mod>[A-Za-z0-9_\.]+)\s+as\s+(?P<alias>[A-Za-z0-9_]+)(?P<comment>\s*#.*)?\s*$",
+    )
+    .unwrap()
+});
+static RE_FROM: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?P<indent>\s*)from\s+(?P<pkg>[A-Za-z0-9_\.]+)\s+import\s+(?P<name>[A-Za-z0-9_]+)(?:\s+as\s+(?P<alias>[A-Za-z0-9_]+))?(?P<comment>\s*#.*)?\s*$").unwrap()
+});
+static RE_FROM_ANY: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?P<indent>\s*)from\s+(?P<pkg>[A-Za-z0-9_\.]+)\s+import\s+(?P<rest>.*)$").unwrap()
+});
+static RE_IMPORT_SIMPLE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?P<indent>\s*)import\s+(?P<mod>[A-Za-z0-9_\.]+)(?P<comment>\s*#.*)?\s*$")
+        .unwrap()
+});
+static RE_IMPORT_LIST: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(?P<indent>\s*)import\s+(?P<rest>.+)$").unwrap());
+static RE_FROM_MODULE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^(?P<indent>\s*)from\s+(?P<mod>[A-Za-z0-9_\.]*_pb2(?:_grpc)?)\s+import\s+(?P<rest>.*)$",
+    )
+    .unwrap()
+});
+static RE_DUNDER_ALL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)__all__\s*\+?=\s*[\[(].*?[\])]").unwrap());
+
+#[allow(clippy::collapsible_if)]
+fn rewrite_imports(
+    content: &str,
+    file_dir: &Path,
+    roots: Roots,
+    exclude_google: bool,
+    anchor: Anchor,
+) -> Result<(String, bool)> {
+    let root = roots.root;
     let mut changed = false;
     let mut out = String::with_capacity(content.len());
     // map of fully-qualified module -> local name to use in annotations
     let mut module_rewrites: Vec<(String, String)> = Vec::new();
 
-    let re_import = Regex::new(
-        r"^(?P<indent>\s*)import\s+(?P<mod>[A-Za-z0-9_\.]+)\s+as\s+(?P<alias>[A-Za-z0-9_]+)\s*(?:#.*)?$",
-    )
-    .unwrap();
-    let re_from = Regex::new(r"^(?P<indent>\s*)from\s+(?P<pkg>[A-Za-z0-9_\.]+)\s+import\s+(?P<name>[A-Za-z0-9_]+)(?:\s+as\s+(?P<alias>[A-Za-z0-9_]+))?\s*(?:#.*)?$").unwrap();
-    let re_from_any =
-        Regex::new(r"^(?P<indent>\s*)from\s+(?P<pkg>[A-Za-z0-9_\.]+)\s+import\s+(?P<rest>.*)$")
-            .unwrap();
-    let re_import_simple =
-        Regex::new(r"^(?P<indent>\s*)import\s+(?P<mod>[A-Za-z0-9_\.]+)\s*(?:#.*)?$").unwrap();
-    let re_import_list = Regex::new(r"^(?P<indent>\s*)import\s+(?P<rest>.+)$").unwrap();
-
     // State for collecting parenthesized multi-line 'from ... import (...)' blocks
     let mut pending_from_block: Option<(String, String, String)> = None; // (indent, pkg, collected)
 
@@ -93,9 +233,13 @@ fn rewrite_lines_in_content(
         if let Some((indent, pkg, mut collected)) = pending_from_block.take() {
             collected.push('\n');
             collected.push_str(line);
-            // Check if parentheses are balanced now
-            let opens = collected.matches('(').count();
-            let closes = collected.matches(')').count();
+            // Check if parentheses are balanced now, ignoring any inside comments
+            let (opens, closes) = collected
+                .lines()
+                .map(strip_inline_comment)
+                .fold((0, 0), |(o, c), l| {
+                    (o + l.matches('(').count(), c + l.matches(')').count())
+                });
             if closes < opens {
                 // Still pending
                 pending_from_block = Some((indent, pkg, collected));
@@ -108,8 +252,9 @@ fn rewrite_lines_in_content(
                 &pkg,
                 &collected,
                 file_dir,
-                root,
+                roots,
                 exclude_google,
+                anchor,
             )?;
             out.push_str(&processed.output);
             changed |= processed.changed;
@@ -120,7 +265,26 @@ fn rewrite_lines_in_content(
             out.push('\n');
             continue;
         }
-        if let Some(caps) = re_import_simple.captures(line) {
+        // 'from pkg.foo_pb2 import Name, ...' imports names from a proto module;
+        // only the module part changes, so a '(' opening a block is kept as is
+        if let Some(caps) = RE_FROM_MODULE.captures(line) {
+            let module = &caps["mod"];
+            let (module_path, leaf) = split_module_qualname(module);
+            if !(exclude_google && module.starts_with("google.protobuf"))
+                && let Some(target) = roots.module_target(&module_path, &leaf)
+                && let Some(from_pkg) =
+                    anchor.import_package(file_dir, target.parent().unwrap_or(root), root)
+            {
+                let sep = if from_pkg.ends_with('.') { "" } else { "." };
+                out.push_str(&format!(
+                    "{}from {from_pkg}{sep}{leaf} import {}\n",
+                    &caps["indent"], &caps["rest"]
+                ));
+                changed = true;
+                continue;
+            }
+        }
+        if let Some(caps) = RE_IMPORT_SIMPLE.captures(line) {
             let indent = &caps["indent"];
             let module = &caps["mod"];
             if !module.ends_with("_pb2") && !module.ends_with("_pb2_grpc") {
@@ -134,23 +298,16 @@ fn rewrite_lines_in_content(
                 continue;
             }
             let (module_path, leaf) = split_module_qualname(module);
-            let target = path_from_module(root, &module_path, &leaf);
-            if !target.exists() {
+            let Some(target) = roots.module_target(&module_path, &leaf) else {
                 out.push_str(line);
                 out.push('\n');
                 continue;
-            }
-            if let Some((ups, remainder)) =
-                compute_relative_import_prefix(file_dir, target.parent().unwrap_or(root))
+            };
+            if let Some(from_pkg) =
+                anchor.import_package(file_dir, target.parent().unwrap_or(root), root)
             {
-                // ups=0 -> "." (current), ups=1 -> ".." (parent)
-                let dots = ".".repeat(ups + 1);
-                let from_pkg = if remainder.is_empty() {
-                    dots
-                } else {
-                    format!("{dots}{remainder}")
-                };
-                let new_line = format!("{indent}from {from_pkg} import {leaf}");
+                let comment = caps.name("comment").map_or("", |m| m.as_str());
+                let new_line = format!("{indent}from {from_pkg} import {leaf}{comment}");
                 out.push_str(&new_line);
                 out.push('\n');
                 changed = true;
@@ -160,7 +317,7 @@ fn rewrite_lines_in_content(
         }
 
         // Handle comma-separated 'import a, b as c' by splitting into tokens
-        if let Some(caps) = re_import_list.captures(line) {
+        if let Some(caps) = RE_IMPORT_LIST.captures(line) {
             let indent = &caps["indent"];
             let rest = &caps["rest"]; // may contain commas and aliases
             if rest.contains(',') {
@@ -187,18 +344,12 @@ fn rewrite_lines_in_content(
                         && !(exclude_google && module.starts_with("google.protobuf"))
                     {
                         let (module_path, leaf) = split_module_qualname(&module);
-                        let target = path_from_module(root, &module_path, &leaf);
-                        if target.exists() {
-                            if let Some((ups, remainder)) = compute_relative_import_prefix(
+                        if let Some(target) = roots.module_target(&module_path, &leaf) {
+                            if let Some(from_pkg) = anchor.import_package(
                                 file_dir,
                                 target.parent().unwrap_or(root),
+                                root,
                             ) {
-                                let dots = ".".repeat(ups + 1);
-                                let from_pkg = if remainder.is_empty() {
-                                    dots
-                                } else {
-                                    format!("{dots}{remainder}")
-                                };
                                 if let Some(a) = alias {
                                     out.push_str(&format!(
                                         "{indent}from {from_pkg} import {leaf} as {a}\n"
@@ -225,7 +376,7 @@ fn rewrite_lines_in_content(
             }
         }
 
-        if let Some(caps) = re_import.captures(line) {
+        if let Some(caps) = RE_IMPORT.captures(line) {
             let indent = &caps["indent"];
             let module = &caps["mod"];
             let alias = &caps["alias"];
@@ -240,23 +391,16 @@ fn rewrite_lines_in_content(
                 continue;
             }
             let (module_path, leaf) = split_module_qualname(module);
-            let target = path_from_module(root, &module_path, &leaf);
-            if !target.exists() {
+            let Some(target) = roots.module_target(&module_path, &leaf) else {
                 out.push_str(line);
                 out.push('\n');
                 continue;
-            }
-            if let Some((ups, remainder)) =
-                compute_relative_import_prefix(file_dir, target.parent().unwrap_or(root))
+            };
+            if let Some(from_pkg) =
+                anchor.import_package(file_dir, target.parent().unwrap_or(root), root)
             {
-                // ups=0 -> "." (current), ups=1 -> ".." (parent)
-                let dots = ".".repeat(ups + 1);
-                let from_pkg = if remainder.is_empty() {
-                    dots
-                } else {
-                    format!("{dots}{remainder}")
-                };
-                let new_line = format!("{indent}from {from_pkg} import {leaf} as {alias}");
+                let comment = caps.name("comment").map_or("", |m| m.as_str());
+                let new_line = format!("{indent}from {from_pkg} import {leaf} as {alias}{comment}");
                 out.push_str(&new_line);
                 out.push('\n');
                 changed = true;
@@ -265,7 +409,7 @@ fn rewrite_lines_in_content(
             }
         }
         // Handle single-name 'from pkg import name [as alias]'
-        if let Some(caps) = re_from.captures(line) {
+        if let Some(caps) = RE_FROM.captures(line) {
             let indent = &caps["indent"];
             let pkg = &caps["pkg"];
             let name = &caps["name"];
@@ -280,31 +424,19 @@ fn rewrite_lines_in_content(
                 out.push('\n');
                 continue;
             }
-            let target = path_from_module(root, pkg, name);
-            if !target.exists() {
+            let Some(target) = roots.module_target(pkg, name) else {
                 out.push_str(line);
                 out.push('\n');
                 continue;
-            }
-            if let Some((ups, remainder)) =
-                compute_relative_import_prefix(file_dir, target.parent().unwrap_or(root))
+            };
+            if let Some(from_pkg) =
+                anchor.import_package(file_dir, target.parent().unwrap_or(root), root)
             {
-                // ups=0 -> same level (use "." + remainder)
-                // ups=1 -> parent level (use ".." + remainder)
-                let dots = if ups == 0 {
-                    ".".to_string()
-                } else {
-                    ".".repeat(ups + 1)
-                };
-                let from_pkg = if remainder.is_empty() {
-                    dots
-                } else {
-                    format!("{dots}{remainder}")
-                };
+                let comment = caps.name("comment").map_or("", |m| m.as_str());
                 let new_line = if let Some(a) = alias {
-                    format!("{indent}from {from_pkg} import {name} as {a}")
+                    format!("{indent}from {from_pkg} import {name} as {a}{comment}")
                 } else {
-                    format!("{indent}from {from_pkg} import {name}")
+                    format!("{indent}from {from_pkg} import {name}{comment}")
                 };
                 out.push_str(&new_line);
                 out.push('\n');
@@ -324,10 +456,10 @@ fn rewrite_lines_in_content(
         }
 
         // Handle 'from pkg import a, b as c' (single-line) or start of parenthesized block
-        if let Some(caps) = re_from_any.captures(line) {
+        if let Some(caps) = RE_FROM_ANY.captures(line) {
             let indent = caps["indent"].to_string();
             let pkg = caps["pkg"].to_string();
-            let rest = caps["rest"].trim();
+            let rest = strip_inline_comment(&caps["rest"]).trim();
             if rest.starts_with('(') && !rest.contains(')') {
                 // Begin collecting a multi-line parenthesized block
                 pending_from_block = Some((indent, pkg, line.to_string()));
@@ -335,8 +467,15 @@ fn rewrite_lines_in_content(
             }
             if rest.contains(',') || rest.starts_with('(') {
                 // Process possibly parenthesized single-line list
-                let processed =
-                    process_from_import_list(&indent, &pkg, line, file_dir, root, exclude_google)?;
+                let processed = process_from_import_list(
+                    &indent,
+                    &pkg,
+                    line,
+                    file_dir,
+                    roots,
+                    exclude_google,
+                    anchor,
+                )?;
                 out.push_str(&processed.output);
                 changed |= processed.changed;
                 continue;
@@ -345,23 +484,84 @@ fn rewrite_lines_in_content(
         out.push_str(line);
         out.push('\n');
     }
+    // An unterminated block at EOF is not valid Python; keep it verbatim
+    if let Some((_, _, collected)) = pending_from_block {
+        out.push_str(&collected);
+        out.push('\n');
+    }
     // After rewriting imports, fix fully-qualified references in annotations
-    if !module_rewrites.is_empty() {
-        for (from_mod, to_name) in module_rewrites.iter() {
-            // replace occurrences like "from_mod.*" to "to_name.*"
-            let pattern = regex::Regex::new(&format!(r"\b{}\.", regex::escape(from_mod))).unwrap();
-            let replaced = pattern.replace_all(&out, format!("{}.", to_name));
-            let new_str = replaced.into_owned();
-            if new_str != out {
-                changed = true;
-                out = new_str;
-            }
-        }
+    let fixed = fix_module_references(&out, &module_rewrites);
+    if fixed != out {
+        changed = true;
+        out = fixed;
+    }
+    // `lines()` drops the '\r' of CRLF endings; put back what the file mostly uses
+    if uses_crlf(content) {
+        out = out.replace('\n', "\r\n");
     }
 
     Ok((out, changed))
 }
 
+/// Whether most line breaks in `content` are CRLF.
+fn uses_crlf(content: &str) -> bool {
+    let crlf = content.matches("\r\n").count();
+    crlf > content.matches('\n').count() - crlf
+}
+
+/// Point references to each rewritten module `from_mod` at its local name.
+///
+/// `rewrites` pairs a fully-qualified module with the name it is now imported
+/// as. Handles attribute access such as `from_mod.Symbol`, including inside
+/// string (forward reference) annotations like `"from_mod.Symbol"`, and the
+/// bare module name quoted in an `__all__` list. A match must not follow an
+/// identifier character or `.`, so `other.from_mod.X` and `myfrom_mod.X` are
+/// left alone. All modules are matched by one combined pattern per file.
+fn fix_module_references(content: &str, rewrites: &[(String, String)]) -> String {
+    if rewrites.is_empty() {
+        return content.to_string();
+    }
+    // The first import of a module decides its local name
+    let mut local: HashMap<&str, &str> = HashMap::new();
+    for (from_mod, to_name) in rewrites {
+        local.entry(from_mod.as_str()).or_insert(to_name.as_str());
+    }
+    // Longest first, so a module wins over another that is a prefix of it
+    let mut modules: Vec<&str> = local.keys().copied().collect();
+    modules.sort_by(|a, b| b.len().cmp(&a.len()).then(a.cmp(b)));
+    let alternation = modules
+        .iter()
+        .map(|m| regex::escape(m))
+        .collect::<Vec<_>>()
+        .join("|");
+
+    let attr = Regex::new(&format!(r"(^|[^\w.])({alternation})\.")).unwrap();
+    let content = attr.replace_all(content, |caps: &regex::Captures| {
+        format!("{}{}.", &caps[1], local[&caps[2]])
+    });
+
+    let quoted = Regex::new(&format!(r#""({alternation})"|'({alternation})'"#)).unwrap();
+    RE_DUNDER_ALL
+        .replace_all(&content, |caps: &regex::Captures| {
+            quoted
+                .replace_all(&caps[0], |q: &regex::Captures| {
+                    let quote = &q[0][..1];
+                    let module = q.get(1).or_else(|| q.get(2)).map_or("", |m| m.as_str());
+                    format!("{quote}{}{quote}", local[module])
+                })
+                .into_owned()
+        })
+        .into_owned()
+}
+
+/// Drop a trailing `# ...` comment from a line of import code.
+fn strip_inline_comment(line: &str) -> &str {
+    match line.find('#') {
+        Some(idx) => &line[..idx],
+        None => line,
+    }
+}
+
 struct FromImportProcessResult {
     output: String,
     changed: bool,
@@ -372,39 +572,38 @@ fn process_from_import_list(
     pkg: &str,
     full_line_or_block: &str,
     file_dir: &Path,
-    root: &Path,
+    roots: Roots,
     exclude_google: bool,
+    anchor: Anchor,
 ) -> Result<FromImportProcessResult> {
-    // Extract everything after 'from <pkg> import'
+    let root = roots.root;
+    // Extract everything after 'from <pkg> import', dropping comments on every
+    // line (including one after the closing paren) before looking at the names
     let after_import = full_line_or_block
         .split_once(" import ")
-        .map(|(_, s)| s.trim())
-        .unwrap_or_else(|| full_line_or_block.trim());
-
-    // Remove wrapping parentheses and trailing comment lines
-    let mut inner = after_import.trim();
-    if inner.starts_with('(') {
-        // Remove the first '(' and the matching last ')'
-        // For robustness, just trim leading '(' and trailing ')' and whitespace/commas
-        inner = inner.trim_start_matches('(');
-        inner = inner.trim_end();
-        if inner.ends_with(')') {
-            inner = &inner[..inner.len() - 1];
+        .map(|(_, s)| s)
+        .unwrap_or(full_line_or_block);
+    let code = after_import
+        .lines()
+        .map(strip_inline_comment)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    // Remove wrapping parentheses
+    let mut inner = code.trim();
+    if let Some(stripped) = inner.strip_prefix('(') {
+        inner = stripped.trim_end();
+        if let Some(stripped) = inner.strip_suffix(')') {
+            inner = stripped;
         }
     }
 
-    // Split by commas across potential multi-lines
+    // Split by commas across potential multi-lines; trailing commas yield empty tokens
     let mut tokens: Vec<String> = Vec::new();
-    for raw in inner.lines() {
-        let no_comment = match raw.find('#') {
-            Some(idx) => &raw[..idx],
-            None => raw,
-        };
-        for part in no_comment.split(',') {
-            let t = part.trim();
-            if !t.is_empty() {
-                tokens.push(t.to_string());
-            }
+    for part in inner.split(',') {
+        let t = part.trim();
+        if !t.is_empty() {
+            tokens.push(t.to_string());
         }
     }
 
@@ -430,8 +629,7 @@ fn process_from_import_list(
             && !(exclude_google && pkg.starts_with("google.protobuf"))
         {
             // Check target exists
-            let target = path_from_module(root, pkg, name);
-            if target.exists() {
+            if roots.module_target(pkg, name).is_some() {
                 rewrite_items.push((name.to_string(), alias));
                 continue;
             }
@@ -447,21 +645,17 @@ fn process_from_import_list(
         });
     }
 
-    // Compute relative from-pkg using any one item's target (they share pkg)
+    // Compute the new from-pkg using any one item's target (they share pkg)
     let any_name = &rewrite_items[0].0;
-    let target = path_from_module(root, pkg, any_name);
-    let (ups, remainder) =
-        compute_relative_import_prefix(file_dir, target.parent().unwrap_or(root))
-            .unwrap_or((0, String::new()));
-    let dots = if ups == 0 {
-        ".".to_string()
-    } else {
-        ".".repeat(ups + 1)
-    };
-    let from_pkg = if remainder.is_empty() {
-        dots
-    } else {
-        format!("{dots}{remainder}")
+    let target = roots
+        .module_target(pkg, any_name)
+        .unwrap_or_else(|| path_from_module(root, pkg, any_name));
+    let Some(from_pkg) = anchor.import_package(file_dir, target.parent().unwrap_or(root), root)
+    else {
+        return Ok(FromImportProcessResult {
+            output: format!("{}{}\n", indent, full_line_or_block.trim()),
+            changed: false,
+        });
     };
 
     // Build output lines: first the rewritten relative import
@@ -488,58 +682,229 @@ fn process_from_import_list(
     })
 }
 
-#[allow(dead_code)]
+/// Rewrite imports of generated modules under `root` into relative imports.
+///
+/// `overlays` are other output roots installed into the same packages as
+/// `root` (e.g. stubs in `mypy_out`); a module found in any of them counts as
+/// part of the tree, and the import is made relative within `root`.
+///
+/// `known_modules` are the modules the descriptor set generates (see
+/// [`generated_modules`](super::fds::generated_modules), after
+/// `module_transform`); when given, only imports of those modules are
+/// rewritten, their targets are located by the descriptor set's file paths
+/// rather than derived from the import, and files mentioning none of them
+/// are skipped.
 pub fn apply_rewrites_in_tree(
     root: &Path,
+    overlays: &[PathBuf],
     exclude_google: bool,
     module_suffixes: &[String],
-    allowed_basenames: Option<&std::collections::HashSet<String>>,
+    known_modules: Option<&HashMap<String, PathBuf>>,
+    exclude: &[glob::Pattern],
+    dry_run: bool,
 ) -> Result<usize> {
+    let rewrite = TreeRewrite {
+        root,
+        overlays,
+        exclude_google,
+        module_suffixes,
+        known_modules,
+        exclude,
+        dry_run,
+    };
     let mut modified = 0usize;
-    for entry in WalkDir::new(root).into_iter().filter_map(Result::ok) {
-        let p = entry.path();
-        if p.is_file() {
-            let rel = p.strip_prefix(root).unwrap_or(p).to_string_lossy();
-            let mut matched = false;
-            for s in module_suffixes {
-                if (s.ends_with(".py") || s.ends_with(".pyi")) && rel.ends_with(s) {
-                    matched = true;
-                    break;
-                }
-            }
-            if !matched {
-                continue;
-            }
-            let content = fs::read_to_string(p).with_context(|| format!("read {}", p.display()))?;
-            // Pre-filter: if allowed_basenames (from FDS) are provided,
-            // skip files that don't contain any target basename
-            if matches!(
-                allowed_basenames,
-                Some(allowed) if !allowed.iter().any(|b| content.contains(b))
-            ) {
-                continue;
-            }
-            let (new_content, changed) = rewrite_lines_in_content(
-                &content,
-                p.parent().unwrap_or(root),
+    scan_tree(root, |file| {
+        modified += usize::from(rewrite.apply(&file)?.is_some());
+        Ok(())
+    })?;
+    Ok(modified)
+}
+
+/// The settings of [`apply_rewrites_in_tree`], applied one file at a time to
+/// files read by [`scan_tree`](super::rel_imports::scan_tree), so a build
+/// that also reports the scan does not read the tree twice.
+pub struct TreeRewrite<'a> {
+    pub root: &'a Path,
+    pub overlays: &'a [PathBuf],
+    pub exclude_google: bool,
+    pub module_suffixes: &'a [String],
+    pub known_modules: Option<&'a HashMap<String, PathBuf>>,
+    pub exclude: &'a [glob::Pattern],
+    pub dry_run: bool,
+}
+
+impl TreeRewrite<'_> {
+    /// Rewrite one scanned file, returning the new content when it was (or,
+    /// with `dry_run`, would be) modified.
+    pub fn apply(&self, file: &ScannedFile) -> Result<Option<String>> {
+        let root = self.root;
+        let p = file.path.as_path();
+        let rel = p.strip_prefix(root).unwrap_or(p);
+        if super::is_excluded(self.exclude, rel) {
+            return Ok(None);
+        }
+        let rel_str = rel.to_string_lossy();
+        let matched = self
+            .module_suffixes
+            .iter()
+            .any(|s| (s.ends_with(".py") || s.ends_with(".pyi")) && rel_str.ends_with(s));
+        if !matched {
+            return Ok(None);
+        }
+        let content = &file.content;
+        // Pre-filter: if known modules (from FDS) are provided,
+        // skip files that don't contain any of their basenames
+        if matches!(
+            self.known_modules,
+            Some(known) if !known.keys().any(|m| content.contains(m.rsplit('.').next().unwrap_or(m)))
+        ) {
+            return Ok(None);
+        }
+        let (new_content, changed) = rewrite_imports(
+            content,
+            p.parent().unwrap_or(root),
+            Roots {
                 root,
-                exclude_google,
-            )?;
-            if changed {
-                let mut f = fs::OpenOptions::new()
-                    .write(true)
-                    .truncate(true)
-                    .open(p)
-                    .with_context(|| format!("open {} for write", p.display()))?;
-                f.write_all(new_content.as_bytes())
-                    .with_context(|| format!("write {}", p.display()))?;
-                modified += 1;
+                overlays: self.overlays,
+                known: self.known_modules,
+            },
+            self.exclude_google,
+            Anchor::Relative,
+        )?;
+        if !changed {
+            return Ok(None);
+        }
+        if self.dry_run {
+            tracing::info!("{}", super::render_diff(rel, content, &new_content));
+        } else {
+            super::write_preserving_mode(p, &new_content)?;
+            crate::events::emit!("file_rewritten", path = %rel.display());
+        }
+        Ok(Some(new_content))
+    }
+}
+
+/// Rewrite imports of generated modules in user files outside the generated tree.
+///
+/// `path` may be a single file or a directory; `.py` files and the code cells of
+/// Jupyter notebooks (`.ipynb`) are processed, and anything under `root` itself
+/// is skipped. Imports that resolve to a module under `root` become absolute
+/// imports under `package`, the dotted name `root` is importable as.
+///
+/// # Returns
+///
+/// The number of files that were (or, with `dry_run`, would be) modified.
+pub fn rewrite_user_files(
+    path: &Path,
+    root: &Path,
+    package: &str,
+    exclude_google: bool,
+    dry_run: bool,
+) -> Result<usize> {
+    let root_abs = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    let anchor = Anchor::Package(package);
+    let roots = Roots {
+        root,
+        overlays: &[],
+        known: None,
+    };
+    let mut modified = 0usize;
+    for entry in WalkDir::new(path).into_iter().filter_map(Result::ok) {
+        let p = entry.path();
+        if !p.is_file() || p.canonicalize().is_ok_and(|c| c.starts_with(&root_abs)) {
+            continue;
+        }
+        let content = match p.extension().and_then(|e| e.to_str()) {
+            Some("py") | Some("ipynb") => {
+                fs::read_to_string(p).with_context(|| format!("read {}", p.display()))?
             }
+            _ => continue,
+        };
+        let file_dir = p.parent().unwrap_or(Path::new("."));
+        let new_content = if p.extension().is_some_and(|e| e == "ipynb") {
+            rewrite_notebook(&content, file_dir, roots, exclude_google, anchor)
+                .with_context(|| format!("rewrite notebook {}", p.display()))?
+        } else {
+            rewrite_imports(&content, file_dir, roots, exclude_google, anchor)?.0
+        };
+        // Re-running on an already rewritten file reports a change with identical text
+        if new_content == content {
+            continue;
         }
+        if dry_run {
+            tracing::info!("{}", super::render_diff(p, &content, &new_content));
+        } else {
+            super::write_preserving_mode(p, &new_content)?;
+        }
+        modified += 1;
     }
     Ok(modified)
 }
 
+/// Apply import rewriting to each code cell of a notebook, keeping the rest intact.
+fn rewrite_notebook(
+    content: &str,
+    file_dir: &Path,
+    roots: Roots,
+    exclude_google: bool,
+    anchor: Anchor,
+) -> Result<String> {
+    let mut nb: serde_json::Value = serde_json::from_str(content).context("parse notebook")?;
+    let mut changed = false;
+    let cells = nb
+        .get_mut("cells")
+        .and_then(|c| c.as_array_mut())
+        .into_iter()
+        .flatten();
+    for cell in cells {
+        if cell.get("cell_type").and_then(|t| t.as_str()) != Some("code") {
+            continue;
+        }
+        let Some(source) = cell.get_mut("source") else {
+            continue;
+        };
+        // nbformat allows the source as one string or a list of lines
+        let (text, as_list) = match source {
+            serde_json::Value::String(s) => (s.clone(), false),
+            serde_json::Value::Array(lines) => (
+                lines.iter().filter_map(|l| l.as_str()).collect::<String>(),
+                true,
+            ),
+            _ => continue,
+        };
+        let (mut new_text, cell_changed) =
+            rewrite_imports(&text, file_dir, roots, exclude_google, anchor)?;
+        if !cell_changed {
+            continue;
+        }
+        // The rewriter always terminates lines; cells usually do not end with one
+        if !text.ends_with('\n') && new_text.ends_with('\n') {
+            new_text.pop();
+        }
+        *source = if as_list {
+            serde_json::Value::Array(
+                new_text
+                    .split_inclusive('\n')
+                    .map(|l| serde_json::Value::String(l.to_string()))
+                    .collect(),
+            )
+        } else {
+            serde_json::Value::String(new_text)
+        };
+        changed = true;
+    }
+    if !changed {
+        return Ok(content.to_string());
+    }
+    // Match Jupyter's own formatting: one-space indent and a trailing newline
+    let mut buf = Vec::new();
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(b" ");
+    let mut ser = serde_json::Serializer::with_formatter(&mut buf, formatter);
+    serde::Serialize::serialize(&nb, &mut ser).context("serialize notebook")?;
+    buf.push(b'\n');
+    Ok(String::from_utf8(buf)?)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -648,7 +1013,9 @@ mod tests {
         fs::write(root.join("a_pb2.py"), "# a\n").unwrap();
         fs::write(root.join("x/b_pb2.py"), "import a_pb2 as a__pb2\n").unwrap();
         fs::write(root.join("c.py"), "import a_pb2 as a__pb2\n").unwrap();
-        let modified = apply_rewrites_in_tree(root, false, &["_pb2.py".into()], None).unwrap();
+        let modified =
+            apply_rewrites_in_tree(root, &[], false, &["_pb2.py".into()], None, &[], false)
+                .unwrap();
         // only x/b_pb2.py should be modified
         assert_eq!(modified, 1);
         let b = fs::read_to_string(root.join("x/b_pb2.py")).unwrap();
@@ -657,6 +1024,44 @@ mod tests {
         assert_eq!(c, "import a_pb2 as a__pb2\n");
     }
 
+    #[test]
+    fn apply_rewrites_skips_excluded_files() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("vendor/google")).unwrap();
+        fs::create_dir_all(root.join("x")).unwrap();
+        fs::write(root.join("foo_pb2.py"), "# foo\n").unwrap();
+        fs::write(
+            root.join("vendor/google/a_pb2.py"),
+            "import foo_pb2 as foo__pb2\n",
+        )
+        .unwrap();
+        fs::write(root.join("x/b_pb2.py"), "import foo_pb2 as foo__pb2\n").unwrap();
+        let exclude = vec![glob::Pattern::new("vendor/**").unwrap()];
+        let modified =
+            apply_rewrites_in_tree(root, &[], false, &["_pb2.py".into()], None, &exclude, false)
+                .unwrap();
+        assert_eq!(modified, 1);
+        let vendored = fs::read_to_string(root.join("vendor/google/a_pb2.py")).unwrap();
+        assert_eq!(vendored, "import foo_pb2 as foo__pb2\n");
+        let b = fs::read_to_string(root.join("x/b_pb2.py")).unwrap();
+        assert_eq!(b, "from .. import foo_pb2 as foo__pb2\n");
+    }
+
+    #[test]
+    fn apply_rewrites_dry_run_does_not_write() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("x")).unwrap();
+        fs::write(root.join("a_pb2.py"), "# a\n").unwrap();
+        fs::write(root.join("x/b_pb2.py"), "import a_pb2 as a__pb2\n").unwrap();
+        let modified =
+            apply_rewrites_in_tree(root, &[], false, &["_pb2.py".into()], None, &[], true).unwrap();
+        assert_eq!(modified, 1);
+        let b = fs::read_to_string(root.join("x/b_pb2.py")).unwrap();
+        assert_eq!(b, "import a_pb2 as a__pb2\n");
+    }
+
     #[test]
     fn rewrite_from_multi_items_single_line() {
         let dir = tempdir().unwrap();
@@ -686,6 +1091,254 @@ mod tests {
         assert_eq!(out.trim_end(), "from . import a_pb2, b_pb2 as bb");
     }
 
+    #[test]
+    fn rewrite_from_parenthesized_with_comments() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("pkg")).unwrap();
+        fs::write(root.join("pkg/a_pb2.py"), "# a").unwrap();
+        fs::write(root.join("pkg/b_pb2.py"), "# b").unwrap();
+        let file_dir = root.join("pkg");
+        let content = "from pkg import (  # generated (do not edit)\n    a_pb2,  # first\n    b_pb2,\n)  # end\nx = 1\n";
+        let (out, changed) = rewrite_lines_in_content(content, &file_dir, root, false).unwrap();
+        assert!(changed);
+        assert_eq!(out, "from . import a_pb2, b_pb2\nx = 1\n");
+    }
+
+    #[test]
+    fn rewrite_preserves_trailing_comments() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("pkg")).unwrap();
+        fs::write(root.join("pkg/a_pb2.py"), "# a").unwrap();
+        fs::write(root.join("pkg/b_pb2.py"), "# b").unwrap();
+        fs::write(root.join("pkg/c_pb2.py"), "# c").unwrap();
+        let file_dir = root.join("pkg");
+        let content = "import pkg.a_pb2 as a__pb2  # type: ignore\n\
+                       import pkg.b_pb2 # noqa\n\
+                       from pkg import c_pb2 as c__pb2\t# type: ignore[attr-defined]\n\
+                       from pkg import c_pb2  # type: ignore\n";
+        let (out, changed) = rewrite_lines_in_content(content, &file_dir, root, false).unwrap();
+        assert!(changed);
+        assert_eq!(
+            out,
+            "from . import a_pb2 as a__pb2  # type: ignore\n\
+             from . import b_pb2 # noqa\n\
+             from . import c_pb2 as c__pb2\t# type: ignore[attr-defined]\n\
+             from . import c_pb2  # type: ignore\n"
+        );
+    }
+
+    #[test]
+    fn rewrite_only_targets_modules_known_to_the_descriptor_set() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("pkg")).unwrap();
+        fs::write(root.join("pkg/a_pb2.py"), "").unwrap();
+        // Left over from a proto that is no longer compiled
+        fs::write(root.join("pkg/old_pb2.py"), "").unwrap();
+        let b = root.join("pkg/b_pb2.py");
+        let imports = "from pkg import old_pb2 as old__pb2\n\
+                       from vendor import money_pb2 as money__pb2\n";
+        fs::write(&b, format!("from pkg import a_pb2 as a__pb2\n{imports}")).unwrap();
+        // vendor/money.proto is imported, so it is in the set, but was not generated
+        let known: HashMap<String, PathBuf> = ["pkg.a_pb2", "pkg.b_pb2", "vendor.money_pb2"]
+            .map(|m| {
+                (
+                    m.to_string(),
+                    PathBuf::from(format!("{}.py", m.replace('.', "/"))),
+                )
+            })
+            .into();
+
+        assert_eq!(
+            apply_rewrites_in_tree(
+                root,
+                &[],
+                false,
+                &["_pb2.py".into()],
+                Some(&known),
+                &[],
+                false
+            )
+            .unwrap(),
+            1
+        );
+        assert_eq!(
+            fs::read_to_string(&b).unwrap(),
+            format!("from . import a_pb2 as a__pb2\n{imports}")
+        );
+    }
+
+    #[test]
+    fn rewrite_keeps_crlf_line_endings_and_file_mode() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("pkg")).unwrap();
+        fs::write(root.join("pkg/a_pb2.py"), "").unwrap();
+        let b = root.join("pkg/b_pb2.py");
+        fs::write(
+            &b,
+            "import grpc\r\nfrom pkg import a_pb2 as a__pb2\r\n\r\nX = a__pb2.Foo\r\n",
+        )
+        .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&b, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        assert_eq!(
+            apply_rewrites_in_tree(root, &[], false, &["_pb2.py".into()], None, &[], false)
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            fs::read_to_string(&b).unwrap(),
+            "import grpc\r\nfrom . import a_pb2 as a__pb2\r\n\r\nX = a__pb2.Foo\r\n"
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(
+                fs::metadata(&b).unwrap().permissions().mode() & 0o777,
+                0o755
+            );
+        }
+    }
+
+    #[test]
+    fn rewrite_preserves_tab_indentation() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("pkg")).unwrap();
+        for m in ["a_pb2", "b_pb2", "c_pb2"] {
+            fs::write(root.join(format!("pkg/{m}.py")), "").unwrap();
+        }
+        let file_dir = root.join("pkg");
+        // As left by a formatter that indents with tabs, including mixed indentation
+        let content = "if TYPE_CHECKING:\n\
+                       \timport pkg.a_pb2 as a__pb2\n\
+                       \tfrom pkg import b_pb2\n\
+                       else:\n\
+                       \t  import pkg.c_pb2, pkg.b_pb2\n\
+                       \tfrom pkg import (\n\
+                       \t\ta_pb2,\n\
+                       \t\tc_pb2 as cc,\n\
+                       \t)\n";
+        let (out, changed) = rewrite_lines_in_content(content, &file_dir, root, false).unwrap();
+        assert!(changed);
+        assert_eq!(
+            out,
+            "if TYPE_CHECKING:\n\
+             \tfrom . import a_pb2 as a__pb2\n\
+             \tfrom . import b_pb2\n\
+             else:\n\
+             \t  from . import c_pb2\n\
+             \t  from . import b_pb2\n\
+             \tfrom . import a_pb2, c_pb2 as cc\n"
+        );
+    }
+
+    #[test]
+    fn rewrite_guarded_imports_in_grpc_modules() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("api/v1")).unwrap();
+        std::fs::create_dir_all(root.join("common")).unwrap();
+        fs::write(root.join("api/v1/service_pb2.py"), "").unwrap();
+        fs::write(root.join("common/types_pb2.py"), "").unwrap();
+        let file_dir = root.join("api/v1");
+        // Shaped like grpcio-tools 1.6x output, with proto imports moved into guards
+        let content = r#"# Generated by the gRPC Python protocol compiler plugin. DO NOT EDIT!
+"""Client and server classes corresponding to protobuf-defined services."""
+from __future__ import annotations
+
+import grpc
+import warnings
+
+try:
+    from api.v1 import service_pb2 as api_dot_v1_dot_service__pb2
+except ImportError:
+    import api.v1.service_pb2 as api_dot_v1_dot_service__pb2
+
+if TYPE_CHECKING:
+    from common.types_pb2 import Money
+    import common.types_pb2
+
+GRPC_GENERATED_VERSION = '1.66.1'
+GRPC_VERSION = grpc.__version__
+_version_not_supported = False
+
+try:
+    from grpc._utilities import first_version_is_lower
+    _version_not_supported = first_version_is_lower(GRPC_VERSION, GRPC_GENERATED_VERSION)
+except ImportError:
+    _version_not_supported = True
+
+def f() -> common.types_pb2.Money: ...
+"#;
+        let (out, changed) = rewrite_lines_in_content(content, &file_dir, root, false).unwrap();
+        assert!(changed);
+        let expected = r#"# Generated by the gRPC Python protocol compiler plugin. DO NOT EDIT!
+"""Client and server classes corresponding to protobuf-defined services."""
+from __future__ import annotations
+
+import grpc
+import warnings
+
+try:
+    from . import service_pb2 as api_dot_v1_dot_service__pb2
+except ImportError:
+    from . import service_pb2 as api_dot_v1_dot_service__pb2
+
+if TYPE_CHECKING:
+    from ...common.types_pb2 import Money
+    from ...common import types_pb2
+
+GRPC_GENERATED_VERSION = '1.66.1'
+GRPC_VERSION = grpc.__version__
+_version_not_supported = False
+
+try:
+    from grpc._utilities import first_version_is_lower
+    _version_not_supported = first_version_is_lower(GRPC_VERSION, GRPC_GENERATED_VERSION)
+except ImportError:
+    _version_not_supported = True
+
+def f() -> types_pb2.Money: ...
+"#;
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn rewrite_from_parenthesized_mixed_names() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("pkg")).unwrap();
+        fs::write(root.join("pkg/a_pb2.py"), "# a").unwrap();
+        let file_dir = root.join("pkg");
+        let content =
+            "from pkg import (\n    helpers,\n    a_pb2 as _a_pb2,\n    missing_pb2,\n)\n";
+        let (out, changed) = rewrite_lines_in_content(content, &file_dir, root, false).unwrap();
+        assert!(changed);
+        // Only the proto module that exists in the tree becomes relative
+        assert_eq!(
+            out,
+            "from . import a_pb2 as _a_pb2\nfrom pkg import helpers, missing_pb2\n"
+        );
+    }
+
+    #[test]
+    fn unterminated_parenthesized_block_is_preserved() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        let content = "from pkg import (\n    a_pb2,\n";
+        let (out, changed) = rewrite_lines_in_content(content, root, root, false).unwrap();
+        assert!(!changed);
+        assert_eq!(out, content);
+    }
+
     #[test]
     fn rewrite_import_list_into_multiple_lines() {
         let dir = tempdir().unwrap();
@@ -740,7 +1393,9 @@ mod tests {
         let billing_content = "from order import order_pb2 as order_dot_order__pb2\n";
         fs::write(root.join("billing/billing_pb2.py"), billing_content).unwrap();
 
-        let modified = apply_rewrites_in_tree(root, false, &["_pb2.py".into()], None).unwrap();
+        let modified =
+            apply_rewrites_in_tree(root, &[], false, &["_pb2.py".into()], None, &[], false)
+                .unwrap();
         assert_eq!(modified, 1);
 
         let billing = fs::read_to_string(root.join("billing/billing_pb2.py")).unwrap();
@@ -750,4 +1405,165 @@ mod tests {
             "from ..order import order_pb2 as order_dot_order__pb2\n"
         );
     }
+
+    #[test]
+    fn rewrite_resolves_modules_in_other_output_roots() {
+        let dir = tempdir().unwrap();
+        let out = dir.path().join("out");
+        let grpc = dir.path().join("grpc");
+        let stubs = dir.path().join("stubs");
+        for d in ["order", "billing"] {
+            fs::create_dir_all(out.join(d)).unwrap();
+            fs::create_dir_all(stubs.join(d)).unwrap();
+        }
+        fs::create_dir_all(grpc.join("billing")).unwrap();
+        fs::write(out.join("order/order_pb2.py"), "").unwrap();
+        fs::write(out.join("billing/billing_pb2.py"), "").unwrap();
+        // The runtime module lives in `out`, and `order/` does not exist in `grpc`
+        fs::write(
+            grpc.join("billing/billing_pb2_grpc.py"),
+            "from billing import billing_pb2 as billing_dot_billing__pb2\n\
+             from order import order_pb2 as order_dot_order__pb2\n",
+        )
+        .unwrap();
+        // Stubs are only `.pyi`, and the imported stub is in the same root
+        fs::write(stubs.join("order/order_pb2.pyi"), "").unwrap();
+        fs::write(
+            stubs.join("billing/billing_pb2.pyi"),
+            "import order.order_pb2\nx: order.order_pb2.Order\n",
+        )
+        .unwrap();
+
+        let suffixes = ["_pb2_grpc.py".to_string(), "_pb2.pyi".to_string()];
+        let overlays = [out.clone(), stubs.clone()];
+        assert_eq!(
+            apply_rewrites_in_tree(&grpc, &overlays, false, &suffixes, None, &[], false).unwrap(),
+            1
+        );
+        assert_eq!(
+            fs::read_to_string(grpc.join("billing/billing_pb2_grpc.py")).unwrap(),
+            "from . import billing_pb2 as billing_dot_billing__pb2\n\
+             from ..order import order_pb2 as order_dot_order__pb2\n"
+        );
+        let overlays = [out.clone(), grpc.clone()];
+        assert_eq!(
+            apply_rewrites_in_tree(&stubs, &overlays, false, &suffixes, None, &[], false).unwrap(),
+            1
+        );
+        assert_eq!(
+            fs::read_to_string(stubs.join("billing/billing_pb2.pyi")).unwrap(),
+            "from ..order import order_pb2\nx: order_pb2.Order\n"
+        );
+
+        // Without the other roots the runtime modules are not found
+        fs::write(
+            grpc.join("billing/billing_pb2_grpc.py"),
+            "from billing import billing_pb2 as billing_dot_billing__pb2\n",
+        )
+        .unwrap();
+        assert_eq!(
+            apply_rewrites_in_tree(&grpc, &[], false, &suffixes, None, &[], false).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn rewrite_user_script_to_generated_package() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("generated");
+        fs::create_dir_all(root.join("api")).unwrap();
+        fs::write(root.join("api/foo_pb2.py"), "# foo\n").unwrap();
+        fs::write(root.join("api/foo_pb2_grpc.py"), "# grpc\n").unwrap();
+        let scripts = dir.path().join("scripts");
+        fs::create_dir_all(&scripts).unwrap();
+        let script = scripts.join("analyze.py");
+        fs::write(
+            &script,
+            "import api.foo_pb2\nfrom api import foo_pb2_grpc\nimport json\n\nmsg = api.foo_pb2.Foo()\n",
+        )
+        .unwrap();
+
+        let modified = rewrite_user_files(&scripts, &root, "generated", true, false).unwrap();
+        assert_eq!(modified, 1);
+        let out = fs::read_to_string(&script).unwrap();
+        assert_eq!(
+            out,
+            "from generated.api import foo_pb2\nfrom generated.api import foo_pb2_grpc\nimport json\n\nmsg = foo_pb2.Foo()\n"
+        );
+
+        // Already rewritten imports no longer resolve under the root and are left alone
+        assert_eq!(
+            rewrite_user_files(&scripts, &root, "generated", true, false).unwrap(),
+            0
+        );
+        // Files inside the generated tree are never touched
+        assert_eq!(
+            fs::read_to_string(root.join("api/foo_pb2.py")).unwrap(),
+            "# foo\n"
+        );
+    }
+
+    #[test]
+    fn rewrite_user_notebook_code_cells() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("generated");
+        fs::create_dir_all(root.join("api")).unwrap();
+        fs::write(root.join("api/foo_pb2.py"), "# foo\n").unwrap();
+        let nb = dir.path().join("explore.ipynb");
+        fs::write(
+            &nb,
+            r#"{"cells": [
+  {"cell_type": "markdown", "metadata": {}, "source": ["import api.foo_pb2 as foo\n"]},
+  {"cell_type": "code", "metadata": {}, "outputs": [], "execution_count": null,
+   "source": ["import api.foo_pb2 as foo\n", "foo.Foo()"]}
+], "metadata": {}, "nbformat": 4, "nbformat_minor": 5}"#,
+        )
+        .unwrap();
+
+        let modified = rewrite_user_files(&nb, &root, "pkg.gen", true, false).unwrap();
+        assert_eq!(modified, 1);
+        let value: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&nb).unwrap()).unwrap();
+        assert_eq!(
+            value["cells"][0]["source"][0],
+            "import api.foo_pb2 as foo\n"
+        );
+        assert_eq!(
+            value["cells"][1]["source"],
+            serde_json::json!(["from pkg.gen.api import foo_pb2 as foo\n", "foo.Foo()"])
+        );
+    }
+
+    #[test]
+    fn rewrite_fixes_string_annotations_and_dunder_all() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("api")).unwrap();
+        fs::write(root.join("api/foo_pb2.py"), "# stub").unwrap();
+        let sub = root.join("svc");
+        fs::create_dir_all(&sub).unwrap();
+        let content = concat!(
+            "import api.foo_pb2\n",
+            "x: \"api.foo_pb2.Bar\"\n",
+            "y: 'api.foo_pb2.Baz' = api.foo_pb2.Baz()\n",
+            "z: \"other.api.foo_pb2.Bar\"\n",
+            "w: \"myapi.foo_pb2.Bar\"\n",
+            "__all__ = [\n    \"api.foo_pb2\",\n    'Bar',\n]\n",
+            "NAME = \"api.foo_pb2\"\n",
+        );
+        let (out, changed) = rewrite_lines_in_content(content, &sub, root, false).unwrap();
+        assert!(changed);
+        assert_eq!(
+            out,
+            concat!(
+                "from ..api import foo_pb2\n",
+                "x: \"foo_pb2.Bar\"\n",
+                "y: 'foo_pb2.Baz' = foo_pb2.Baz()\n",
+                "z: \"other.api.foo_pb2.Bar\"\n",
+                "w: \"myapi.foo_pb2.Bar\"\n",
+                "__all__ = [\n    \"foo_pb2\",\n    'Bar',\n]\n",
+                "NAME = \"api.foo_pb2\"\n",
+            )
+        );
+    }
 }
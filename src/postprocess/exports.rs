@@ -0,0 +1,248 @@
+use crate::config::ModuleTransform;
+use crate::postprocess::{is_excluded, render_diff};
+use anyhow::{Context, Result};
+use glob::Pattern;
+use prost::Message;
+use prost_types::FileDescriptorSet;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Default `all_exclude` patterns: protobuf internals such as `DESCRIPTOR`
+/// and anything private by Python convention.
+pub const DEFAULT_ALL_EXCLUDE: &[&str] = &["DESCRIPTOR", "_*"];
+
+/// Collect the public names of each generated module from a FileDescriptorSet.
+///
+/// Keys are module paths relative to the output root without extension, e.g.
+/// `api/foo_pb2`. `_pb2` modules export top-level messages, enums, values of
+/// top-level enums and extensions; `_pb2_grpc` modules export the stub,
+/// servicer, registration function and experimental class of each service.
+/// Names matching any `exclude` pattern are dropped, as are modules left
+/// without names.
+pub fn collect_exports(bytes: &[u8], exclude: &[Pattern]) -> Result<BTreeMap<String, Vec<String>>> {
+    let fds = FileDescriptorSet::decode(bytes).context("decode FDS via prost-types failed")?;
+    let mut exports = BTreeMap::new();
+    for file in fds.file {
+        let Some(stem) = file.name.as_deref().and_then(|n| n.strip_suffix(".proto")) else {
+            continue;
+        };
+
+        let mut pb2: Vec<String> = Vec::new();
+        pb2.extend(file.message_type.iter().filter_map(|m| m.name.clone()));
+        for e in &file.enum_type {
+            pb2.extend(e.name.clone());
+            pb2.extend(e.value.iter().filter_map(|v| v.name.clone()));
+        }
+        pb2.extend(file.extension.iter().filter_map(|x| x.name.clone()));
+
+        let mut grpc: Vec<String> = Vec::new();
+        for s in file.service.iter().filter_map(|s| s.name.as_deref()) {
+            grpc.push(format!("{s}Stub"));
+            grpc.push(format!("{s}Servicer"));
+            grpc.push(format!("add_{s}Servicer_to_server"));
+            grpc.push(s.to_string());
+        }
+
+        for (module, names) in [
+            (format!("{stem}_pb2"), pb2),
+            (format!("{stem}_pb2_grpc"), grpc),
+        ] {
+            let names: Vec<String> = names
+                .into_iter()
+                .filter(|n| !exclude.iter().any(|p| p.matches(n)))
+                .collect();
+            if !names.is_empty() {
+                exports.insert(module, names);
+            }
+        }
+    }
+    Ok(exports)
+}
+
+/// Write `__all__` into the generated modules and stubs under `root`.
+///
+/// `exports` comes from [`collect_exports`]; module names are passed through
+/// `transform` to match renamed files. An existing `__all__` block written by
+/// a previous build is replaced, otherwise one is appended. Files matching
+/// `exclude_files` are skipped.
+///
+/// # Returns
+///
+/// The number of files (with `dry_run`, that would be) modified.
+pub fn write_all_exports(
+    root: &Path,
+    exports: &BTreeMap<String, Vec<String>>,
+    transform: Option<&ModuleTransform>,
+    exclude_files: &[Pattern],
+    dry_run: bool,
+) -> Result<usize> {
+    let mut modified = 0usize;
+    for (module, names) in exports {
+        let module = match (transform, module.rsplit_once('/')) {
+            (Some(t), Some((dir, base))) => format!("{dir}/{}", t.apply(base)),
+            (Some(t), None) => t.apply(module),
+            (None, _) => module.clone(),
+        };
+        for ext in [".py", ".pyi"] {
+            let rel = format!("{module}{ext}");
+            let path = root.join(&rel);
+            if !path.is_file() || is_excluded(exclude_files, Path::new(&rel)) {
+                continue;
+            }
+            let old =
+                fs::read_to_string(&path).with_context(|| format!("read {}", path.display()))?;
+            let new = set_dunder_all(&old, names);
+            if new == old {
+                continue;
+            }
+            if dry_run {
+                tracing::info!("{}", render_diff(&path, &old, &new));
+            } else {
+                fs::write(&path, &new).with_context(|| format!("write {}", path.display()))?;
+            }
+            modified += 1;
+        }
+    }
+    Ok(modified)
+}
+
+/// Replace the `__all__ = [ ... ]` block in `content`, or append one.
+fn set_dunder_all(content: &str, names: &[String]) -> String {
+    let block = format!(
+        "__all__ = [\n{}]\n",
+        names
+            .iter()
+            .map(|n| format!("    \"{n}\",\n"))
+            .collect::<String>()
+    );
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let start = lines.iter().position(|l| l.trim_end() == "__all__ = [");
+    let end = start.and_then(|s| {
+        lines[s..]
+            .iter()
+            .position(|l| l.trim_end() == "]")
+            .map(|i| s + i)
+    });
+    if let (Some(start), Some(end)) = (start, end) {
+        return format!(
+            "{}{}{}",
+            lines[..start].concat(),
+            block,
+            lines[end + 1..].concat()
+        );
+    }
+    let mut out = content.to_string();
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+    out.push('\n');
+    out.push_str(&block);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost_types::{
+        EnumDescriptorProto, EnumValueDescriptorProto, FileDescriptorProto, ServiceDescriptorProto,
+    };
+    use tempfile::tempdir;
+
+    fn fds() -> Vec<u8> {
+        let named = |n: &str| Some(n.to_string());
+        FileDescriptorSet {
+            file: vec![FileDescriptorProto {
+                name: named("api/foo.proto"),
+                message_type: vec![
+                    prost_types::DescriptorProto {
+                        name: named("Request"),
+                        ..Default::default()
+                    },
+                    prost_types::DescriptorProto {
+                        name: named("_Internal"),
+                        ..Default::default()
+                    },
+                ],
+                enum_type: vec![EnumDescriptorProto {
+                    name: named("Color"),
+                    value: vec![EnumValueDescriptorProto {
+                        name: named("RED"),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                service: vec![ServiceDescriptorProto {
+                    name: named("Greeter"),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        }
+        .encode_to_vec()
+    }
+
+    fn default_exclude() -> Vec<Pattern> {
+        DEFAULT_ALL_EXCLUDE
+            .iter()
+            .map(|p| Pattern::new(p).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn internal_symbols_are_excluded() {
+        let exports = collect_exports(&fds(), &default_exclude()).unwrap();
+        assert_eq!(exports["api/foo_pb2"], vec!["Request", "Color", "RED"]);
+        assert_eq!(
+            exports["api/foo_pb2_grpc"],
+            vec![
+                "GreeterStub",
+                "GreeterServicer",
+                "add_GreeterServicer_to_server",
+                "Greeter"
+            ]
+        );
+
+        let exclude = vec![Pattern::new("*Stub").unwrap()];
+        let exports = collect_exports(&fds(), &exclude).unwrap();
+        assert!(exports["api/foo_pb2"].contains(&"_Internal".to_string()));
+        assert!(!exports["api/foo_pb2_grpc"].contains(&"GreeterStub".to_string()));
+    }
+
+    #[test]
+    fn writes_and_replaces_dunder_all() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("api")).unwrap();
+        let pb2 = root.join("api/foo_pb2.py");
+        fs::write(
+            &pb2,
+            "DESCRIPTOR = _descriptor_pool.Default()\n_sym_db = None\n",
+        )
+        .unwrap();
+        let exports = collect_exports(&fds(), &default_exclude()).unwrap();
+
+        // The missing grpc module is skipped
+        assert_eq!(
+            write_all_exports(root, &exports, None, &[], false).unwrap(),
+            1
+        );
+        let text = fs::read_to_string(&pb2).unwrap();
+        assert!(text.ends_with(
+            "_sym_db = None\n\n__all__ = [\n    \"Request\",\n    \"Color\",\n    \"RED\",\n]\n"
+        ));
+        assert!(!text.contains("\"DESCRIPTOR\""));
+
+        // Rebuilding is idempotent, and a changed export list replaces the block
+        assert_eq!(
+            write_all_exports(root, &exports, None, &[], false).unwrap(),
+            0
+        );
+        let mut fewer = exports.clone();
+        fewer.insert("api/foo_pb2".into(), vec!["Request".into()]);
+        write_all_exports(root, &fewer, None, &[], false).unwrap();
+        let text = fs::read_to_string(&pb2).unwrap();
+        assert_eq!(text.matches("__all__").count(), 1);
+        assert!(text.ends_with("_sym_db = None\n\n__all__ = [\n    \"Request\",\n]\n"));
+    }
+}
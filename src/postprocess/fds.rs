@@ -2,8 +2,8 @@ use anyhow::{Context, Result};
 use prost::Message;
 use prost_reflect::DescriptorPool;
 use prost_types::FileDescriptorSet;
-use std::collections::HashSet;
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 
 /// Load a FileDescriptorSet (binary) and return a DescriptorPool
 #[allow(dead_code)]
@@ -38,6 +38,88 @@ pub fn collect_generated_basenames_from_bytes(bytes: &[u8]) -> Result<HashSet<St
     Ok(set)
 }
 
+/// The modules generated for each file of `pool`, keyed by their dotted
+/// import name, with the path of each relative to the output root: e.g.
+/// `api.v1.orders_pb2` -> `api/v1/orders_pb2.py` and `api.v1.orders_pb2_grpc`
+/// -> `api/v1/orders_pb2_grpc.py` for `api/v1/orders.proto`.
+pub fn generated_modules(pool: &DescriptorPool) -> HashMap<String, PathBuf> {
+    let mut modules = HashMap::new();
+    for file in pool.files() {
+        let Some(stem) = file.name().strip_suffix(".proto") else {
+            continue;
+        };
+        for suffix in ["_pb2", "_pb2_grpc"] {
+            let module = format!("{stem}{suffix}");
+            modules.insert(
+                module.replace('/', "."),
+                PathBuf::from(format!("{module}.py")),
+            );
+        }
+    }
+    modules
+}
+
+/// Warn about protos in the set that have no `package` declaration.
+///
+/// Their modules land at the output root, where they can collide with each
+/// other. With `require_package` the build fails instead. Returns the
+/// package-less file names, sorted.
+pub fn check_package_declarations(bytes: &[u8], require_package: bool) -> Result<Vec<String>> {
+    let fds = FileDescriptorSet::decode(bytes).context("decode FDS via prost-types failed")?;
+    let mut missing: Vec<String> = fds
+        .file
+        .into_iter()
+        .filter(|file| file.package.as_deref().unwrap_or("").is_empty())
+        .filter_map(|file| file.name)
+        .collect();
+    missing.sort();
+    for name in &missing {
+        tracing::warn!("{} has no package declaration", name);
+    }
+    if require_package && !missing.is_empty() {
+        anyhow::bail!(
+            "{} protos have no package declaration (require_package = true): {}",
+            missing.len(),
+            missing.join(", ")
+        );
+    }
+    Ok(missing)
+}
+
+/// Warn about `include` roots that no file in the set was resolved from.
+///
+/// Every compiled proto and dependency name is resolved the way protoc does,
+/// against the first include root containing it; roots that resolve nothing
+/// are reported. Returns the unused roots in configuration order.
+pub fn find_unused_includes(bytes: &[u8], include: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let fds = FileDescriptorSet::decode(bytes).context("decode FDS via prost-types failed")?;
+    let names: HashSet<&str> = fds
+        .file
+        .iter()
+        .flat_map(|f| f.name.iter().chain(&f.dependency))
+        .map(String::as_str)
+        .collect();
+    let mut used = vec![false; include.len()];
+    for name in names {
+        if let Some(i) = include.iter().position(|root| root.join(name).is_file()) {
+            used[i] = true;
+        }
+    }
+    let unused: Vec<PathBuf> = include
+        .iter()
+        .zip(used)
+        .filter(|(_, used)| !used)
+        .map(|(root, _)| root.clone())
+        .collect();
+    for root in &unused {
+        tracing::warn!(
+            "include path {} did not contribute any proto; consider removing it",
+            root.display()
+        );
+    }
+    Ok(unused)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -86,6 +168,26 @@ mod tests {
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn generated_modules_map_import_names_to_paths() {
+        let files = ["service/api.proto", "common.proto"]
+            .map(|name| FileDescriptorProto {
+                name: Some(name.to_string()),
+                ..Default::default()
+            })
+            .to_vec();
+        let bytes = FileDescriptorSet { file: files }.encode_to_vec();
+        let pool = load_fds_from_bytes(&bytes).unwrap();
+
+        let modules = generated_modules(&pool);
+        assert_eq!(modules.len(), 4);
+        assert_eq!(
+            modules["service.api_pb2_grpc"],
+            PathBuf::from("service/api_pb2_grpc.py")
+        );
+        assert_eq!(modules["common_pb2"], PathBuf::from("common_pb2.py"));
+    }
+
     #[test]
     fn collect_generated_basenames_multiple_files() {
         let files = vec![
@@ -201,4 +303,64 @@ mod tests {
                 .contains("failed to decode FileDescriptorSet")
         );
     }
+
+    #[test]
+    fn package_less_protos_warn_or_fail() {
+        let files = vec![
+            FileDescriptorProto {
+                name: Some("api/user.proto".to_string()),
+                package: Some("api".to_string()),
+                ..Default::default()
+            },
+            FileDescriptorProto {
+                name: Some("legacy.proto".to_string()),
+                ..Default::default()
+            },
+            FileDescriptorProto {
+                name: Some("common.proto".to_string()),
+                package: Some(String::new()),
+                ..Default::default()
+            },
+        ];
+        let bytes = FileDescriptorSet { file: files }.encode_to_vec();
+
+        let missing = check_package_declarations(&bytes, false).unwrap();
+        assert_eq!(missing, vec!["common.proto", "legacy.proto"]);
+
+        let err = check_package_declarations(&bytes, true).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("2 protos have no package declaration (require_package = true): common.proto, legacy.proto"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn unused_include_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let proto = dir.path().join("proto");
+        let vendor = dir.path().join("vendor");
+        let extra = dir.path().join("extra");
+        for d in [&proto, &vendor, &extra] {
+            std::fs::create_dir_all(d.join("api")).unwrap();
+        }
+        std::fs::write(proto.join("api/a.proto"), "").unwrap();
+        std::fs::write(vendor.join("api/dep.proto"), "").unwrap();
+        // Shadowed by `proto`, which comes first in the search order
+        std::fs::write(extra.join("api/a.proto"), "").unwrap();
+
+        let fds = FileDescriptorSet {
+            file: vec![FileDescriptorProto {
+                name: Some("api/a.proto".to_string()),
+                dependency: vec!["api/dep.proto".to_string()],
+                ..Default::default()
+            }],
+        };
+        let unused = find_unused_includes(
+            &fds.encode_to_vec(),
+            &[proto.clone(), vendor.clone(), extra.clone()],
+        )
+        .unwrap();
+        assert_eq!(unused, vec![extra]);
+    }
 }
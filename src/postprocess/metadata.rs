@@ -0,0 +1,147 @@
+use crate::postprocess::render_diff;
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use walkdir::WalkDir;
+
+/// File written into the output root by [`write_package_metadata`].
+pub const METADATA_FILE: &str = "pyproject.toml";
+
+/// Write a minimal setuptools `pyproject.toml` into `root` so the generated
+/// tree can be installed with `pip install <root>`.
+///
+/// `root` is mapped to the dotted `package` via `package-dir`, every directory
+/// holding Python files becomes a listed subpackage (so namespace layouts
+/// without `__init__.py` are packaged too), and `.pyi` stubs and `py.typed`
+/// markers are declared as package data. An empty `package` means `root` holds
+/// top-level packages rather than being one.
+///
+/// # Returns
+///
+/// Whether the file was (or, with `dry_run`, would be) created or changed.
+pub fn write_package_metadata(root: &Path, package: &str, dry_run: bool) -> Result<bool> {
+    let content = render_package_metadata(root, package);
+    let path = root.join(METADATA_FILE);
+    let old = fs::read_to_string(&path).unwrap_or_default();
+    if old == content {
+        return Ok(false);
+    }
+    if dry_run {
+        tracing::info!("{}", render_diff(&path, &old, &content));
+    } else {
+        fs::write(&path, &content).with_context(|| format!("write {}", path.display()))?;
+    }
+    Ok(true)
+}
+
+fn render_package_metadata(root: &Path, package: &str) -> String {
+    let mut packages = BTreeSet::new();
+    let mut has_grpc = false;
+    for entry in WalkDir::new(root)
+        .into_iter()
+        .filter_entry(|e| {
+            let name = e.file_name().to_string_lossy();
+            e.depth() == 0 || !(name.starts_with('.') || name == "__pycache__")
+        })
+        .filter_map(Result::ok)
+    {
+        let p = entry.path();
+        let name = entry.file_name().to_string_lossy();
+        if !entry.file_type().is_file() || !(name.ends_with(".py") || name.ends_with(".pyi")) {
+            continue;
+        }
+        has_grpc |= name.ends_with("_grpc.py");
+        let rel_dir = p
+            .parent()
+            .and_then(|d| d.strip_prefix(root).ok())
+            .unwrap_or(Path::new(""));
+        let mut parts: Vec<String> = Vec::new();
+        if !package.is_empty() {
+            parts.push(package.to_string());
+        }
+        parts.extend(
+            rel_dir
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned()),
+        );
+        // Modules directly under a non-package root have no package to join
+        if !parts.is_empty() {
+            packages.insert(parts.join("."));
+        }
+    }
+
+    let project = if package.is_empty() {
+        root.file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "generated".to_string())
+    } else {
+        package.to_string()
+    };
+    let mut dependencies = vec!["\"protobuf\""];
+    if has_grpc {
+        dependencies.push("\"grpcio\"");
+    }
+    let packages = packages
+        .iter()
+        .map(|p| format!("    \"{p}\",\n"))
+        .collect::<String>();
+
+    format!(
+        r#"# Generated by python-proto-importer; regenerated on every build.
+[build-system]
+requires = ["setuptools>=61"]
+build-backend = "setuptools.build_meta"
+
+[project]
+name = "{name}"
+version = "0.0.0"
+dependencies = [{deps}]
+
+[tool.setuptools]
+package-dir = {{ "{package}" = "." }}
+packages = [
+{packages}]
+
+[tool.setuptools.package-data]
+"*" = ["*.pyi", "py.typed"]
+"#,
+        name = project.replace(['.', '_'], "-"),
+        deps = dependencies.join(", "),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn metadata_maps_out_to_package_with_typed_data() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("generated");
+        fs::create_dir_all(root.join("api/v1")).unwrap();
+        fs::create_dir_all(root.join("__pycache__")).unwrap();
+        fs::write(root.join("__init__.py"), "").unwrap();
+        fs::write(root.join("api/v1/service_pb2.py"), "").unwrap();
+        fs::write(root.join("api/v1/service_pb2.pyi"), "").unwrap();
+        fs::write(root.join("api/v1/service_pb2_grpc.py"), "").unwrap();
+        fs::write(root.join("__pycache__/x.py"), "").unwrap();
+
+        assert!(write_package_metadata(&root, "mypkg.proto", false).unwrap());
+        let text = fs::read_to_string(root.join(METADATA_FILE)).unwrap();
+        assert!(text.contains("name = \"mypkg-proto\""));
+        assert!(text.contains("package-dir = { \"mypkg.proto\" = \".\" }"));
+        assert!(text.contains("    \"mypkg.proto\",\n"));
+        assert!(text.contains("    \"mypkg.proto.api.v1\",\n"));
+        assert!(!text.contains("__pycache__"));
+        assert!(text.contains("\"*\" = [\"*.pyi\", \"py.typed\"]"));
+        assert!(text.contains("dependencies = [\"protobuf\", \"grpcio\"]"));
+        // The emitted file must itself be valid TOML
+        let parsed: toml::Table = toml::from_str(&text).unwrap();
+        assert!(parsed.contains_key("project"));
+
+        // Unchanged content is not rewritten
+        assert!(!write_package_metadata(&root, "mypkg.proto", false).unwrap());
+    }
+}
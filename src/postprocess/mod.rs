@@ -11,7 +11,10 @@
 //! - **Package Creation** ([`create_packages`]): Automatically creates `__init__.py` files
 //! - **Type Checker Headers** ([`add_pyright_header`]): Adds suppression headers for type checkers
 //! - **FileDescriptorSet Processing** ([`fds`]): Extracts metadata from protoc output
-//! - **Import Analysis** ([`rel_imports`]): Scans and reports import conversion opportunities
+//! - **Export Lists** ([`exports`]): Writes `__all__` into generated modules from the FileDescriptorSet
+//! - **Package Metadata** ([`metadata`]): Writes a minimal `pyproject.toml` so `out` can be pip-installed
+//! - **Module Renaming** ([`transform`]): Renames generated modules via a configured regex and updates references
+//! - **Import Analysis** ([`rel_imports`]): Scans import conversion opportunities and flags relative imports that escape the output root
 //!
 //! # Post-processing Pipeline
 //!
@@ -22,25 +25,29 @@
 //! let output_dir = Path::new("generated");
 //!
 //! // 1. Create __init__.py files for Python package structure
-//! let packages_created = create_packages(output_dir)?;
+//! let packages_created = create_packages(output_dir, true, &[], false)?;
 //! println!("Created {} __init__.py files", packages_created);
 //!
 //! // 2. Add type checker suppression headers
-//! let headers_added = add_pyright_header(output_dir)?;
+//! let headers_added = add_pyright_header(output_dir, false)?;
 //! println!("Added headers to {} files", headers_added);
 //!
 //! # Ok::<(), anyhow::Error>(())
 //! ```
 
 use anyhow::{Context, Result};
-use std::collections::BTreeSet;
+use glob::{MatchOptions, Pattern};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
 
 pub mod apply;
+pub mod exports;
 pub mod fds;
+pub mod metadata;
 pub mod rel_imports;
+pub mod transform;
 
 /// Add Pyright suppression headers to generated Python protobuf files.
 ///
@@ -51,10 +58,11 @@ pub mod rel_imports;
 /// # Arguments
 ///
 /// * `root` - Root directory to recursively scan for protobuf Python files
+/// * `dry_run` - If true, log a diff for each file instead of writing it
 ///
 /// # Returns
 ///
-/// Returns the number of files that were modified with headers.
+/// Returns the number of files that were (or, with `dry_run`, would be) modified with headers.
 ///
 /// # Behavior
 ///
@@ -70,7 +78,7 @@ pub mod rel_imports;
 /// # pyright: reportAttributeAccessIssue=false
 /// # This file is generated by grpcio-tools and may reference grpc.experimental which lacks stubs in types-grpcio.
 /// ```
-pub fn add_pyright_header(root: &Path) -> Result<usize> {
+pub fn add_pyright_header(root: &Path, dry_run: bool) -> Result<usize> {
     use std::io::Write;
     let mut modified = 0usize;
     for entry in WalkDir::new(root).into_iter().filter_map(Result::ok) {
@@ -85,6 +93,18 @@ pub fn add_pyright_header(root: &Path) -> Result<usize> {
             if content.starts_with(header) {
                 continue;
             }
+            if dry_run {
+                tracing::info!(
+                    "{}",
+                    render_diff(
+                        p.strip_prefix(root).unwrap_or(p),
+                        &content,
+                        &format!("{header}{content}")
+                    )
+                );
+                modified += 1;
+                continue;
+            }
             let mut f = fs::OpenOptions::new()
                 .write(true)
                 .truncate(true)
@@ -107,16 +127,21 @@ pub fn add_pyright_header(root: &Path) -> Result<usize> {
 /// # Arguments
 ///
 /// * `root` - Root directory to recursively process for package creation
+/// * `include_root` - Whether `root` itself also gets an `__init__.py`. Pass `false`
+///   when `root` is a PYTHONPATH root containing several top-level packages.
+/// * `dry_run` - If true, log each missing `__init__.py` instead of creating it
 ///
 /// # Returns
 ///
-/// Returns the number of `__init__.py` files that were created.
+/// Returns the number of `__init__.py` files that were (or, with `dry_run`, would be) created.
 ///
 /// # Behavior
 ///
 /// - Recursively scans all directories under the root path
 /// - Creates empty `__init__.py` files in directories that don't have them
 /// - Skips directories that already have `__init__.py` files
+/// - Skips `root` itself when `include_root` is false
+/// - Skips directories whose `__init__.py` would match an `exclude` pattern
 /// - Uses `BTreeSet` for consistent ordering of directory processing
 ///
 /// # Package Types
@@ -128,10 +153,18 @@ pub fn add_pyright_header(root: &Path) -> Result<usize> {
 /// [tool.python_proto_importer.postprocess]
 /// create_package = false
 /// ```
-pub fn create_packages(root: &Path) -> Result<usize> {
+pub fn create_packages(
+    root: &Path,
+    include_root: bool,
+    exclude: &[Pattern],
+    dry_run: bool,
+) -> Result<usize> {
     let mut dirs: BTreeSet<PathBuf> = BTreeSet::new();
     for entry in WalkDir::new(root).into_iter().filter_map(Result::ok) {
         let path = entry.path();
+        if !include_root && entry.depth() == 0 {
+            continue;
+        }
         if path.is_dir() {
             dirs.insert(path.to_path_buf());
         }
@@ -140,7 +173,15 @@ pub fn create_packages(root: &Path) -> Result<usize> {
     let mut created = 0usize;
     for dir in dirs {
         let init_py = dir.join("__init__.py");
+        if is_excluded(exclude, init_py.strip_prefix(root).unwrap_or(&init_py)) {
+            continue;
+        }
         if !init_py.exists() {
+            if dry_run {
+                tracing::info!("would create {}", init_py.display());
+                created += 1;
+                continue;
+            }
             fs::write(&init_py, b"")
                 .with_context(|| format!("failed to write {}", init_py.display()))?;
             created += 1;
@@ -149,6 +190,264 @@ pub fn create_packages(root: &Path) -> Result<usize> {
     Ok(created)
 }
 
+/// Write empty `py.typed` markers (PEP 561) into the generated packages.
+///
+/// Without `all` only the top-level package is marked: `root` itself, or each
+/// directory directly under it when `root_is_import_root` (e.g. with
+/// `package_root_init = false`). With `all` every package directory is marked.
+/// Existing markers are left as they are, so repeated builds are no-ops.
+///
+/// # Returns
+///
+/// The number of markers (with `dry_run`, that would be) created.
+pub fn write_py_typed(
+    root: &Path,
+    root_is_import_root: bool,
+    all: bool,
+    dry_run: bool,
+) -> Result<usize> {
+    let max_depth = if all { usize::MAX } else { 1 };
+    let mut created = 0usize;
+    for entry in WalkDir::new(root)
+        .max_depth(max_depth)
+        .into_iter()
+        .filter_entry(|e| {
+            let name = e.file_name().to_string_lossy();
+            e.depth() == 0 || !(name.starts_with('.') || name == "__pycache__")
+        })
+        .filter_map(Result::ok)
+    {
+        if !entry.file_type().is_dir() {
+            continue;
+        }
+        // The import root is not a package; its children are the top level
+        let is_package = if root_is_import_root {
+            entry.depth() > 0
+        } else {
+            all || entry.depth() == 0
+        };
+        if !is_package {
+            continue;
+        }
+        let marker = entry.path().join("py.typed");
+        if marker.exists() {
+            continue;
+        }
+        if dry_run {
+            tracing::info!("would create {}", marker.display());
+        } else {
+            fs::write(&marker, b"")
+                .with_context(|| format!("failed to write {}", marker.display()))?;
+        }
+        created += 1;
+    }
+    Ok(created)
+}
+
+/// First line of the block [`write_init_reexports`] maintains in `__init__.py`.
+const REEXPORTS_BEGIN: &str = "# BEGIN python-proto-importer re-exports";
+/// Last line of the re-export block.
+const REEXPORTS_END: &str = "# END python-proto-importer re-exports";
+
+/// Write `from . import <module>` lines into each package's `__init__.py`.
+///
+/// Every directory holding generated `.py` modules (those whose basename is in
+/// `basenames`) gets a marked block re-exporting them, so consumers can reach
+/// them from the package itself, e.g. `from generated.api.v1 import *`. The
+/// block is replaced on every run, so rebuilding never duplicates lines and
+/// removed modules disappear from it; anything else in the file is kept.
+/// `include_root` and `exclude` skip the same `__init__.py` files as
+/// [`create_packages`].
+///
+/// # Returns
+///
+/// The number of `__init__.py` files (with `dry_run`, that would be) written.
+pub fn write_init_reexports(
+    root: &Path,
+    basenames: &HashSet<String>,
+    include_root: bool,
+    exclude: &[Pattern],
+    dry_run: bool,
+) -> Result<usize> {
+    let mut modules: BTreeMap<PathBuf, BTreeSet<String>> = BTreeMap::new();
+    for entry in WalkDir::new(root).into_iter().filter_map(Result::ok) {
+        let p = entry.path();
+        if entry.file_type().is_dir() {
+            modules.entry(p.to_path_buf()).or_default();
+            continue;
+        }
+        let Some(stem) = entry
+            .file_name()
+            .to_str()
+            .and_then(|n| n.strip_suffix(".py"))
+        else {
+            continue;
+        };
+        if basenames.contains(stem)
+            && let Some(dir) = p.parent()
+        {
+            modules
+                .entry(dir.to_path_buf())
+                .or_default()
+                .insert(stem.to_string());
+        }
+    }
+
+    let mut written = 0usize;
+    for (dir, names) in modules {
+        if !include_root && dir == root {
+            continue;
+        }
+        let init_py = dir.join("__init__.py");
+        if is_excluded(exclude, init_py.strip_prefix(root).unwrap_or(&init_py)) {
+            continue;
+        }
+        let old = fs::read_to_string(&init_py).unwrap_or_default();
+        let new = with_reexports(&old, &names);
+        if new == old {
+            continue;
+        }
+        if dry_run {
+            tracing::info!("{}", render_diff(&init_py, &old, &new));
+        } else {
+            fs::write(&init_py, &new)
+                .with_context(|| format!("failed to write {}", init_py.display()))?;
+        }
+        written += 1;
+    }
+    Ok(written)
+}
+
+/// Replace the re-export block in `content` with one importing `names`,
+/// dropping it when `names` is empty.
+fn with_reexports(content: &str, names: &BTreeSet<String>) -> String {
+    let mut kept = String::new();
+    let mut in_block = false;
+    for line in content.split_inclusive('\n') {
+        match line.trim_end() {
+            REEXPORTS_BEGIN => in_block = true,
+            REEXPORTS_END => in_block = false,
+            _ if !in_block => kept.push_str(line),
+            _ => {}
+        }
+    }
+    if names.is_empty() {
+        return kept;
+    }
+    let mut block = format!("{REEXPORTS_BEGIN}\n");
+    for name in names {
+        block.push_str(&format!("from . import {name}\n"));
+    }
+    block.push_str(REEXPORTS_END);
+    block.push('\n');
+    if kept.trim().is_empty() {
+        return block;
+    }
+    // One blank line before the block, however many the last run left
+    format!("{}\n\n{block}", kept.trim_end())
+}
+
+/// Whether an `__init__.py` holds nothing but what the tool wrote into it:
+/// empty, or only a [`write_init_reexports`] block.
+pub fn is_generated_init(content: &str) -> bool {
+    with_reexports(content, &BTreeSet::new()).trim().is_empty()
+}
+
+/// Whether `rel` (a path relative to the output root) matches any `exclude` pattern.
+///
+/// `*` does not cross `/`, so use `**` to cover whole subtrees, e.g. `google/**`.
+pub fn is_excluded(exclude: &[Pattern], rel: &Path) -> bool {
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    exclude
+        .iter()
+        .any(|pat| pat.matches_path_with(rel, options))
+}
+
+/// Replace an existing file with `content`, keeping its permissions.
+///
+/// The content is written to a temp file next to `path` and renamed over it,
+/// so an interrupted build never leaves a half-written module. The temp file
+/// is created private, so the original mode (e.g. an executable bit on
+/// generated scripts) is copied onto it before the rename.
+pub(crate) fn write_preserving_mode(path: &Path, content: &str) -> Result<()> {
+    use std::io::Write;
+    let permissions = fs::metadata(path)
+        .with_context(|| format!("stat {}", path.display()))?
+        .permissions();
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut tmp = tempfile::NamedTempFile::new_in(dir)
+        .with_context(|| format!("create temp file in {}", dir.display()))?;
+    tmp.write_all(content.as_bytes())
+        .with_context(|| format!("write {}", tmp.path().display()))?;
+    fs::set_permissions(tmp.path(), permissions)
+        .with_context(|| format!("set permissions of {}", tmp.path().display()))?;
+    tmp.persist(path)
+        .with_context(|| format!("replace {}", path.display()))?;
+    Ok(())
+}
+
+/// Render a unified-style diff of `old` -> `new` for dry-run logging.
+///
+/// Only changed lines are shown, each hunk introduced by its 1-based line
+/// numbers in the old and new content.
+pub(crate) fn render_diff(path: &Path, old: &str, new: &str) -> String {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+
+    // Trim the common prefix/suffix so the LCS table only covers the changed region
+    let prefix = old_lines
+        .iter()
+        .zip(&new_lines)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old_lines[prefix..]
+        .iter()
+        .rev()
+        .zip(new_lines[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old_lines[prefix..old_lines.len() - suffix];
+    let b = &new_lines[prefix..new_lines.len() - suffix];
+
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = format!("--- {0}\n+++ {0}", path.display());
+    let (mut i, mut j) = (0, 0);
+    let mut in_hunk = false;
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            in_hunk = false;
+            i += 1;
+            j += 1;
+            continue;
+        }
+        if !in_hunk {
+            out.push_str(&format!("\n@@ -{} +{} @@", prefix + i + 1, prefix + j + 1));
+            in_hunk = true;
+        }
+        if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push_str(&format!("\n-{}", a[i]));
+            i += 1;
+        } else {
+            out.push_str(&format!("\n+{}", b[j]));
+            j += 1;
+        }
+    }
+    out
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -172,7 +471,7 @@ mod tests {
         .unwrap();
         fs::write(&regular_file, "# Regular Python file\nprint('hello')\n").unwrap();
 
-        let modified = add_pyright_header(dir.path()).unwrap();
+        let modified = add_pyright_header(dir.path(), false).unwrap();
         assert_eq!(modified, 2); // Only pb2 and grpc files should be modified
 
         // Verify headers were added
@@ -197,7 +496,7 @@ mod tests {
         let existing_content = "# pyright: reportAttributeAccessIssue=false\n# This file is generated by grpcio-tools and may reference grpc.experimental which lacks stubs in types-grpcio.\n# Generated code\n";
         fs::write(&pb2_file, existing_content).unwrap();
 
-        let modified = add_pyright_header(dir.path()).unwrap();
+        let modified = add_pyright_header(dir.path(), false).unwrap();
         assert_eq!(modified, 0); // Should skip files that already have header
 
         let content = fs::read_to_string(&pb2_file).unwrap();
@@ -213,7 +512,7 @@ mod tests {
         let pb2_file = nested_dir.join("api_pb2.py");
         fs::write(&pb2_file, "# Generated code\n").unwrap();
 
-        let modified = add_pyright_header(dir.path()).unwrap();
+        let modified = add_pyright_header(dir.path(), false).unwrap();
         assert_eq!(modified, 1);
 
         let content = fs::read_to_string(&pb2_file).unwrap();
@@ -231,7 +530,7 @@ mod tests {
             fs::create_dir_all(dir.path().join(nested)).unwrap();
         }
 
-        let created = create_packages(dir.path()).unwrap();
+        let created = create_packages(dir.path(), true, &[], false).unwrap();
         // Should create __init__.py in root + 4 nested directories = 5 total
         assert_eq!(created, 5);
 
@@ -253,7 +552,7 @@ mod tests {
         // Pre-create one __init__.py file
         fs::write(nested_dir.join("__init__.py"), "# Existing content").unwrap();
 
-        let created = create_packages(dir.path()).unwrap();
+        let created = create_packages(dir.path(), true, &[], false).unwrap();
         // Should only create __init__.py in root directory
         assert_eq!(created, 1);
 
@@ -271,11 +570,53 @@ mod tests {
         let dir = tempdir().unwrap();
         // Empty directory should still get __init__.py
 
-        let created = create_packages(dir.path()).unwrap();
+        let created = create_packages(dir.path(), true, &[], false).unwrap();
         assert_eq!(created, 1);
         assert!(dir.path().join("__init__.py").exists());
     }
 
+    #[test]
+    fn create_packages_without_root_init() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("billing")).unwrap();
+        fs::create_dir_all(dir.path().join("order/v1")).unwrap();
+
+        let created = create_packages(dir.path(), false, &[], false).unwrap();
+        assert_eq!(created, 3);
+        assert!(!dir.path().join("__init__.py").exists());
+        assert!(dir.path().join("billing/__init__.py").exists());
+        assert!(dir.path().join("order/__init__.py").exists());
+        assert!(dir.path().join("order/v1/__init__.py").exists());
+    }
+
+    #[test]
+    fn create_packages_skips_excluded_dirs() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("vendor/google/protobuf")).unwrap();
+        fs::create_dir_all(dir.path().join("api")).unwrap();
+
+        let exclude = vec![Pattern::new("vendor/**").unwrap()];
+        let created = create_packages(dir.path(), true, &exclude, false).unwrap();
+        assert_eq!(created, 2);
+        assert!(dir.path().join("__init__.py").exists());
+        assert!(dir.path().join("api/__init__.py").exists());
+        // `vendor/**` covers `vendor/__init__.py` as well
+        assert!(!dir.path().join("vendor/__init__.py").exists());
+        assert!(!dir.path().join("vendor/google/__init__.py").exists());
+        assert!(
+            !dir.path()
+                .join("vendor/google/protobuf/__init__.py")
+                .exists()
+        );
+    }
+
+    #[test]
+    fn is_excluded_star_does_not_cross_directories() {
+        let exclude = vec![Pattern::new("third_party/*.py").unwrap()];
+        assert!(is_excluded(&exclude, Path::new("third_party/a_pb2.py")));
+        assert!(!is_excluded(&exclude, Path::new("third_party/x/a_pb2.py")));
+    }
+
     #[test]
     fn add_pyright_header_file_extension_filtering() {
         let dir = tempdir().unwrap();
@@ -286,7 +627,7 @@ mod tests {
         fs::write(dir.path().join("service_pb2.txt"), "# Text file").unwrap();
         fs::write(dir.path().join("service.py"), "# Regular Python").unwrap();
 
-        let modified = add_pyright_header(dir.path()).unwrap();
+        let modified = add_pyright_header(dir.path(), false).unwrap();
         // Only .py files with correct naming should be modified
         assert_eq!(modified, 1);
 
@@ -299,4 +640,109 @@ mod tests {
         let regular_content = fs::read_to_string(dir.path().join("service.py")).unwrap();
         assert!(!regular_content.contains("pyright"));
     }
+
+    #[test]
+    fn dry_run_leaves_files_untouched() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("services")).unwrap();
+        fs::write(dir.path().join("services/api_pb2.py"), "# Generated code\n").unwrap();
+
+        assert_eq!(create_packages(dir.path(), true, &[], true).unwrap(), 2);
+        assert_eq!(add_pyright_header(dir.path(), true).unwrap(), 1);
+
+        assert!(!dir.path().join("__init__.py").exists());
+        assert!(!dir.path().join("services/__init__.py").exists());
+        let content = fs::read_to_string(dir.path().join("services/api_pb2.py")).unwrap();
+        assert_eq!(content, "# Generated code\n");
+    }
+
+    #[test]
+    fn render_diff_shows_changed_lines_only() {
+        let old = "# header\nimport a_pb2 as a__pb2\nx = 1\nimport b_pb2 as b__pb2\n";
+        let new = "# header\nfrom . import a_pb2 as a__pb2\nx = 1\nfrom . import b_pb2 as b__pb2\n";
+        let diff = render_diff(Path::new("pkg/c_pb2.py"), old, new);
+        assert_eq!(
+            diff,
+            "--- pkg/c_pb2.py\n+++ pkg/c_pb2.py\n\
+             @@ -2 +2 @@\n-import a_pb2 as a__pb2\n+from . import a_pb2 as a__pb2\n\
+             @@ -4 +4 @@\n-import b_pb2 as b__pb2\n+from . import b_pb2 as b__pb2"
+        );
+    }
+
+    #[test]
+    fn init_reexports_are_idempotent() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("api/v1")).unwrap();
+        for f in ["service_pb2.py", "service_pb2_grpc.py", "service_pb2.pyi"] {
+            fs::write(root.join("api/v1").join(f), "").unwrap();
+        }
+        fs::write(root.join("api/v1/helpers.py"), "").unwrap();
+        fs::write(root.join("api/__init__.py"), "VERSION = 1\n").unwrap();
+        let basenames: HashSet<String> = ["service_pb2", "service_pb2_grpc"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        assert_eq!(
+            write_init_reexports(root, &basenames, true, &[], false).unwrap(),
+            1
+        );
+        let init = fs::read_to_string(root.join("api/v1/__init__.py")).unwrap();
+        assert_eq!(
+            init,
+            format!(
+                "{REEXPORTS_BEGIN}\nfrom . import service_pb2\nfrom . import service_pb2_grpc\n{REEXPORTS_END}\n"
+            )
+        );
+        assert!(is_generated_init(&init));
+        // Packages without generated modules are left alone
+        assert_eq!(
+            fs::read_to_string(root.join("api/__init__.py")).unwrap(),
+            "VERSION = 1\n"
+        );
+
+        // Rebuilding rewrites nothing; a removed module drops out of the block
+        assert_eq!(
+            write_init_reexports(root, &basenames, true, &[], false).unwrap(),
+            0
+        );
+        fs::write(root.join("api/v1/__init__.py"), format!("X = 1\n\n{init}")).unwrap();
+        fs::remove_file(root.join("api/v1/service_pb2_grpc.py")).unwrap();
+        write_init_reexports(root, &basenames, true, &[], false).unwrap();
+        let init = fs::read_to_string(root.join("api/v1/__init__.py")).unwrap();
+        assert_eq!(
+            init,
+            format!("X = 1\n\n{REEXPORTS_BEGIN}\nfrom . import service_pb2\n{REEXPORTS_END}\n")
+        );
+        assert!(!is_generated_init(&init));
+    }
+
+    #[test]
+    fn py_typed_marks_top_level_or_all_packages() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("api/v1/__pycache__")).unwrap();
+        fs::create_dir_all(root.join("common")).unwrap();
+
+        assert_eq!(write_py_typed(root, false, false, false).unwrap(), 1);
+        assert!(root.join("py.typed").is_file());
+        assert!(!root.join("api/py.typed").exists());
+        // Idempotent
+        assert_eq!(write_py_typed(root, false, false, false).unwrap(), 0);
+
+        assert_eq!(write_py_typed(root, false, true, false).unwrap(), 3);
+        assert!(root.join("api/v1/py.typed").is_file());
+        assert!(!root.join("api/v1/__pycache__/py.typed").exists());
+
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("api/v1")).unwrap();
+        fs::create_dir_all(root.join("common")).unwrap();
+        assert_eq!(write_py_typed(root, true, false, false).unwrap(), 2);
+        assert!(!root.join("py.typed").exists());
+        assert!(root.join("api/py.typed").is_file());
+        assert!(root.join("common/py.typed").is_file());
+        assert!(!root.join("api/v1/py.typed").exists());
+    }
 }
@@ -1,44 +1,130 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use regex::Regex;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
 
 /// Very small scaffold for future import rewriting.
 /// For now, it only identifies candidate lines and returns count.
 #[allow(dead_code)]
 pub fn rewrite_file_for_relative_imports(path: &Path) -> Result<usize> {
     let content = fs::read_to_string(path)?;
-    let import_re = Regex::new(r"(?m)^import\s+([A-Za-z0-9_\.]+_pb2(?:_grpc)?)\b").unwrap();
-    let from_re =
-        Regex::new(r"(?m)^from\s+([A-Za-z0-9_\.]+)\s+import\s+([A-Za-z0-9_]+_pb2(?:_grpc)?)\b")
-            .unwrap();
-
-    let mut hits = 0usize;
-    hits += import_re.find_iter(&content).count();
-    hits += from_re.find_iter(&content).count();
+    Ok(candidate_lines(&content).len())
+}
 
-    // No modifications yet; further phases will compute and apply rewrites.
-    Ok(hits)
+/// A Python source under the output root with its content as scanned.
+#[derive(Debug, Clone)]
+pub struct ScannedFile {
+    pub path: PathBuf,
+    pub content: String,
+    /// Lines importing a `_pb2`/`_pb2_grpc` module by absolute name
+    pub candidates: Vec<String>,
 }
 
-/// Walk output tree and report count of candidate files/lines (dry-run).
-#[allow(dead_code)]
-pub fn scan_and_report(root: &Path) -> Result<(usize, usize)> {
-    let mut files = 0usize;
-    let mut lines = 0usize;
+/// Read each `.py`/`.pyi` file under `root` in turn and hand it to `visit`
+/// with its candidate lines.
+///
+/// The candidate report and the import rewriter share this single read; only
+/// one file is held in memory at a time. Returns the number of files scanned
+/// and of candidate lines found in them.
+pub fn scan_tree(
+    root: &Path,
+    mut visit: impl FnMut(ScannedFile) -> Result<()>,
+) -> Result<(usize, usize)> {
+    let (mut files, mut lines) = (0, 0);
     for entry in walkdir::WalkDir::new(root)
         .into_iter()
         .filter_map(Result::ok)
     {
         let p = entry.path();
-        if p.is_file() && p.extension().and_then(|e| e.to_str()) == Some("py") {
-            files += 1;
-            lines += rewrite_file_for_relative_imports(p)?;
+        let is_py = matches!(
+            p.extension().and_then(|e| e.to_str()),
+            Some("py") | Some("pyi")
+        );
+        if !p.is_file() || !is_py {
+            continue;
         }
+        let content = fs::read_to_string(p).with_context(|| format!("read {}", p.display()))?;
+        let candidates = candidate_lines(&content);
+        files += 1;
+        lines += candidates.len();
+        visit(ScannedFile {
+            path: p.to_path_buf(),
+            candidates,
+            content,
+        })?;
     }
     Ok((files, lines))
 }
 
+/// Walk output tree and report count of candidate files/lines (dry-run).
+pub fn scan_and_report(root: &Path) -> Result<(usize, usize)> {
+    scan_tree(root, |_| Ok(()))
+}
+
+static CANDIDATE_IMPORT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^import\s+([A-Za-z0-9_\.]+_pb2(?:_grpc)?)\b").unwrap());
+static CANDIDATE_FROM: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^from\s+([A-Za-z0-9_\.]+)\s+import\s+([A-Za-z0-9_]+_pb2(?:_grpc)?)\b").unwrap()
+});
+
+fn candidate_lines(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .filter(|l| CANDIDATE_IMPORT.is_match(l) || CANDIDATE_FROM.is_match(l))
+        .map(str::to_string)
+        .collect()
+}
+
+/// A relative import whose leading dots climb above the output root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EscapingImport {
+    /// File containing the import.
+    pub file: PathBuf,
+    /// 1-based line number of the `from` statement.
+    pub line: usize,
+    /// The offending statement, trimmed.
+    pub statement: String,
+}
+
+static FROM_RELATIVE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*from\s+(?P<dots>\.+)[A-Za-z0-9_\.]*\s+import\b").unwrap());
+
+/// Statically check that the relative imports in `content`, the source of
+/// `path` under `root`, stay within `root`.
+///
+/// For `from <dots><rest> import ...`, the import walks up `dots - 1` packages
+/// from the file's directory. If that exceeds the packages between `root` and
+/// the file, the target lies outside the generated tree, which usually means
+/// an off-by-one in the rewrite. When `root_is_package` is false, `root` is an
+/// import root rather than a package (see
+/// [`PostProcess::out_is_import_root`](crate::config::PostProcess::out_is_import_root)),
+/// so there is one package fewer to climb and modules directly under it
+/// cannot use relative imports at all.
+pub fn find_escaping_relative_imports(
+    root: &Path,
+    path: &Path,
+    content: &str,
+    root_is_package: bool,
+) -> Vec<EscapingImport> {
+    // Packages between root and the file's directory, counting root itself
+    let rel = path.strip_prefix(root).unwrap_or(path);
+    let depth = rel.components().count().saturating_sub(1) + usize::from(root_is_package);
+    content
+        .lines()
+        .enumerate()
+        .filter_map(|(idx, line)| {
+            let caps = FROM_RELATIVE.captures(line)?;
+            // `.` resolves within the file's own package, so needs one
+            (caps["dots"].len() > depth).then(|| EscapingImport {
+                file: path.to_path_buf(),
+                line: idx + 1,
+                statement: line.trim().to_string(),
+            })
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -178,6 +264,100 @@ import another_service_pb2
         assert_eq!(lines, 0); // But no proto import lines
     }
 
+    #[test]
+    fn scan_tree_keeps_content_and_candidate_lines() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("api")).unwrap();
+        let stub = "import grpc\nfrom api import user_pb2\n";
+        fs::write(dir.path().join("api/service_pb2.pyi"), stub).unwrap();
+        fs::write(dir.path().join("api/user_pb2.py"), "x = 1\n").unwrap();
+        fs::write(dir.path().join("README.md"), "import service_pb2\n").unwrap();
+
+        let mut files = Vec::new();
+        let counts = scan_tree(dir.path(), |file| {
+            files.push(file);
+            Ok(())
+        })
+        .unwrap();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(counts, (2, 1));
+        assert_eq!(files[0].path, dir.path().join("api/service_pb2.pyi"));
+        assert_eq!(files[0].content, stub);
+        assert_eq!(files[0].candidates, vec!["from api import user_pb2"]);
+        assert!(files[1].candidates.is_empty());
+    }
+
+    /// Escaping imports in each `(path, content)` under `/out`.
+    fn escaping(files: &[(&str, &str)], root_is_package: bool) -> Vec<(String, usize)> {
+        let root = Path::new("/out");
+        files
+            .iter()
+            .flat_map(|(rel, content)| {
+                find_escaping_relative_imports(root, &root.join(rel), content, root_is_package)
+            })
+            .map(|e| {
+                (
+                    e.file.strip_prefix(root).unwrap().display().to_string(),
+                    e.line,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn escaping_imports_none_for_valid_tree() {
+        let files = [
+            ("root_pb2.py", "from . import other_pb2\n"),
+            (
+                "billing/billing_pb2.py",
+                "from ..order import order_pb2\nfrom . import types_pb2\n",
+            ),
+        ];
+        assert!(escaping(&files, true).is_empty());
+    }
+
+    #[test]
+    fn escaping_imports_detects_over_dotted() {
+        let root = Path::new("/out");
+        let found = find_escaping_relative_imports(
+            root,
+            &root.join("billing/billing_pb2.pyi"),
+            "import grpc\nfrom ...order import order_pb2\n",
+            true,
+        );
+        assert_eq!(
+            found,
+            vec![EscapingImport {
+                file: root.join("billing/billing_pb2.pyi"),
+                line: 2,
+                statement: "from ...order import order_pb2".to_string(),
+            }]
+        );
+        assert_eq!(
+            escaping(&[("top_pb2.py", "from .. import x_pb2\n")], true),
+            vec![("top_pb2.py".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn escaping_imports_climb_one_less_below_an_import_root() {
+        let files = [
+            ("root_pb2.py", "from . import other_pb2\n"),
+            (
+                "pkg/x_pb2.py",
+                "from . import types_pb2\nfrom ..other import y_pb2\n",
+            ),
+            ("pkg/sub/z_pb2.py", "from ..other import y_pb2\n"),
+        ];
+        assert_eq!(
+            escaping(&files, false),
+            vec![
+                ("root_pb2.py".to_string(), 1),
+                ("pkg/x_pb2.py".to_string(), 2)
+            ]
+        );
+    }
+
     #[test]
     fn rewrite_file_nonexistent_file() {
         let nonexistent = std::path::Path::new("/nonexistent/file.py");
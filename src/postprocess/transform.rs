@@ -0,0 +1,177 @@
+use crate::config::ModuleTransform;
+use anyhow::{Context, Result, bail};
+use regex::Regex;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+fn split_python_file_name(name: &str) -> Option<(&str, &str)> {
+    name.strip_suffix(".pyi")
+        .map(|stem| (stem, ".pyi"))
+        .or_else(|| name.strip_suffix(".py").map(|stem| (stem, ".py")))
+}
+
+/// Rename generated modules under `root` according to `transform`.
+///
+/// Every `.py`/`.pyi` file (except `__init__`) whose basename changes under the
+/// substitution is renamed on disk, then every whole-word reference to an old
+/// basename in the tree is replaced with the new one so imports keep resolving.
+///
+/// # Returns
+///
+/// A map from old module basename to new module basename, for callers that
+/// need to adjust other name sets (e.g. FDS-derived basenames).
+///
+/// # Errors
+///
+/// Fails if a transformed name is empty or collides with an existing file.
+pub fn apply_module_transform(
+    root: &Path,
+    transform: &ModuleTransform,
+) -> Result<BTreeMap<String, String>> {
+    let mut renames: Vec<(PathBuf, PathBuf)> = Vec::new();
+    let mut mapping: BTreeMap<String, String> = BTreeMap::new();
+    let mut targets: HashSet<PathBuf> = HashSet::new();
+
+    for entry in WalkDir::new(root).into_iter().filter_map(Result::ok) {
+        let p = entry.path();
+        if !p.is_file() {
+            continue;
+        }
+        let Some(name) = p.file_name().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        let Some((stem, ext)) = split_python_file_name(name) else {
+            continue;
+        };
+        if stem == "__init__" {
+            continue;
+        }
+        let new_stem = transform.apply(stem);
+        if new_stem == stem {
+            continue;
+        }
+        if new_stem.is_empty() {
+            bail!("module_transform maps {} to an empty name", p.display());
+        }
+        let target = p.with_file_name(format!("{new_stem}{ext}"));
+        if target.exists() || !targets.insert(target.clone()) {
+            bail!(
+                "module_transform renames {} to {}, which already exists",
+                p.display(),
+                target.display()
+            );
+        }
+        mapping.insert(stem.to_string(), new_stem);
+        renames.push((p.to_path_buf(), target));
+    }
+
+    for (from, to) in &renames {
+        fs::rename(from, to)
+            .with_context(|| format!("rename {} -> {}", from.display(), to.display()))?;
+    }
+    if mapping.is_empty() {
+        return Ok(mapping);
+    }
+
+    let patterns = mapping
+        .iter()
+        .map(|(old, new)| {
+            let re = Regex::new(&format!(r"\b{}\b", regex::escape(old))).unwrap();
+            (re, new.as_str())
+        })
+        .collect::<Vec<_>>();
+
+    for entry in WalkDir::new(root).into_iter().filter_map(Result::ok) {
+        let p = entry.path();
+        let is_python = p
+            .file_name()
+            .and_then(|s| s.to_str())
+            .and_then(split_python_file_name)
+            .is_some();
+        if !p.is_file() || !is_python {
+            continue;
+        }
+        let content = fs::read_to_string(p).with_context(|| format!("read {}", p.display()))?;
+        let mut updated = content.clone();
+        for (re, new) in &patterns {
+            updated = re.replace_all(&updated, *new).into_owned();
+        }
+        if updated != content {
+            fs::write(p, updated).with_context(|| format!("write {}", p.display()))?;
+        }
+    }
+
+    Ok(mapping)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn strip_version() -> ModuleTransform {
+        ModuleTransform {
+            pattern: Regex::new("_v[0-9]+(_pb2)").unwrap(),
+            replacement: "$1".to_string(),
+        }
+    }
+
+    #[test]
+    fn strips_version_suffix_and_updates_imports() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("api")).unwrap();
+        fs::write(root.join("api/types_v1_pb2.py"), "# types\n").unwrap();
+        fs::write(root.join("api/types_v1_pb2.pyi"), "# types stub\n").unwrap();
+        fs::write(
+            root.join("api/service_v1_pb2.py"),
+            "from api import types_v1_pb2 as api_dot_types__v1__pb2\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("api/service_v1_pb2_grpc.py"),
+            "from api import service_v1_pb2 as api_dot_service__v1__pb2\n",
+        )
+        .unwrap();
+
+        let mapping = apply_module_transform(root, &strip_version()).unwrap();
+
+        assert_eq!(mapping.get("types_v1_pb2").unwrap(), "types_pb2");
+        assert_eq!(
+            mapping.get("service_v1_pb2_grpc").unwrap(),
+            "service_pb2_grpc"
+        );
+        assert!(root.join("api/types_pb2.py").exists());
+        assert!(root.join("api/types_pb2.pyi").exists());
+        assert!(root.join("api/service_pb2_grpc.py").exists());
+        assert!(!root.join("api/types_v1_pb2.py").exists());
+
+        let service = fs::read_to_string(root.join("api/service_pb2.py")).unwrap();
+        // Only the module name changes; protoc-style aliases are left alone
+        assert_eq!(
+            service,
+            "from api import types_pb2 as api_dot_types__v1__pb2\n"
+        );
+        let grpc = fs::read_to_string(root.join("api/service_pb2_grpc.py")).unwrap();
+        assert_eq!(
+            grpc,
+            "from api import service_pb2 as api_dot_service__v1__pb2\n"
+        );
+    }
+
+    #[test]
+    fn collision_fails() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("a_v1_pb2.py"), "").unwrap();
+        fs::write(root.join("a_v2_pb2.py"), "").unwrap();
+
+        let err = apply_module_transform(root, &strip_version()).unwrap_err();
+        assert!(err.to_string().contains("already exists"));
+        // Nothing is renamed when a collision is detected
+        assert!(root.join("a_v1_pb2.py").exists());
+        assert!(root.join("a_v2_pb2.py").exists());
+    }
+}
@@ -1,17 +1,219 @@
-use anyhow::{Context, Result};
+use std::ffi::OsString;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
 
-/// Run a command with the given arguments
-pub fn run_cmd(cmd: &[String]) -> Result<()> {
-    let mut it = cmd.iter();
-    let prog = it.next().ok_or_else(|| anyhow::anyhow!("empty command"))?;
-    let status = std::process::Command::new(prog)
-        .args(it)
-        .status()
-        .with_context(|| format!("failed to run {}", prog))?;
-    if !status.success() {
-        anyhow::bail!("command failed: {} (status {:?})", prog, status.code());
+/// Like [`Command::output`], but kills the child once `timeout` elapses.
+///
+/// Returns `Ok(None)` when the child was killed. stdout and stderr are
+/// drained on background threads so a chatty child cannot block on a full
+/// pipe while we wait. On unix the child runs in its own process group, so a
+/// timeout also kills what it spawned (protoc plugins, grpc_tools' python).
+/// SIGINT and SIGTERM are forwarded to that group while it runs, so Ctrl-C
+/// still stops it even though it left the terminal's foreground group.
+/// Collecting the output is bounded by the same deadline: a grandchild that
+/// outlives the child and keeps its pipes open counts as a timeout too.
+pub fn output_with_timeout(
+    cmd: &mut Command,
+    timeout: Option<Duration>,
+) -> io::Result<Option<Output>> {
+    let Some(limit) = timeout else {
+        return cmd.output().map(Some);
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
     }
-    Ok(())
+    let deadline = Instant::now() + limit;
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    #[cfg(unix)]
+    let _forwarding = process_groups::register(child.id() as libc::pid_t);
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+    let Some(status) = wait_until(&mut child, deadline)? else {
+        return Ok(None);
+    };
+    let collect = |rx: &Receiver<Vec<u8>>| match rx
+        .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+    {
+        Ok(buf) => Some(buf),
+        Err(RecvTimeoutError::Disconnected) => Some(Vec::new()),
+        Err(RecvTimeoutError::Timeout) => None,
+    };
+    let (Some(stdout), Some(stderr)) = (collect(&stdout), collect(&stderr)) else {
+        kill_group(&child);
+        return Ok(None);
+    };
+    Ok(Some(Output {
+        status,
+        stdout,
+        stderr,
+    }))
+}
+
+/// "timed out after N seconds", for errors about a killed subprocess.
+pub fn timed_out(timeout: Option<Duration>) -> String {
+    format!(
+        "timed out after {} seconds",
+        timeout.unwrap_or_default().as_secs()
+    )
+}
+
+/// Locate an external tool: the path in `env_var` when it is set and
+/// non-empty (e.g. `PROTOC`, `BUF`), otherwise `name` looked up on PATH.
+pub fn find_tool(env_var: &str, name: &str) -> Option<PathBuf> {
+    env_override(std::env::var_os(env_var)).or_else(|| which::which(name).ok())
+}
+
+/// The path an environment variable points at, ignoring empty values.
+pub fn env_override(value: Option<OsString>) -> Option<PathBuf> {
+    value.filter(|v| !v.is_empty()).map(PathBuf::from)
+}
+
+/// Lexically resolve `.` and `..` components so prefix checks are reliable.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for comp in path.components() {
+        match comp {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+/// Write an executable shell script named `protoc` into `dir`, running
+/// `body`, to stand in for protoc in tests.
+#[cfg(all(test, unix))]
+pub(crate) fn fake_protoc(dir: &Path, body: &str) -> PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+    let path = dir.join("protoc");
+    std::fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+/// Poll `child` until it exits or `deadline` passes, killing it (and on unix
+/// its process group) in the latter case.
+fn wait_until(child: &mut Child, deadline: Instant) -> io::Result<Option<ExitStatus>> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            kill_group(child);
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// Kill the process group led by `child`; a no-op off unix.
+fn kill_group(child: &Child) {
+    #[cfg(unix)]
+    // SAFETY: kill(2) has no memory-safety preconditions; the negated
+    // pid addresses the group the child leads
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    #[cfg(not(unix))]
+    let _ = child;
+}
+
+/// Process groups of the children [`output_with_timeout`] is running, so that
+/// SIGINT/SIGTERM sent to the CLI reach them as well.
+#[cfg(unix)]
+mod process_groups {
+    use std::sync::Once;
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    /// Registered group ids; 0 marks a free slot. Fixed-size so the signal
+    /// handler never allocates or locks.
+    pub(super) static GROUPS: [AtomicI32; 16] = [const { AtomicI32::new(0) }; 16];
+
+    /// Keeps a group registered until dropped.
+    pub(super) struct Registration(Option<usize>);
+
+    impl Drop for Registration {
+        fn drop(&mut self) {
+            if let Some(slot) = self.0 {
+                GROUPS[slot].store(0, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Register `pgid` for forwarding, installing the handlers on first use.
+    pub(super) fn register(pgid: libc::pid_t) -> Registration {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| {
+            for sig in [libc::SIGINT, libc::SIGTERM] {
+                // SAFETY: `handle` only calls async-signal-safe functions
+                unsafe {
+                    let previous = libc::signal(
+                        sig,
+                        handle as extern "C" fn(libc::c_int) as libc::sighandler_t,
+                    );
+                    // Leave signals the CLI was started with ignored alone
+                    if previous == libc::SIG_IGN {
+                        libc::signal(sig, libc::SIG_IGN);
+                    }
+                }
+            }
+        });
+        Registration(GROUPS.iter().position(|slot| {
+            slot.compare_exchange(0, pgid, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        }))
+    }
+
+    /// Send `sig` to every group in `groups`.
+    pub(super) fn forward(groups: &[AtomicI32], sig: libc::c_int) {
+        for slot in groups {
+            let pgid = slot.load(Ordering::SeqCst);
+            if pgid > 0 {
+                // SAFETY: kill(2) is async-signal-safe
+                unsafe {
+                    libc::kill(-pgid, sig);
+                }
+            }
+        }
+    }
+
+    /// Forward `sig`, then terminate the way the default disposition would.
+    extern "C" fn handle(sig: libc::c_int) {
+        forward(&GROUPS, sig);
+        // SAFETY: signal(2) and raise(3) are async-signal-safe
+        unsafe {
+            libc::signal(sig, libc::SIG_DFL);
+            libc::raise(sig);
+        }
+    }
+}
+
+/// Read `pipe` to the end on a background thread, sending the bytes once done.
+fn drain<R: Read + Send + 'static>(pipe: Option<R>) -> Receiver<Vec<u8>> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        let _ = tx.send(buf);
+    });
+    rx
 }
 
 #[cfg(test)]
@@ -19,33 +221,90 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_run_cmd_empty_command() {
-        let cmd = vec![];
-        let result = run_cmd(&cmd);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("empty command"));
+    fn env_override_ignores_unset_and_empty_values() {
+        assert_eq!(env_override(None), None);
+        assert_eq!(env_override(Some(OsString::new())), None);
+        assert_eq!(
+            env_override(Some(OsString::from("/opt/protoc/bin/protoc"))),
+            Some(PathBuf::from("/opt/protoc/bin/protoc"))
+        );
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_run_cmd_success() {
-        let cmd = vec!["echo".to_string(), "hello".to_string()];
-        let result = run_cmd(&cmd);
-        assert!(result.is_ok());
+    fn test_output_with_timeout_kills_child() {
+        let mut cmd = Command::new("sleep");
+        cmd.arg("30");
+        let started = Instant::now();
+        let output = output_with_timeout(&mut cmd, Some(Duration::from_secs(1))).unwrap();
+        assert!(output.is_none());
+        assert!(started.elapsed() < Duration::from_secs(10));
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_run_cmd_failure() {
-        let cmd = vec!["false".to_string()];
-        let result = run_cmd(&cmd);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("command failed"));
+    fn test_output_with_timeout_kills_grandchildren() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("survived");
+        // A backgrounded grandchild that outlives the timeout leaves a marker
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+            .arg(format!("(sleep 2; touch {}) & wait", marker.display()));
+        let output = output_with_timeout(&mut cmd, Some(Duration::from_secs(1))).unwrap();
+        assert!(output.is_none());
+        thread::sleep(Duration::from_secs(3));
+        assert!(!marker.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_output_with_timeout_stops_waiting_for_held_pipes() {
+        // sh exits at once, but the backgrounded sleep keeps stdout open
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "sleep 30 &"]);
+        let started = Instant::now();
+        let output = output_with_timeout(&mut cmd, Some(Duration::from_secs(1))).unwrap();
+        assert!(output.is_none());
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_registered_process_groups_receive_forwarded_signals() {
+        use std::os::unix::process::{CommandExt, ExitStatusExt};
+        use std::sync::atomic::{AtomicI32, Ordering};
+        let mut child = Command::new("sleep")
+            .arg("30")
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let pgid = child.id() as libc::pid_t;
+
+        let registration = process_groups::register(pgid);
+        let registered = || {
+            process_groups::GROUPS
+                .iter()
+                .any(|slot| slot.load(Ordering::SeqCst) == pgid)
+        };
+        assert!(registered());
+        drop(registration);
+        assert!(!registered());
+
+        // A private slot table, so other tests' children are left alone
+        process_groups::forward(&[AtomicI32::new(pgid), AtomicI32::new(0)], libc::SIGTERM);
+        assert_eq!(child.wait().unwrap().signal(), Some(libc::SIGTERM));
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_run_cmd_nonexistent_program() {
-        let cmd = vec!["nonexistent_program_xyz123".to_string()];
-        let result = run_cmd(&cmd);
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("failed to run"));
+    fn test_output_with_timeout_captures_output() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo out; echo err >&2"]);
+        let output = output_with_timeout(&mut cmd, Some(Duration::from_secs(10)))
+            .unwrap()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr, b"err\n");
     }
 }
@@ -10,6 +10,7 @@
 //! - **Import Testing** ([`import_test`]): Validates that all generated modules can be imported
 //! - **Package Structure Analysis** ([`package_structure`]): Determines optimal Python package layout
 //! - **Test Script Generation** ([`script_generator`]): Creates dynamic test scripts for validation
+//! - **Type Checking** ([`typecheck`]): Runs mypy/pyright with JSON output and reports diagnostics per file
 //!
 //! # Verification Pipeline
 //!
@@ -18,7 +19,7 @@
 //! 1. **Package Structure Detection**: Analyzes the output directory structure
 //! 2. **Import Script Generation**: Creates a Python test script to import all modules  
 //! 3. **Import Execution**: Runs the test script in the appropriate Python environment
-//! 4. **Type Checking** (optional): Executes configured mypy/pyright commands
+//! 4. **Type Checking** (optional): Executes configured mypy/pyright commands, failing only on errors
 //! 5. **Result Analysis**: Reports any failures with actionable error messages
 //!
 //! # Usage Example
@@ -39,6 +40,13 @@
 pub mod import_test;
 pub mod package_structure;
 pub mod script_generator;
+pub mod typecheck;
 
+pub use import_test::{
+    FailureKind, ModuleFailure, VerifyFailed, VerifyReport, render_failure_summary, verify,
+    verify_modules,
+};
 pub use package_structure::{determine_package_structure, determine_package_structure_legacy};
-pub use script_generator::create_import_test_script;
+pub use script_generator::{
+    create_import_test_script, create_side_effect_test_script, sentinel_nonce, strip_sentinel,
+};
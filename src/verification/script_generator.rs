@@ -1,9 +1,37 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Generate a per-run prefix for the sentinel lines the test scripts print.
+///
+/// The result lines of a script read `<nonce>TAG:...`. Imported modules can
+/// print anything to stderr, but they cannot guess the nonce, so a module
+/// printing e.g. `IMPORT_TEST_SUCCESS:` cannot be mistaken for a result.
+pub fn sentinel_nonce() -> String {
+    let mut h = RandomState::new().build_hasher();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    h.write_u128(now);
+    h.write_u32(std::process::id());
+    format!("PPI{:016x}:", h.finish())
+}
+
+/// Return the payload of `line` if it is the `tag` sentinel for `nonce`.
+pub fn strip_sentinel<'a>(line: &'a str, nonce: &str, tag: &str) -> Option<&'a str> {
+    line.strip_prefix(nonce)?
+        .strip_prefix(tag)?
+        .strip_prefix(':')
+}
+
 /// Create a comprehensive Python import test script
 ///
 /// This function generates a Python script that attempts to import all provided modules
 /// and reports success/failure statistics to stderr. The script handles various types
-/// of import errors and provides detailed error reporting.
-pub fn create_import_test_script(package_name: &str, modules: &[String]) -> String {
+/// of import errors and provides detailed error reporting. Result lines are
+/// prefixed with `nonce` (see [`sentinel_nonce`]).
+pub fn create_import_test_script(package_name: &str, modules: &[String], nonce: &str) -> String {
     let mut script = String::new();
     script.push_str("import sys\n");
     script.push_str("import importlib\n");
@@ -44,27 +72,78 @@ except Exception as e:
     }
 
     script.push('\n');
-    script.push_str("print(f'IMPORT_TEST_SUMMARY:succeeded={len(succeeded)},failed={len(failed)},total={len(succeeded)+len(failed)}', file=sys.stderr)\n");
+    script.push_str(&format!("print(f'{nonce}IMPORT_TEST_SUMMARY:succeeded={{len(succeeded)}},failed={{len(failed)}},total={{len(succeeded)+len(failed)}}', file=sys.stderr)\n"));
     script.push('\n');
     script.push_str("if failed:\n");
     script.push_str("    for module, error in failed:\n");
-    script.push_str("        print(f'IMPORT_ERROR:{module}:{error}', file=sys.stderr)\n");
+    script.push_str(&format!(
+        "        print(f'{nonce}IMPORT_ERROR:{{module}}:{{error}}', file=sys.stderr)\n"
+    ));
     script.push_str("    sys.exit(1)\n");
     script.push_str("else:\n");
-    script.push_str(
-        "    print('IMPORT_TEST_SUCCESS:all_modules_imported_successfully', file=sys.stderr)\n",
-    );
+    script.push_str(&format!(
+        "    print('{nonce}IMPORT_TEST_SUCCESS:all_modules_imported_successfully', file=sys.stderr)\n"
+    ));
 
     script
 }
 
+/// Create a script importing `full_module` with side-effect instrumentation
+///
+/// `builtins.open` and `threading.Thread.start` are wrapped to record calls
+/// made while the module is imported; each is printed to stderr as
+/// `<nonce>SIDE_EFFECT:<description>` and the script exits non-zero. The protobuf and
+/// grpc runtimes are imported before instrumenting so their own setup is not
+/// attributed to the generated module.
+pub fn create_side_effect_test_script(full_module: &str, nonce: &str) -> String {
+    format!(
+        r#"import builtins
+import importlib
+import io
+import sys
+import threading
+
+for _dep in ('google.protobuf.descriptor_pool', 'google.protobuf.symbol_database',
+             'google.protobuf.internal.builder', 'grpc'):
+    try:
+        importlib.import_module(_dep)
+    except Exception:
+        pass
+
+_effects = []
+_real_open = builtins.open
+_real_start = threading.Thread.start
+
+def _record_open(file, *args, **kwargs):
+    _effects.append('open(' + repr(file) + ')')
+    return _real_open(file, *args, **kwargs)
+
+def _record_start(self, *args, **kwargs):
+    _effects.append('thread start (' + self.name + ')')
+    return _real_start(self, *args, **kwargs)
+
+builtins.open = io.open = _record_open
+threading.Thread.start = _record_start
+try:
+    importlib.import_module('{full_module}')
+finally:
+    builtins.open = io.open = _real_open
+    threading.Thread.start = _real_start
+
+for _effect in _effects:
+    print('{nonce}SIDE_EFFECT:' + _effect, file=sys.stderr)
+sys.exit(4 if _effects else 0)
+"#
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_create_import_test_script_empty_modules() {
-        let script = create_import_test_script("test_package", &[]);
+        let script = create_import_test_script("test_package", &[], "N:");
 
         assert!(script.contains("import sys"));
         assert!(script.contains("import importlib"));
@@ -78,7 +157,7 @@ mod tests {
     #[test]
     fn test_create_import_test_script_single_module() {
         let modules = vec!["test_module".to_string()];
-        let script = create_import_test_script("test_package", &modules);
+        let script = create_import_test_script("test_package", &modules, "N:");
 
         assert!(script.contains("test_module -> test_package.test_module"));
         assert!(script.contains("importlib.import_module('test_package.test_module')"));
@@ -95,7 +174,7 @@ mod tests {
             "module2".to_string(),
             "subpkg.module3".to_string(),
         ];
-        let script = create_import_test_script("mypackage", &modules);
+        let script = create_import_test_script("mypackage", &modules, "N:");
 
         assert!(script.contains("module1 -> mypackage.module1"));
         assert!(script.contains("module2 -> mypackage.module2"));
@@ -108,7 +187,7 @@ mod tests {
     #[test]
     fn test_create_import_test_script_empty_package_name() {
         let modules = vec!["standalone_module".to_string()];
-        let script = create_import_test_script("", &modules);
+        let script = create_import_test_script("", &modules, "N:");
 
         assert!(script.contains("standalone_module -> standalone_module"));
         assert!(script.contains("importlib.import_module('standalone_module')"));
@@ -118,7 +197,7 @@ mod tests {
     #[test]
     fn test_create_import_test_script_error_handling() {
         let modules = vec!["test_module".to_string()];
-        let script = create_import_test_script("pkg", &modules);
+        let script = create_import_test_script("pkg", &modules, "N:");
 
         assert!(script.contains("relative import"));
         assert!(script.contains("relative import context issue"));
@@ -132,13 +211,39 @@ mod tests {
     #[test]
     fn test_create_import_test_script_output_format() {
         let modules = vec!["mod1".to_string(), "mod2".to_string()];
-        let script = create_import_test_script("pkg", &modules);
+        let script = create_import_test_script("pkg", &modules, "N:");
 
-        assert!(script.contains("IMPORT_TEST_SUMMARY:succeeded="));
+        assert!(script.contains("N:IMPORT_TEST_SUMMARY:succeeded="));
         assert!(script.contains(",failed="));
         assert!(script.contains(",total="));
-        assert!(script.contains("IMPORT_ERROR:"));
+        assert!(script.contains("N:IMPORT_ERROR:"));
         assert!(script.contains("sys.exit(1)"));
-        assert!(script.contains("IMPORT_TEST_SUCCESS:all_modules_imported_successfully"));
+        assert!(script.contains("N:IMPORT_TEST_SUCCESS:all_modules_imported_successfully"));
+    }
+
+    #[test]
+    fn test_create_side_effect_test_script() {
+        let script = create_side_effect_test_script("pkg.mod_pb2", "N:");
+
+        assert!(script.contains("importlib.import_module('pkg.mod_pb2')"));
+        assert!(script.contains("builtins.open = io.open = _record_open"));
+        assert!(script.contains("threading.Thread.start = _record_start"));
+        assert!(script.contains("N:SIDE_EFFECT:"));
+    }
+
+    #[test]
+    fn sentinels_require_the_run_nonce() {
+        let nonce = sentinel_nonce();
+        assert_ne!(nonce, sentinel_nonce());
+        let line = format!("{nonce}IMPORT_ERROR:a_pb2:boom");
+        assert_eq!(
+            strip_sentinel(&line, &nonce, "IMPORT_ERROR"),
+            Some("a_pb2:boom")
+        );
+        assert_eq!(strip_sentinel(&line, &nonce, "IMPORT_TEST_SUMMARY"), None);
+        assert_eq!(
+            strip_sentinel("IMPORT_ERROR:a_pb2:boom", &nonce, "IMPORT_ERROR"),
+            None
+        );
     }
 }
@@ -0,0 +1,274 @@
+use crate::utils::{output_with_timeout, timed_out};
+use anyhow::{Context, Result, bail};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::process::Command;
+use std::time::Duration;
+
+/// Type checker behind `verify.mypy_cmd` / `verify.pyright_cmd`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checker {
+    Mypy,
+    Pyright,
+}
+
+/// Severity of a type checker diagnostic. Only errors fail the build.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+/// One diagnostic reported by a type checker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub file: String,
+    /// 1-based line number
+    pub line: u64,
+    /// 1-based column number
+    pub column: u64,
+    pub severity: Severity,
+    pub message: String,
+    /// Error code (mypy) or rule name (pyright), when reported
+    pub code: Option<String>,
+}
+
+impl Checker {
+    fn name(self) -> &'static str {
+        match self {
+            Checker::Mypy => "mypy",
+            Checker::Pyright => "pyright",
+        }
+    }
+
+    /// Flags appended to the configured command to get machine-readable output.
+    fn json_args(self) -> &'static [&'static str] {
+        match self {
+            Checker::Mypy => &["--output", "json"],
+            Checker::Pyright => &["--outputjson"],
+        }
+    }
+
+    /// Parse the checker's JSON output, or None if it is not in the expected format.
+    pub fn parse(self, stdout: &str) -> Option<Vec<Diagnostic>> {
+        match self {
+            Checker::Mypy => parse_mypy(stdout),
+            Checker::Pyright => parse_pyright(stdout),
+        }
+    }
+}
+
+/// mypy `--output json` prints one JSON object per diagnostic line.
+fn parse_mypy(stdout: &str) -> Option<Vec<Diagnostic>> {
+    stdout
+        .lines()
+        .filter(|l| l.trim_start().starts_with('{'))
+        .map(|l| {
+            let v: Value = serde_json::from_str(l).ok()?;
+            let severity = match v["severity"].as_str()? {
+                "error" => Severity::Error,
+                "warning" => Severity::Warning,
+                _ => Severity::Note,
+            };
+            let mut message = v["message"].as_str()?.to_string();
+            if let Some(hint) = v["hint"].as_str().filter(|h| !h.is_empty()) {
+                message = format!("{message} ({hint})");
+            }
+            Some(Diagnostic {
+                file: v["file"].as_str()?.to_string(),
+                line: v["line"].as_u64().unwrap_or(0),
+                // mypy columns are 0-based
+                column: v["column"].as_u64().map_or(0, |c| c + 1),
+                severity,
+                message,
+                code: v["code"].as_str().map(str::to_string),
+            })
+        })
+        .collect()
+}
+
+/// pyright `--outputjson` prints a single report object.
+fn parse_pyright(stdout: &str) -> Option<Vec<Diagnostic>> {
+    let report: Value = serde_json::from_str(stdout.trim()).ok()?;
+    report["generalDiagnostics"]
+        .as_array()?
+        .iter()
+        .map(|d| {
+            let severity = match d["severity"].as_str()? {
+                "error" => Severity::Error,
+                "warning" => Severity::Warning,
+                _ => Severity::Note,
+            };
+            // pyright ranges are 0-based
+            let start = &d["range"]["start"];
+            Some(Diagnostic {
+                file: d["file"].as_str()?.to_string(),
+                line: start["line"].as_u64().map_or(0, |l| l + 1),
+                column: start["character"].as_u64().map_or(0, |c| c + 1),
+                severity,
+                message: d["message"].as_str()?.to_string(),
+                code: d["rule"].as_str().map(str::to_string),
+            })
+        })
+        .collect()
+}
+
+/// Run a configured type checker command and report its diagnostics per file.
+///
+/// The checker's JSON output flag is appended to `cmd`. Errors fail the step
+/// with a count of affected files; warnings and notes are only logged. If the
+/// command fails without reporting errors, e.g. a mypy too old for
+/// `--output json`, the step fails with its raw output.
+pub fn run_typecheck(
+    checker: Checker,
+    cmd: &[String],
+    timeout: Option<Duration>,
+) -> Result<Vec<Diagnostic>> {
+    let name = checker.name();
+    let (prog, args) = cmd
+        .split_first()
+        .ok_or_else(|| anyhow::anyhow!("{name} command is empty"))?;
+    let mut command = Command::new(prog);
+    command.args(args).args(checker.json_args());
+    let Some(output) = output_with_timeout(&mut command, timeout)
+        .with_context(|| format!("failed to run {}", prog))?
+    else {
+        bail!("{} {}", name, timed_out(timeout));
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    // Unparseable output still gets judged by the exit status below
+    let diagnostics = checker.parse(&stdout).unwrap_or_default();
+    for d in &diagnostics {
+        let code = d
+            .code
+            .as_deref()
+            .map(|c| format!(" [{c}]"))
+            .unwrap_or_default();
+        let location = format!("{}:{}:{}", d.file, d.line, d.column);
+        match d.severity {
+            Severity::Error => tracing::error!("{} {}: {}{}", name, location, d.message, code),
+            Severity::Warning => tracing::warn!("{} {}: {}{}", name, location, d.message, code),
+            Severity::Note => tracing::info!("{} {}: {}{}", name, location, d.message, code),
+        }
+    }
+
+    let errors: Vec<&Diagnostic> = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .collect();
+    if !errors.is_empty() {
+        let files: BTreeSet<&str> = errors.iter().map(|d| d.file.as_str()).collect();
+        bail!(
+            "{} reported {} errors in {} files: {}",
+            name,
+            errors.len(),
+            files.len(),
+            files.into_iter().collect::<Vec<_>>().join(", ")
+        );
+    }
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!(
+            "{} failed (status {:?}) without reporting errors:\n{}\n{}",
+            name,
+            output.status.code(),
+            stdout.trim(),
+            stderr.trim()
+        );
+    }
+    tracing::info!("{} passed ({} warnings/notes)", name, diagnostics.len());
+    Ok(diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_mypy_json_lines() {
+        let stdout = concat!(
+            r#"{"file": "generated/a_pb2.pyi", "line": 3, "column": 4, "message": "Name \"x\" is not defined", "hint": null, "code": "name-defined", "severity": "error"}"#,
+            "\n",
+            r#"{"file": "generated/a_pb2.pyi", "line": 3, "column": 0, "message": "See docs", "hint": null, "code": null, "severity": "note"}"#,
+            "\n",
+        );
+        let diags = Checker::Mypy.parse(stdout).unwrap();
+        assert_eq!(diags.len(), 2);
+        assert_eq!(
+            diags[0],
+            Diagnostic {
+                file: "generated/a_pb2.pyi".to_string(),
+                line: 3,
+                column: 5,
+                severity: Severity::Error,
+                message: "Name \"x\" is not defined".to_string(),
+                code: Some("name-defined".to_string()),
+            }
+        );
+        assert_eq!(diags[1].severity, Severity::Note);
+        // A clean run prints nothing
+        assert_eq!(Checker::Mypy.parse(""), Some(vec![]));
+        assert_eq!(Checker::Mypy.parse("{not json"), None);
+    }
+
+    #[test]
+    fn parse_pyright_report() {
+        let stdout = r#"{
+            "version": "1.1.380",
+            "generalDiagnostics": [
+                {"file": "/w/generated/b_pb2.pyi", "severity": "warning", "message": "Import cycle",
+                 "range": {"start": {"line": 0, "character": 0}, "end": {"line": 0, "character": 5}},
+                 "rule": "reportImportCycles"}
+            ],
+            "summary": {"errorCount": 0, "warningCount": 1}
+        }"#;
+        let diags = Checker::Pyright.parse(stdout).unwrap();
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].severity, Severity::Warning);
+        assert_eq!((diags[0].line, diags[0].column), (1, 1));
+        assert_eq!(diags[0].code.as_deref(), Some("reportImportCycles"));
+        assert_eq!(Checker::Pyright.parse("Found 0 errors"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn only_errors_fail_the_step() {
+        let fake_pyright = |severity: &str| {
+            let report = format!(
+                r#"{{"generalDiagnostics": [{{"file": "gen/x_pb2.pyi", "severity": "{severity}", "message": "m", "range": {{"start": {{"line": 1, "character": 2}}}}}}]}}"#
+            );
+            // The appended `--outputjson` becomes a positional argument of the script
+            vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                format!("printf '%s' '{report}'"),
+            ]
+        };
+
+        let diags = run_typecheck(Checker::Pyright, &fake_pyright("warning"), None).unwrap();
+        assert_eq!(diags.len(), 1);
+
+        let err = run_typecheck(Checker::Pyright, &fake_pyright("error"), None).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("pyright reported 1 errors in 1 files: gen/x_pb2.pyi"),
+            "{err}"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unparseable_output_falls_back_to_exit_status() {
+        let cmd = |script: &str| vec!["sh".to_string(), "-c".to_string(), script.to_string()];
+        assert!(run_typecheck(Checker::Mypy, &cmd("echo ok"), None).is_ok());
+        let err = run_typecheck(Checker::Mypy, &cmd("echo 'bad flag'; exit 2"), None).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("mypy failed (status Some(2)) without reporting errors"),
+            "{err}"
+        );
+        assert!(err.to_string().contains("bad flag"), "{err}");
+    }
+}